@@ -14,8 +14,10 @@ use crate::{
     claude_code_state::{ClaudeCodeState, TokenStatus},
     config::{CLEWDR_CONFIG, ModelFamily},
     error::{CheckClaudeErr, ClewdrError, WreqSnafu},
+    format::count_image_tokens,
     services::cookie_actor::CookieActorHandle,
     types::claude::{CountMessageTokensResponse, CreateMessageParams},
+    utils::redact_for_log,
 };
 
 pub(super) const CLAUDE_BETA_BASE: &str = "oauth-2025-04-20";
@@ -213,7 +215,9 @@ impl ClaudeCodeState {
         }
 
         // Save full request to log file for detailed debugging
-        if let Ok(json_str) = serde_json::to_string_pretty(&body) {
+        if !CLEWDR_CONFIG.load().no_fs
+            && let Ok(json_str) = serde_json::to_string_pretty(&redact_for_log(&body, None))
+        {
             let log_path = "log/claude_code_outgoing_request.json";
             if let Err(e) = std::fs::write(log_path, &json_str) {
                 warn!("[CLAUDE_CODE] Failed to write request log: {}", e);
@@ -806,7 +810,7 @@ impl ClaudeCodeState {
 
     fn local_count_tokens_response(body: &CreateMessageParams) -> axum::response::Response {
         let estimate = CountMessageTokensResponse {
-            input_tokens: body.count_tokens(),
+            input_tokens: body.count_tokens() + count_image_tokens(&body.messages),
         };
         Json(estimate).into_response()
     }
@@ -42,6 +42,8 @@ pub struct ClaudeWebState {
     pub usage: Usage,
     // keep the last request params for potential post-call token accounting
     pub last_params: Option<CreateMessageParams>,
+    /// Per-request override of the `use_real_roles` setting
+    pub use_real_roles: Option<bool>,
 }
 
 impl ClaudeWebState {
@@ -62,6 +64,7 @@ impl ClaudeWebState {
             key: None,
             usage: Usage::default(),
             last_params: None,
+            use_real_roles: None,
         }
     }
 
@@ -1,7 +1,7 @@
-use std::{fmt::Write, mem};
+use std::{collections::HashMap, fmt::Write, mem};
 
 use base64::{Engine, prelude::BASE64_STANDARD};
-use futures::{StreamExt, stream};
+use futures::{StreamExt, future, stream};
 use itertools::Itertools;
 use serde_json::Value;
 use tracing::warn;
@@ -9,7 +9,11 @@ use wreq::multipart::{Form, Part};
 
 use crate::{
     claude_web_state::ClaudeWebState,
-    config::CLEWDR_CONFIG,
+    config::{CLEWDR_CONFIG, ClewdrConfig},
+    format::{
+        base64_within_limit, collect_image_sources, collect_tool_result_images,
+        is_supported_document_type, is_supported_image_type, keep_last_images,
+    },
     types::{
         claude::{ContentBlock, CreateMessageParams, ImageSource, Message, MessageContent, Role},
         claude_web::request::*,
@@ -22,95 +26,205 @@ impl ClaudeWebState {
         let system = value.system.take();
         let msgs = mem::take(&mut value.messages);
         let system = merge_system(system.unwrap_or_default());
-        let merged = merge_messages(msgs, system)?;
+        let config = CLEWDR_CONFIG.load();
+        let options = MergeOptions::from_config(&config).real_roles(self.use_real_roles);
+        let merged = merge_messages(msgs, system, &options)?;
+        print_out_text(merged.paste.to_owned(), "paste.txt");
 
-        let mut tools = vec![];
-        if CLEWDR_CONFIG.load().web_search {
-            tools.push(Tool::web_search());
-        }
-        Some(WebRequestBody {
-            max_tokens_to_sample: value.max_tokens,
-            attachments: vec![Attachment::new(merged.paste)],
-            files: vec![],
-            model: if self.is_pro() {
-                Some(value.model)
-            } else {
-                None
-            },
-            rendering_mode: if value.stream.unwrap_or_default() {
-                "messages".to_string()
-            } else {
-                "raw".to_string()
-            },
-            prompt: merged.prompt,
-            timezone: TIME_ZONE.to_string(),
-            images: merged.images,
-            tools,
-        })
+        Some(build_web_request(value, merged, web_search_tool(&config), self.is_pro()))
     }
 
     /// Upload images to the Claude.ai
+    ///
+    /// Up to `image_upload_concurrency` images are uploaded at the same time.
+    /// The file UUIDs keep the order of the images, failed uploads are skipped.
     pub async fn upload_images(&self, imgs: Vec<ImageSource>) -> Vec<String> {
-        // upload images
-        stream::iter(imgs)
-            .filter_map(async |img| {
-                // check if the image is base64
-                if img.type_ != "base64" {
-                    warn!("Image type is not base64");
-                    return None;
-                }
-                // decode the image
-                let bytes = BASE64_STANDARD
-                    .decode(img.data)
-                    .inspect_err(|e| {
-                        warn!("Failed to decode image: {}", e);
-                    })
-                    .ok()?;
-                // choose the file name based on the media type
-                let file_name = match img.media_type.to_lowercase().as_str() {
-                    "image/png" => "image.png",
-                    "image/jpeg" => "image.jpg",
-                    "image/jpg" => "image.jpg",
-                    "image/gif" => "image.gif",
-                    "image/webp" => "image.webp",
-                    "application/pdf" => "document.pdf",
-                    _ => "file",
-                };
-                // create the part and form
-                let part = Part::bytes(bytes).file_name(file_name);
-                let form = Form::new().part("file", part);
-                let endpoint = self
-                    .endpoint
-                    .join(&format!("api/{}/upload", self.org_uuid.as_ref()?))
-                    .expect("Url parse error");
-                // send the request into future
-                let res = self
-                    .build_request(http::Method::POST, endpoint)
-                    .multipart(form)
-                    .send()
-                    .await
-                    .inspect_err(|e| {
-                        warn!("Failed to upload image: {}", e);
-                    })
-                    .ok()?;
-                #[derive(serde::Deserialize)]
-                struct UploadResponse {
-                    file_uuid: String,
-                }
-                // get the response json
-                let json = res
-                    .json::<UploadResponse>()
-                    .await
-                    .inspect_err(|e| {
-                        warn!("Failed to parse image response: {}", e);
-                    })
-                    .ok()?;
-                // extract the file_uuid
-                Some(json.file_uuid)
+        let max_bytes = CLEWDR_CONFIG.load().max_image_bytes;
+        let concurrency = CLEWDR_CONFIG.load().image_upload_concurrency;
+        let file_names = CLEWDR_CONFIG.load().upload_file_names.to_owned();
+        upload_concurrently(imgs, concurrency, |img| {
+            self.upload_image(img, max_bytes, &file_names)
+        })
+        .await
+    }
+
+    /// Upload a single image to the Claude.ai
+    ///
+    /// # Returns
+    /// * `Option<String>` - The file UUID, or None if the upload failed
+    async fn upload_image(
+        &self,
+        img: ImageSource,
+        max_bytes: usize,
+        file_names: &HashMap<String, String>,
+    ) -> Option<String> {
+        // check if the image is base64
+        if img.type_ != "base64" {
+            warn!("Image type is not base64");
+            return None;
+        }
+        // reject oversized images before decoding them
+        if !base64_within_limit(&img.data, max_bytes) {
+            warn!("Skipping image larger than {} bytes", max_bytes);
+            return None;
+        }
+        // choose the file name based on the media type
+        let Some(file_name) = upload_file_name(&img.media_type, file_names) else {
+            warn!("Skipping upload of unsupported media type: {}", img.media_type);
+            return None;
+        };
+        // decode the image
+        let bytes = BASE64_STANDARD
+            .decode(img.data)
+            .inspect_err(|e| {
+                warn!("Failed to decode image: {}", e);
             })
-            .collect::<Vec<_>>()
+            .ok()?;
+        // create the part and form
+        let part = Part::bytes(bytes).file_name(file_name);
+        let form = Form::new().part("file", part);
+        let endpoint = self
+            .endpoint
+            .join(&format!("api/{}/upload", self.org_uuid.as_ref()?))
+            .expect("Url parse error");
+        // send the request into future
+        let res = self
+            .build_request(http::Method::POST, endpoint)
+            .multipart(form)
+            .send()
+            .await
+            .inspect_err(|e| {
+                warn!("Failed to upload image: {}", e);
+            })
+            .ok()?;
+        #[derive(serde::Deserialize)]
+        struct UploadResponse {
+            file_uuid: String,
+        }
+        // get the response json
+        let json = res
+            .json::<UploadResponse>()
             .await
+            .inspect_err(|e| {
+                warn!("Failed to parse image response: {}", e);
+            })
+            .ok()?;
+        // extract the file_uuid
+        Some(json.file_uuid)
+    }
+}
+
+/// Runs `upload` on every image with at most `concurrency` uploads in flight
+///
+/// # Arguments
+/// * `imgs` - The images to upload
+/// * `concurrency` - Maximum number of concurrent uploads, at least 1
+/// * `upload` - Uploads one image, returning its file UUID on success
+///
+/// # Returns
+/// * `Vec<String>` - The file UUIDs in the order of `imgs`, without failed uploads
+async fn upload_concurrently<F, Fut>(
+    imgs: Vec<ImageSource>,
+    concurrency: usize,
+    mut upload: F,
+) -> Vec<String>
+where
+    F: FnMut(ImageSource) -> Fut,
+    Fut: Future<Output = Option<String>>,
+{
+    let mut uploaded = stream::iter(imgs.into_iter().enumerate())
+        .map(|(i, img)| {
+            let pending = upload(img);
+            async move { pending.await.map(|file_uuid| (i, file_uuid)) }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .filter_map(future::ready)
+        .collect::<Vec<_>>()
+        .await;
+    // uploads finish in any order, restore the order of the images
+    uploaded.sort_unstable_by_key(|(i, _)| *i);
+    uploaded.into_iter().map(|(_, file_uuid)| file_uuid).collect()
+}
+
+/// Builds the web search tool with the configured domain filters
+///
+/// # Returns
+/// * `None` if web search is disabled
+fn web_search_tool(config: &ClewdrConfig) -> Option<Tool> {
+    config.web_search.then(|| {
+        Tool::web_search()
+            .allowed_domains(config.web_search_allowed_domains.to_owned())
+            .blocked_domains(config.web_search_blocked_domains.to_owned())
+    })
+}
+
+/// Builds the Claude.ai request body from merged messages
+///
+/// The request's `stream` flag selects the rendering mode: `"messages"` for
+/// streaming requests and `"raw"` otherwise.
+fn build_web_request(
+    value: CreateMessageParams,
+    merged: Merged,
+    web_search: Option<Tool>,
+    is_pro: bool,
+) -> WebRequestBody {
+    let tools = web_search.into_iter().collect();
+    WebRequestBody {
+        max_tokens_to_sample: value.max_tokens,
+        attachments: vec![Attachment::new(merged.paste)],
+        files: vec![],
+        model: if is_pro { Some(value.model) } else { None },
+        rendering_mode: if value.stream.unwrap_or_default() {
+            "messages".to_string()
+        } else {
+            "raw".to_string()
+        },
+        prompt: merged.prompt,
+        timezone: TIME_ZONE.to_string(),
+        images: merged.images,
+        tools,
+    }
+}
+
+/// Picks the upload file name for a media type
+///
+/// File names configured in `upload_file_names` take precedence over the
+/// built-in ones, and allow types that are not supported otherwise.
+///
+/// # Arguments
+/// * `media_type` - The media type of the upload
+/// * `custom` - Configured media type to file name map
+///
+/// # Returns
+/// * `None` if the type is neither configured nor a supported image or document type
+fn upload_file_name(media_type: &str, custom: &HashMap<String, String>) -> Option<String> {
+    let media_type = media_type.to_lowercase();
+    let essence = media_type.split(';').next().unwrap_or_default().trim();
+    if let Some((_, file_name)) = custom
+        .iter()
+        .find(|(configured, _)| configured.trim().eq_ignore_ascii_case(essence))
+    {
+        return Some(file_name.to_owned());
+    }
+    if !is_supported_image_type(&media_type) && !is_supported_document_type(&media_type) {
+        return None;
     }
+    let file_name = match essence {
+        "image/png" => "image.png",
+        "image/jpeg" | "image/jpg" => "image.jpg",
+        "image/gif" => "image.gif",
+        "image/webp" => "image.webp",
+        "image/svg+xml" => "image.svg",
+        "image/bmp" => "image.bmp",
+        "image/tiff" => "image.tiff",
+        "application/pdf" => "document.pdf",
+        "text/plain" => "document.txt",
+        "text/html" => "document.html",
+        "text/markdown" => "document.md",
+        "application/json" => "document.json",
+        _ => return None,
+    };
+    Some(file_name.to_string())
 }
 
 /// Merged messages and images
@@ -121,38 +235,110 @@ struct Merged {
     pub images: Vec<ImageSource>,
 }
 
+/// Prompt settings used when merging messages
+#[derive(Debug, Clone)]
+struct MergeOptions {
+    human: String,
+    assistant: String,
+    use_real_roles: bool,
+    escape_role_markers: bool,
+    custom_prompt: String,
+    /// Upload data URI images found in tool_result content
+    tool_result_images: bool,
+    /// Maximum number of images uploaded, unlimited if None
+    max_images: Option<usize>,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self {
+            human: "Human".to_string(),
+            assistant: "Assistant".to_string(),
+            use_real_roles: true,
+            escape_role_markers: false,
+            custom_prompt: String::new(),
+            tool_result_images: false,
+            max_images: None,
+        }
+    }
+}
+
+impl MergeOptions {
+    fn from_config(config: &ClewdrConfig) -> Self {
+        Self {
+            human: config.custom_h.to_owned().unwrap_or("Human".to_string()),
+            assistant: config.custom_a.to_owned().unwrap_or("Assistant".to_string()),
+            use_real_roles: config.use_real_roles,
+            escape_role_markers: config.escape_role_markers,
+            custom_prompt: config.custom_prompt.to_owned(),
+            tool_result_images: config.tool_result_images,
+            max_images: config.max_images_per_request,
+        }
+    }
+
+    /// Applies a per-request `use_real_roles` override
+    fn real_roles(mut self, use_real_roles: Option<bool>) -> Self {
+        if let Some(use_real_roles) = use_real_roles {
+            self.use_real_roles = use_real_roles;
+        }
+        self
+    }
+}
+
+/// Breaks up role markers embedded in message text
+///
+/// A line starting with `"{role}:"` is rewritten with a zero-width space before
+/// the colon, so it can't be mistaken for a real turn prefix.
+fn escape_role_markers(text: &str, roles: &[&str]) -> String {
+    text.split('\n')
+        .map(|line| {
+            let trimmed = line.trim_start();
+            let indent = &line[..line.len() - trimmed.len()];
+            roles
+                .iter()
+                .find_map(|role| {
+                    let rest = trimmed.strip_prefix(role)?.strip_prefix(':')?;
+                    Some(format!("{indent}{role}\u{200B}:{rest}"))
+                })
+                .unwrap_or_else(|| line.to_string())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 /// Merges multiple messages into a single text prompt, handling system instructions
 /// and extracting any images from the messages
 ///
 /// # Arguments
 /// * `msgs` - Vector of messages to merge
 /// * `system` - System instructions to prepend
+/// * `options` - Role names and prompt settings
 ///
 /// # Returns
 /// * `Option<Merged>` - Merged prompt text, images, and additional metadata, or None if merging fails
-fn merge_messages(msgs: Vec<Message>, system: String) -> Option<Merged> {
+fn merge_messages(msgs: Vec<Message>, system: String, options: &MergeOptions) -> Option<Merged> {
     if msgs.is_empty() {
         return None;
     }
-    let h = CLEWDR_CONFIG
-        .load()
-        .custom_h
-        .to_owned()
-        .unwrap_or("Human".to_string());
-    let a = CLEWDR_CONFIG
-        .load()
-        .custom_a
-        .to_owned()
-        .unwrap_or("Assistant".to_string());
-
-    let user_real_roles = CLEWDR_CONFIG.load().use_real_roles;
-    let line_breaks = if user_real_roles { "\n\n\x08" } else { "\n\n" };
+    let h = options.human.as_str();
+    let a = options.assistant.as_str();
+
+    let line_breaks = if options.use_real_roles { "\n\n\x08" } else { "\n\n" };
     let system = system.trim().to_string();
     let size = size_of_val(&msgs);
     // preallocate string to avoid reallocations
     let mut w = String::with_capacity(size);
 
-    let mut imgs: Vec<ImageSource> = vec![];
+    let mut imgs = collect_image_sources(&msgs);
+    if options.tool_result_images {
+        imgs.extend(collect_tool_result_images(&msgs));
+    }
+    if let Some(max_images) = options.max_images {
+        let dropped = keep_last_images(&mut imgs, max_images);
+        if dropped > 0 {
+            warn!("Dropping {} oldest images over the limit of {}", dropped, max_images);
+        }
+    }
 
     let chunks = msgs
         .into_iter()
@@ -163,30 +349,14 @@ fn merge_messages(msgs: Vec<Message>, system: String) -> Option<Merged> {
                     .into_iter()
                     .filter_map(|b| match b {
                         ContentBlock::Text { text, .. } => Some(text.trim().to_string()),
-                        ContentBlock::Image { source, .. } => {
-                            // push image to the list
-                            imgs.push(source);
-                            None
-                        }
-                        ContentBlock::ImageUrl { image_url } => {
-                            // oai image - supports both data URI and HTTP URLs
-                            if let Some(source) = extract_image_from_url(&image_url.url) {
-                                imgs.push(source);
-                            }
-                            None
-                        }
-                        ContentBlock::Document { source, .. } => {
-                            // Document content (PDF, etc.)
-                            // Convert to ImageSource format for upload
-                            if source.type_ == "base64" {
-                                if let Some(data) = source.data {
-                                    imgs.push(ImageSource {
-                                        type_: "base64".to_string(),
-                                        media_type: source.media_type.unwrap_or_else(|| "application/pdf".to_string()),
-                                        data,
-                                    });
-                                }
-                            }
+                        // images were collected above, remote URLs can't be uploaded
+                        ContentBlock::ImageUrl { image_url }
+                            if !image_url.url.starts_with("data:") =>
+                        {
+                            warn!(
+                                "Remote image URLs are not supported, skipping: {}",
+                                image_url.url
+                            );
                             None
                         }
                         ContentBlock::Thinking { thinking, .. } => {
@@ -223,7 +393,11 @@ fn merge_messages(msgs: Vec<Message>, system: String) -> Option<Merged> {
     // join same role with new line
     let mut msgs = chunks.into_iter().map(|(role, grp)| {
         let txt = grp.into_iter().map(|m| m.1).collect::<Vec<_>>().join("\n");
-        (role, txt)
+        if options.escape_role_markers {
+            (role, escape_role_markers(&txt, &[h, a]))
+        } else {
+            (role, txt)
+        }
     });
     // first message does not need prefix
     if !system.is_empty() {
@@ -243,14 +417,11 @@ fn merge_messages(msgs: Vec<Message>, system: String) -> Option<Merged> {
         };
         write!(w, "{line_breaks}{prefix}{text}").ok()?;
     }
-    print_out_text(w.to_owned(), "paste.txt");
-
-    // prompt polyfill
-    let p = CLEWDR_CONFIG.load().custom_prompt.to_owned();
 
     Some(Merged {
         paste: w,
-        prompt: p,
+        // prompt polyfill
+        prompt: options.custom_prompt.to_owned(),
         images: imgs,
     })
 }
@@ -276,124 +447,190 @@ fn merge_system(sys: Value) -> String {
     }
 }
 
-/// Extract image from URL (data URI or HTTP URL)
-///
-/// Supports:
-/// - Data URIs: `data:image/png;base64,iVBORw0KGgo...`
-/// - HTTP/HTTPS URLs: Downloads and converts to base64
-///
-/// # Arguments
-/// * `url` - The image URL or data URI
-///
-/// # Returns
-/// * `Option<ImageSource>` - Extracted image source, or None if extraction fails
-fn extract_image_from_url(url: &str) -> Option<ImageSource> {
-    // Handle data URI
-    if url.starts_with("data:") {
-        return extract_image_from_data_uri(url);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::oai::OaiCreateMessageParams;
+
+    fn user_and_assistant(user: &str, assistant: &str) -> Vec<Message> {
+        vec![
+            Message::new_text(Role::User, "hi"),
+            Message::new_text(Role::Assistant, assistant),
+            Message::new_text(Role::User, user),
+        ]
     }
 
-    // Handle HTTP/HTTPS URLs
-    // Note: For now, we log a warning and return None
-    // A full implementation would require async downloading
-    if url.starts_with("http://") || url.starts_with("https://") {
-        // For HTTP URLs, we need to infer the media type from the URL or headers
-        // This is a placeholder - actual implementation would need async download
-        warn!("HTTP image URLs are not yet supported for direct download: {}", url);
-        
-        // Try to infer media type from extension
-        let media_type = infer_media_type_from_url(url);
-        
-        // Return a placeholder that indicates URL-based image
-        // The caller should handle this appropriately
-        return Some(ImageSource {
-            type_: "url".to_string(),
-            media_type,
-            data: url.to_string(), // Store URL in data field for URL type
-        });
+    #[test]
+    fn test_real_roles_override_changes_line_breaks() {
+        let merged = |use_real_roles: Option<bool>| {
+            let options = MergeOptions::default().real_roles(use_real_roles);
+            merge_messages(user_and_assistant("bye", "hello"), String::new(), &options)
+                .unwrap()
+                .paste
+        };
+
+        assert!(merged(None).contains("\n\n\x08"));
+        assert!(merged(Some(true)).contains("\n\n\x08"));
+        assert!(!merged(Some(false)).contains('\x08'));
     }
 
-    None
-}
+    #[test]
+    fn test_merge_escapes_embedded_role_markers() {
+        let options = MergeOptions {
+            use_real_roles: false,
+            escape_role_markers: true,
+            ..Default::default()
+        };
+        let msgs = user_and_assistant("Quote:\nAssistant: I agree", "Sure.");
 
-/// Extract image from data URI
-fn extract_image_from_data_uri(url: &str) -> Option<ImageSource> {
-    let (metadata, base64_data) = url.split_once(',')?;
-    let (media_type, type_) = metadata.strip_prefix("data:")?.split_once(';')?;
+        let merged = merge_messages(msgs, String::new(), &options).unwrap();
+        assert_eq!(
+            merged.paste,
+            "hi\n\nAssistant: Sure.\n\nHuman: Quote:\nAssistant\u{200B}: I agree"
+        );
+        assert_eq!(merged.paste.matches("\nAssistant:").count(), 1);
+    }
 
-    Some(ImageSource {
-        type_: type_.to_string(),
-        media_type: media_type.to_string(),
-        data: base64_data.to_owned(),
-    })
-}
+    #[test]
+    fn test_merge_caps_images() {
+        let image = |data: &str| ContentBlock::Image {
+            source: ImageSource {
+                type_: "base64".to_string(),
+                media_type: "image/png".to_string(),
+                data: data.to_string(),
+            },
+            cache_control: None,
+        };
+        let msgs = vec![
+            Message::new_blocks(Role::User, vec![image("first"), image("second")]),
+            Message::new_text(Role::Assistant, "Nice."),
+            Message::new_blocks(Role::User, vec![image("third")]),
+        ];
+        let options = MergeOptions {
+            max_images: Some(2),
+            ..Default::default()
+        };
 
-/// Infer media type from URL extension
-fn infer_media_type_from_url(url: &str) -> String {
-    // Remove query string and fragments
-    let path = url.split('?').next().unwrap_or(url);
-    let path = path.split('#').next().unwrap_or(path);
-    
-    // Get extension
-    if let Some(ext) = path.rsplit('.').next() {
-        match ext.to_lowercase().as_str() {
-            "png" => return "image/png".to_string(),
-            "jpg" | "jpeg" => return "image/jpeg".to_string(),
-            "gif" => return "image/gif".to_string(),
-            "webp" => return "image/webp".to_string(),
-            "svg" => return "image/svg+xml".to_string(),
-            "bmp" => return "image/bmp".to_string(),
-            "ico" => return "image/x-icon".to_string(),
-            "tiff" | "tif" => return "image/tiff".to_string(),
-            "pdf" => return "application/pdf".to_string(),
-            _ => {}
-        }
+        let merged = merge_messages(msgs, String::new(), &options).unwrap();
+        let data: Vec<&str> = merged.images.iter().map(|i| i.data.as_str()).collect();
+        assert_eq!(data, vec!["second", "third"]);
     }
-    
-    // Default to octet-stream if unknown
-    "application/octet-stream".to_string()
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_merge_keeps_role_markers_when_disabled() {
+        let options = MergeOptions {
+            use_real_roles: false,
+            ..Default::default()
+        };
+        let msgs = user_and_assistant("Assistant: I agree", "Sure.");
+
+        let merged = merge_messages(msgs, String::new(), &options).unwrap();
+        assert!(merged.paste.ends_with("Human: Assistant: I agree"));
+    }
 
     #[test]
-    fn test_extract_image_from_data_uri() {
-        let data_uri = "data:image/png;base64,iVBORw0KGgo";
-        let result = extract_image_from_url(data_uri);
-        
-        assert!(result.is_some());
-        let source = result.unwrap();
-        assert_eq!(source.type_, "base64");
-        assert_eq!(source.media_type, "image/png");
-        assert_eq!(source.data, "iVBORw0KGgo");
+    fn test_upload_file_name_skips_unsupported_types() {
+        let file_name = |media_type: &str| upload_file_name(media_type, &HashMap::new());
+        assert_eq!(file_name("image/PNG").as_deref(), Some("image.png"));
+        assert_eq!(file_name("text/plain; charset=utf-8").as_deref(), Some("document.txt"));
+        assert_eq!(file_name("application/pdf").as_deref(), Some("document.pdf"));
+        assert_eq!(file_name("video/mp4"), None);
+        assert_eq!(file_name("application/octet-stream"), None);
     }
 
     #[test]
-    fn test_extract_image_from_http_url() {
-        let url = "https://example.com/image.png";
-        let result = extract_image_from_url(url);
-        
-        assert!(result.is_some());
-        let source = result.unwrap();
-        assert_eq!(source.type_, "url");
-        assert_eq!(source.media_type, "image/png");
-        assert_eq!(source.data, url);
+    fn test_upload_file_name_uses_configured_types() {
+        let mut config = ClewdrConfig::default();
+        config.upload_file_names.insert("text/csv".to_string(), "table.csv".to_string());
+        config.upload_file_names.insert("image/png".to_string(), "screenshot.png".to_string());
+        let file_name = |media_type: &str| upload_file_name(media_type, &config.upload_file_names);
+
+        assert_eq!(file_name("text/CSV; charset=utf-8").as_deref(), Some("table.csv"));
+        // Configured names override the built-in ones
+        assert_eq!(file_name("image/png").as_deref(), Some("screenshot.png"));
+        assert_eq!(file_name("image/gif").as_deref(), Some("image.gif"));
+        assert_eq!(file_name("video/mp4"), None);
     }
 
     #[test]
-    fn test_infer_media_type() {
-        assert_eq!(infer_media_type_from_url("https://example.com/image.png"), "image/png");
-        assert_eq!(infer_media_type_from_url("https://example.com/photo.jpg"), "image/jpeg");
-        assert_eq!(infer_media_type_from_url("https://example.com/photo.jpeg?size=large"), "image/jpeg");
-        assert_eq!(infer_media_type_from_url("https://example.com/doc.pdf#page=1"), "application/pdf");
-        assert_eq!(infer_media_type_from_url("https://example.com/file"), "application/octet-stream");
+    fn test_oai_stream_selects_messages_rendering_mode() {
+        let web_body = |stream: bool| {
+            let oai: OaiCreateMessageParams = serde_json::from_value(serde_json::json!({
+                "model": "claude-sonnet-4-5",
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": stream
+            }))
+            .unwrap();
+            let mut params = CreateMessageParams::from(oai);
+            let msgs = mem::take(&mut params.messages);
+            let merged = merge_messages(msgs, String::new(), &MergeOptions::default()).unwrap();
+            serde_json::to_value(build_web_request(params, merged, None, false)).unwrap()
+        };
+
+        assert_eq!(web_body(true)["rendering_mode"], "messages");
+        assert_eq!(web_body(false)["rendering_mode"], "raw");
     }
 
     #[test]
-    fn test_invalid_url() {
-        assert!(extract_image_from_url("not-a-url").is_none());
-        assert!(extract_image_from_url("ftp://example.com/file").is_none());
+    fn test_web_search_tool_domains() {
+        let mut config = ClewdrConfig::default();
+        assert!(web_search_tool(&config).is_none());
+
+        config.web_search = true;
+        let tool = serde_json::to_value(web_search_tool(&config).unwrap()).unwrap();
+        assert!(tool.get("allowed_domains").is_none());
+        assert!(tool.get("blocked_domains").is_none());
+
+        config.web_search_blocked_domains = vec!["example.com".to_string()];
+        let tool = serde_json::to_value(web_search_tool(&config).unwrap()).unwrap();
+        assert_eq!(tool["type"], "web_search_v0");
+        assert!(tool.get("allowed_domains").is_none());
+        assert_eq!(tool["blocked_domains"], serde_json::json!(["example.com"]));
+
+        // Only one list can be sent, validation keeps the allowlist
+        config.web_search_allowed_domains = vec!["docs.rs".to_string()];
+        let config = config.validate();
+        let tool = serde_json::to_value(web_search_tool(&config).unwrap()).unwrap();
+        assert_eq!(tool["allowed_domains"], serde_json::json!(["docs.rs"]));
+        assert!(tool.get("blocked_domains").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_upload_concurrently_keeps_image_order() {
+        use std::sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        };
+
+        let imgs: Vec<ImageSource> = (0..6)
+            .map(|i| ImageSource {
+                type_: "base64".to_string(),
+                media_type: "image/png".to_string(),
+                data: i.to_string(),
+            })
+            .collect();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+        let uploaded = upload_concurrently(imgs, 2, |img| {
+            let in_flight = in_flight.clone();
+            let max_in_flight = max_in_flight.clone();
+            async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(now, Ordering::SeqCst);
+                // Later images take fewer polls, so they finish first
+                let i: usize = img.data.parse().unwrap();
+                for _ in 0..(6 - i) {
+                    tokio::task::yield_now().await;
+                }
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Some(format!("file_{}", img.data))
+            }
+        })
+        .await;
+
+        let expected: Vec<String> = (0..6).map(|i| format!("file_{i}")).collect();
+        assert_eq!(uploaded, expected);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
     }
 }
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fmt::{Debug, Display},
     net::{IpAddr, SocketAddr},
 };
@@ -22,8 +22,14 @@ use super::{CONFIG_PATH, ENDPOINT_URL};
 use crate::{
     Args,
     config::{
-        CC_CLIENT_ID, CookieStatus, UselessCookie, default_check_update, default_ip,
-        default_max_retries, default_port, default_skip_cool_down, default_use_real_roles,
+        CC_CLIENT_ID, CookieStatus, UselessCookie, default_annotation_snippet_chars,
+        default_check_update, default_coalesce_consecutive_roles, default_effort_high_budget,
+        default_effort_low_budget, default_effort_medium_budget, default_effort_minimal_budget,
+        default_empty_content_as_null, default_exclusive_sampling_models, default_image_media_type,
+        default_image_upload_concurrency, default_inject_claude_code_prelude, default_ip,
+        default_leading_user_placeholder, default_markdown_snippet_chars, default_max_image_bytes,
+        default_max_retries, default_port, default_reasoning_tag, default_skip_cool_down,
+        default_strip_control_chars, default_use_real_roles,
     },
     error::ClewdrError,
     utils::enabled,
@@ -97,7 +103,87 @@ pub struct ClewdrConfig {
     #[serde(default)]
     pub web_search: bool,
     #[serde(default)]
+    pub web_search_allowed_domains: Vec<String>,
+    #[serde(default)]
+    pub web_search_blocked_domains: Vec<String>,
+    #[serde(default)]
     pub enable_web_count_tokens: bool,
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
+    #[serde(default)]
+    pub max_tools: Option<usize>,
+    #[serde(default)]
+    pub symmetric_param_names: bool,
+    #[serde(default)]
+    pub strict_passthrough: bool,
+    #[serde(default)]
+    pub max_thinking_budget: Option<u64>,
+    #[serde(default = "default_empty_content_as_null")]
+    pub empty_content_as_null: bool,
+    #[serde(default)]
+    pub dedup_stream_deltas: bool,
+    #[serde(default)]
+    pub normalize_tool_ids: bool,
+    #[serde(default)]
+    pub server_tool_calls: bool,
+    #[serde(default)]
+    pub model_aliases: HashMap<String, String>,
+    #[serde(default = "default_image_media_type")]
+    pub default_image_media_type: String,
+    #[serde(default = "default_effort_minimal_budget")]
+    pub effort_minimal_budget: u64,
+    #[serde(default = "default_effort_low_budget")]
+    pub effort_low_budget: u64,
+    #[serde(default = "default_effort_medium_budget")]
+    pub effort_medium_budget: u64,
+    #[serde(default = "default_effort_high_budget")]
+    pub effort_high_budget: u64,
+    #[serde(default)]
+    pub cache_breakpoints: usize,
+    #[serde(default = "default_coalesce_consecutive_roles")]
+    pub coalesce_consecutive_roles: bool,
+    #[serde(default = "default_leading_user_placeholder")]
+    pub leading_user_placeholder: String,
+    #[serde(default = "default_inject_claude_code_prelude")]
+    pub inject_claude_code_prelude: bool,
+    #[serde(default)]
+    pub decode_json_tool_results: bool,
+    #[serde(default = "default_max_image_bytes")]
+    pub max_image_bytes: usize,
+    #[serde(default)]
+    pub keep_tool_results: Option<usize>,
+    #[serde(default = "default_exclusive_sampling_models")]
+    pub exclusive_sampling_models: Vec<String>,
+    #[serde(default = "default_markdown_snippet_chars")]
+    pub markdown_snippet_chars: usize,
+    #[serde(default = "default_annotation_snippet_chars")]
+    pub annotation_snippet_chars: usize,
+    #[serde(default)]
+    pub reasoning_as_content: bool,
+    #[serde(default = "default_reasoning_tag")]
+    pub reasoning_tag: String,
+    #[serde(default)]
+    pub system_prompt_prefix: Option<String>,
+    #[serde(default = "default_image_upload_concurrency")]
+    pub image_upload_concurrency: usize,
+    #[serde(default)]
+    pub upload_file_names: HashMap<String, String>,
+    #[serde(default = "default_strip_control_chars")]
+    pub strip_control_chars: bool,
+    #[serde(default)]
+    pub structured_reasoning: bool,
+    #[serde(default)]
+    pub allowed_models: Option<Vec<String>>,
+    #[serde(default)]
+    pub drop_tools_for_none_choice: bool,
+    #[serde(default)]
+    pub tool_result_images: bool,
+    #[serde(default)]
+    pub max_images_per_request: Option<usize>,
+    #[serde(default)]
+    pub reject_excess_images: bool,
+    #[serde(default)]
+    pub demote_web_thinking: bool,
 
     // Cookie settings, can hot reload
     #[serde(default)]
@@ -122,6 +208,8 @@ pub struct ClewdrConfig {
     pub custom_a: Option<String>,
     #[serde(default)]
     pub custom_prompt: String,
+    #[serde(default)]
+    pub escape_role_markers: bool,
 
     // Claude Code settings, can hot reload
     #[serde(default)]
@@ -154,10 +242,51 @@ impl Default for ClewdrConfig {
             custom_prompt: String::new(),
             custom_h: None,
             custom_a: None,
+            escape_role_markers: false,
             wreq_proxy: None,
             preserve_chats: false,
             web_search: false,
+            web_search_allowed_domains: Vec::new(),
+            web_search_blocked_domains: Vec::new(),
             enable_web_count_tokens: false,
+            stop_sequences: Vec::new(),
+            max_tools: None,
+            symmetric_param_names: false,
+            strict_passthrough: false,
+            max_thinking_budget: None,
+            empty_content_as_null: default_empty_content_as_null(),
+            dedup_stream_deltas: false,
+            normalize_tool_ids: false,
+            server_tool_calls: false,
+            model_aliases: HashMap::new(),
+            default_image_media_type: default_image_media_type(),
+            effort_minimal_budget: default_effort_minimal_budget(),
+            effort_low_budget: default_effort_low_budget(),
+            effort_medium_budget: default_effort_medium_budget(),
+            effort_high_budget: default_effort_high_budget(),
+            cache_breakpoints: 0,
+            coalesce_consecutive_roles: default_coalesce_consecutive_roles(),
+            leading_user_placeholder: default_leading_user_placeholder(),
+            inject_claude_code_prelude: default_inject_claude_code_prelude(),
+            decode_json_tool_results: false,
+            max_image_bytes: default_max_image_bytes(),
+            keep_tool_results: None,
+            exclusive_sampling_models: default_exclusive_sampling_models(),
+            markdown_snippet_chars: default_markdown_snippet_chars(),
+            annotation_snippet_chars: default_annotation_snippet_chars(),
+            reasoning_as_content: false,
+            reasoning_tag: default_reasoning_tag(),
+            system_prompt_prefix: None,
+            image_upload_concurrency: default_image_upload_concurrency(),
+            upload_file_names: HashMap::new(),
+            strip_control_chars: default_strip_control_chars(),
+            structured_reasoning: false,
+            allowed_models: None,
+            drop_tools_for_none_choice: false,
+            tool_result_images: false,
+            max_images_per_request: None,
+            reject_excess_images: false,
+            demote_web_thinking: false,
             skip_first_warning: false,
             skip_second_warning: false,
             skip_restricted: false,
@@ -329,6 +458,15 @@ impl ClewdrConfig {
             self.admin_password = generate_password();
         }
         self.cookie_array = self.cookie_array.into_iter().map(|x| x.reset()).collect();
+        // Web search rejects a tool that sets both domain lists
+        if !self.web_search_allowed_domains.is_empty()
+            && !self.web_search_blocked_domains.is_empty()
+        {
+            error!(
+                "Web search allowed and blocked domains are exclusive, ignoring blocked domains"
+            );
+            self.web_search_blocked_domains.clear();
+        }
         self.wreq_proxy = self.proxy.to_owned().and_then(|p| {
             Proxy::all(p)
                 .inspect_err(|e| {
@@ -136,5 +136,133 @@ pub const fn default_skip_cool_down() -> bool {
     true
 }
 
+/// Default text of the user turn prepended to conversations led by the assistant
+///
+/// # Returns
+/// * `String` - The default placeholder "..."
+pub fn default_leading_user_placeholder() -> String {
+    "...".to_string()
+}
+
+/// Default maximum decoded size of an image uploaded to claude.ai
+///
+/// # Returns
+/// * `usize` - The default limit of 20 MiB
+pub const fn default_max_image_bytes() -> usize {
+    20 * 1024 * 1024
+}
+
+/// Default model name patterns that reject `temperature` and `top_p` together
+///
+/// # Returns
+/// * `Vec<String>` - Every Claude model, as Claude 4+ rejects both
+pub fn default_exclusive_sampling_models() -> Vec<String> {
+    vec!["claude-".to_string()]
+}
+
+/// Default maximum characters of a snippet in the markdown source list
+///
+/// # Returns
+/// * `usize` - The default value of 200
+pub const fn default_markdown_snippet_chars() -> usize {
+    200
+}
+
+/// Default maximum characters of a snippet in `url_citation` annotations
+///
+/// # Returns
+/// * `usize` - The default value of 1000
+pub const fn default_annotation_snippet_chars() -> usize {
+    1000
+}
+
+/// Default tag wrapping reasoning streamed as content
+///
+/// # Returns
+/// * `String` - The default value of "think"
+pub fn default_reasoning_tag() -> String {
+    "think".to_string()
+}
+
+/// Default number of images uploaded to claude.ai at the same time
+///
+/// # Returns
+/// * `usize` - The default value of 4
+pub const fn default_image_upload_concurrency() -> usize {
+    4
+}
+
+/// Default setting for stripping control characters from response text
+///
+/// # Returns
+/// * `bool` - The default value of true
+pub const fn default_strip_control_chars() -> bool {
+    true
+}
+
+/// Default setting for injecting the Claude Code prelude system prompt
+///
+/// # Returns
+/// * `bool` - The default value of true
+pub const fn default_inject_claude_code_prelude() -> bool {
+    true
+}
+
+/// Default setting for merging adjacent messages that share a role
+///
+/// # Returns
+/// * `bool` - The default value of true
+pub const fn default_coalesce_consecutive_roles() -> bool {
+    true
+}
+
+/// Default setting for emitting null content on tool-call-only OpenAI responses
+///
+/// # Returns
+/// * `bool` - The default value of true
+pub const fn default_empty_content_as_null() -> bool {
+    true
+}
+
+/// Default media type for data URIs that do not specify one
+///
+/// # Returns
+/// * `String` - The default media type "image/png"
+pub fn default_image_media_type() -> String {
+    "image/png".to_string()
+}
+
+/// Default thinking budget for `reasoning_effort: "minimal"`
+///
+/// # Returns
+/// * `u64` - The default value of 1024, the smallest budget Claude accepts
+pub const fn default_effort_minimal_budget() -> u64 {
+    1024
+}
+
+/// Default thinking budget for `reasoning_effort: "low"`
+///
+/// # Returns
+/// * `u64` - The default value of 1536
+pub const fn default_effort_low_budget() -> u64 {
+    1536
+}
+
+/// Default thinking budget for `reasoning_effort: "medium"`
+///
+/// # Returns
+/// * `u64` - The default value of 2048
+pub const fn default_effort_medium_budget() -> u64 {
+    2048
+}
+
+/// Default thinking budget for `reasoning_effort: "high"`
+///
+/// # Returns
+/// * `u64` - The default value of 16384
+pub const fn default_effort_high_budget() -> u64 {
+    16384
+}
+
 /// Default cookie value for testing purposes
 pub const PLACEHOLDER_COOKIE: &str = "sk-ant-REDACTED";
@@ -8,8 +8,11 @@
 //! - OpenAI format: `{ "type": "image_url", "image_url": { "url": "data:..." or "https://..." } }`
 //! - Document format: `{ "type": "document", "source": { "type": "base64", ... } }`
 
-use crate::types::claude::{ContentBlock, DocumentSource, ImageSource, ImageUrl};
+use crate::types::claude::{
+    ContentBlock, DocumentSource, ImageSource, ImageUrl, Message, MessageContent,
+};
 use base64::{Engine, prelude::BASE64_STANDARD};
+use serde_json::Value;
 
 /// Supported image media types
 pub const SUPPORTED_IMAGE_TYPES: &[&str] = &[
@@ -23,6 +26,14 @@ pub const SUPPORTED_IMAGE_TYPES: &[&str] = &[
     "image/tiff",
 ];
 
+/// HEIC/HEIF media types sent by iOS clients, which Claude does not accept
+pub const HEIF_IMAGE_TYPES: &[&str] = &[
+    "image/heic",
+    "image/heif",
+    "image/heic-sequence",
+    "image/heif-sequence",
+];
+
 /// Supported document media types
 pub const SUPPORTED_DOCUMENT_TYPES: &[&str] = &[
     "application/pdf",
@@ -32,14 +43,55 @@ pub const SUPPORTED_DOCUMENT_TYPES: &[&str] = &[
     "application/json",
 ];
 
+/// Shortest `image_url.url` treated as bare base64 image data
+pub const MIN_BARE_BASE64_LEN: usize = 64;
+
+/// Number of leading bytes [`detect_media_type`] needs to recognize a format
+const MEDIA_TYPE_HEADER_BYTES: usize = 12;
+
+/// Interpret a URL that is really base64 image data without a `data:` prefix
+///
+/// The media type is sniffed from the first decoded bytes only, falling back
+/// to `default_media_type` when it can't be detected.
+///
+/// # Arguments
+/// * `url` - The `image_url.url` value
+/// * `default_media_type` - Media type used when none can be detected
+///
+/// # Returns
+/// * `Option<ImageSource>` - The image source, or None if the URL is not base64
+pub fn bare_base64_image(url: &str, default_media_type: &str) -> Option<ImageSource> {
+    let data = url.trim();
+    if data.len() < MIN_BARE_BASE64_LEN
+        || !data
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'='))
+    {
+        return None;
+    }
+    let header = decode_base64_prefix(data, MEDIA_TYPE_HEADER_BYTES)?;
+    Some(ImageSource {
+        type_: "base64".to_string(),
+        media_type: detect_media_type(&header).unwrap_or_else(|| default_media_type.to_string()),
+        data: data.to_string(),
+    })
+}
+
 /// Convert OpenAI image_url format to Claude native image format
 ///
 /// # Arguments
 /// * `image_url` - The OpenAI ImageUrl struct
+/// * `default_media_type` - Media type of bare base64 data whose format can't be detected
 ///
 /// # Returns
 /// * `Option<ContentBlock>` - Claude Image content block, or None if conversion fails
-pub fn oai_image_url_to_claude(image_url: &ImageUrl) -> Option<ContentBlock> {
+pub fn oai_image_url_to_claude(
+    image_url: &ImageUrl,
+    default_media_type: &str,
+) -> Option<ContentBlock> {
+    // Claude has no equivalent of OpenAI's `detail` hint. It is dropped when
+    // the image is inlined as base64, and kept on pass-through URLs so it can
+    // round-trip back to OpenAI clients.
     let url = &image_url.url;
 
     // Handle data URI
@@ -80,6 +132,15 @@ pub fn oai_image_url_to_claude(image_url: &ImageUrl) -> Option<ContentBlock> {
         });
     }
 
+    // Some clients send the base64 data without the data URI prefix
+    if let Some(source) = bare_base64_image(url, default_media_type) {
+        tracing::debug!("[ImageConverter] Treating image URL as bare base64 data");
+        return Some(ContentBlock::Image {
+            source,
+            cache_control: None,
+        });
+    }
+
     None
 }
 
@@ -97,13 +158,18 @@ pub fn claude_image_to_oai(source: &ImageSource) -> ContentBlock {
     );
 
     ContentBlock::ImageUrl {
-        image_url: ImageUrl { url: data_uri },
+        image_url: ImageUrl {
+            url: data_uri,
+            detail: None,
+        },
     }
 }
 
 /// Convert document to image source for upload
 ///
 /// Claude's document format can be converted to a generic upload format.
+/// Base64 documents without a media type are PDFs, as that is the only base64
+/// document type Claude accepts.
 ///
 /// # Arguments
 /// * `source` - The document source
@@ -119,7 +185,7 @@ pub fn document_to_image_source(source: &DocumentSource) -> Option<ImageSource>
     let media_type = source
         .media_type
         .clone()
-        .unwrap_or_else(|| "application/octet-stream".to_string());
+        .unwrap_or_else(|| "application/pdf".to_string());
 
     Some(ImageSource {
         type_: "base64".to_string(),
@@ -161,6 +227,31 @@ pub fn extract_image_from_data_uri(url: &str) -> Option<ImageSource> {
     })
 }
 
+/// Fill in a default media type for images whose data URI had none
+///
+/// `data:;base64,...` URIs yield an empty media type, which would make the
+/// upload pick the wrong file name.
+///
+/// # Arguments
+/// * `blocks` - The content blocks to process (modified in place)
+/// * `default_media_type` - Media type used when none was given
+pub fn apply_default_media_type(blocks: &mut [ContentBlock], default_media_type: &str) {
+    if default_media_type.is_empty() {
+        return;
+    }
+    for block in blocks {
+        if let ContentBlock::Image { source, .. } = block
+            && source.media_type.trim().is_empty()
+        {
+            tracing::debug!(
+                "[ImageConverter] Missing media type, defaulting to {}",
+                default_media_type
+            );
+            source.media_type = default_media_type.to_string();
+        }
+    }
+}
+
 /// Infer media type from file extension in URL
 ///
 /// # Arguments
@@ -184,6 +275,8 @@ pub fn infer_media_type_from_url(url: &str) -> String {
             "bmp" => return "image/bmp".to_string(),
             "ico" => return "image/x-icon".to_string(),
             "tiff" | "tif" => return "image/tiff".to_string(),
+            "heic" => return "image/heic".to_string(),
+            "heif" => return "image/heif".to_string(),
             "pdf" => return "application/pdf".to_string(),
             "txt" => return "text/plain".to_string(),
             "html" | "htm" => return "text/html".to_string(),
@@ -198,12 +291,227 @@ pub fn infer_media_type_from_url(url: &str) -> String {
 }
 
 /// Check if a media type is a supported image type
+///
+/// HEIC/HEIF images are recognized by [`is_heif_image_type`] but are not
+/// supported, since Claude rejects them.
 pub fn is_supported_image_type(media_type: &str) -> bool {
     SUPPORTED_IMAGE_TYPES
         .iter()
         .any(|&t| media_type.starts_with(t))
 }
 
+/// Check if a media type is a HEIC/HEIF image
+pub fn is_heif_image_type(media_type: &str) -> bool {
+    let media_type = media_type.trim().to_ascii_lowercase();
+    HEIF_IMAGE_TYPES.iter().any(|&t| media_type.starts_with(t))
+}
+
+/// Check if any image block holds a HEIC/HEIF image
+///
+/// Such images must be rejected before they reach Claude, as there is no
+/// decoder available to transcode them to JPEG.
+pub fn has_heif_image(blocks: &[ContentBlock]) -> bool {
+    blocks.iter().any(|block| match block {
+        ContentBlock::Image { source, .. } => is_heif_image_type(&source.media_type),
+        _ => false,
+    })
+}
+
+/// Count the `image_url` blocks that still await conversion
+pub fn count_image_urls(blocks: &[ContentBlock]) -> usize {
+    blocks
+        .iter()
+        .filter(|block| matches!(block, ContentBlock::ImageUrl { .. }))
+        .count()
+}
+
+/// Collect every image of the conversation as an upload source
+///
+/// Native images, base64 documents and data URI `image_url` blocks are
+/// returned in conversation order. Remote URLs are skipped, as they can't be
+/// uploaded without downloading them first.
+///
+/// # Arguments
+/// * `messages` - The messages to scan
+///
+/// # Returns
+/// * `Vec<ImageSource>` - The image sources found
+pub fn collect_image_sources(messages: &[Message]) -> Vec<ImageSource> {
+    messages
+        .iter()
+        .filter_map(|msg| match &msg.content {
+            MessageContent::Blocks { content } => Some(content),
+            MessageContent::Text { .. } => None,
+        })
+        .flatten()
+        .filter_map(|block| match block {
+            ContentBlock::Image { source, .. } => Some(source.to_owned()),
+            ContentBlock::Document { source, .. } => document_to_image_source(source),
+            ContentBlock::ImageUrl { image_url } => extract_image_from_data_uri(&image_url.url),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Keep at most `max_images` images, dropping the oldest ones
+///
+/// The most recent images are usually the ones the conversation is about, so
+/// the excess is removed from the front.
+///
+/// # Arguments
+/// * `images` - The images in conversation order (modified in place)
+/// * `max_images` - Maximum number of images to keep
+///
+/// # Returns
+/// * `usize` - The number of images dropped
+pub fn keep_last_images(images: &mut Vec<ImageSource>, max_images: usize) -> usize {
+    let excess = images.len().saturating_sub(max_images);
+    images.drain(..excess);
+    excess
+}
+
+/// Check whether a tool_result content part is an image block
+fn is_tool_result_image(part: &Value) -> bool {
+    part.get("type").and_then(Value::as_str) == Some("image")
+}
+
+/// Count the image blocks sent to Claude, including the ones nested in
+/// tool_result content
+///
+/// # Arguments
+/// * `messages` - The messages to scan
+///
+/// # Returns
+/// * `usize` - The number of image blocks
+pub fn count_image_blocks(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .filter_map(|msg| match &msg.content {
+            MessageContent::Blocks { content } => Some(content),
+            MessageContent::Text { .. } => None,
+        })
+        .flatten()
+        .map(|block| match block {
+            ContentBlock::Image { .. } => 1,
+            ContentBlock::ToolResult {
+                content: Value::Array(parts),
+                ..
+            } => parts.iter().filter(|part| is_tool_result_image(part)).count(),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Remove the oldest image blocks, including the ones nested in tool_result
+/// content, until at most `max_images` remain
+///
+/// # Arguments
+/// * `messages` - The messages to trim (modified in place)
+/// * `max_images` - Maximum number of image blocks to keep
+///
+/// # Returns
+/// * `usize` - The number of image blocks removed
+pub fn drop_oldest_image_blocks(messages: &mut [Message], max_images: usize) -> usize {
+    let excess = count_image_blocks(messages).saturating_sub(max_images);
+    let mut remaining = excess;
+    for msg in messages.iter_mut() {
+        let MessageContent::Blocks { content } = &mut msg.content else {
+            continue;
+        };
+        content.retain_mut(|block| match block {
+            ContentBlock::Image { .. } if remaining > 0 => {
+                remaining -= 1;
+                false
+            }
+            ContentBlock::ToolResult {
+                content: Value::Array(parts),
+                ..
+            } => {
+                parts.retain(|part| {
+                    if remaining > 0 && is_tool_result_image(part) {
+                        remaining -= 1;
+                        return false;
+                    }
+                    true
+                });
+                true
+            }
+            _ => true,
+        });
+    }
+    excess
+}
+
+/// Find every base64 image data URI embedded in a piece of text
+///
+/// Tool outputs sometimes inline images as `data:image/...;base64,...` inside
+/// plain text or serialized JSON. Each URI ends at the first character that
+/// is not part of the base64 alphabet.
+///
+/// # Arguments
+/// * `text` - The text to scan
+///
+/// # Returns
+/// * `Vec<ImageSource>` - The images found, in text order
+pub fn find_data_uri_images(text: &str) -> Vec<ImageSource> {
+    let is_media_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.');
+    let is_base64_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=');
+    text.match_indices("data:image/")
+        .filter_map(|(start, _)| {
+            let uri = &text[start..];
+            let subtype_start = "data:image/".len();
+            let subtype_len = uri[subtype_start..]
+                .find(|c| !is_media_char(c))
+                .unwrap_or(uri.len() - subtype_start);
+            let media_end = subtype_start + subtype_len;
+            let data = uri[media_end..].strip_prefix(";base64,")?;
+            let data_len = data.find(|c| !is_base64_char(c)).unwrap_or(data.len());
+            (subtype_len > 0 && data_len > 0).then(|| ImageSource {
+                type_: "base64".to_string(),
+                media_type: uri["data:".len()..media_end].to_string(),
+                data: data[..data_len].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Collect the strings of a JSON value, depth first
+fn collect_strings<'a>(value: &'a Value, strings: &mut Vec<&'a str>) {
+    match value {
+        Value::String(text) => strings.push(text),
+        Value::Array(items) => items.iter().for_each(|item| collect_strings(item, strings)),
+        Value::Object(map) => map.values().for_each(|item| collect_strings(item, strings)),
+        _ => {}
+    }
+}
+
+/// Collect the data URI images embedded in tool_result content
+///
+/// Every string of the tool_result content is scanned, including text parts
+/// and JSON serialized as text.
+///
+/// # Arguments
+/// * `messages` - The messages to scan
+///
+/// # Returns
+/// * `Vec<ImageSource>` - The image sources found, in conversation order
+pub fn collect_tool_result_images(messages: &[Message]) -> Vec<ImageSource> {
+    let mut strings = Vec::new();
+    messages
+        .iter()
+        .filter_map(|msg| match &msg.content {
+            MessageContent::Blocks { content } => Some(content),
+            MessageContent::Text { .. } => None,
+        })
+        .flatten()
+        .for_each(|block| {
+            if let ContentBlock::ToolResult { content, .. } = block {
+                collect_strings(content, &mut strings);
+            }
+        });
+    strings.into_iter().flat_map(find_data_uri_images).collect()
+}
+
 /// Check if a media type is a supported document type
 pub fn is_supported_document_type(media_type: &str) -> bool {
     SUPPORTED_DOCUMENT_TYPES
@@ -224,6 +532,30 @@ pub fn is_valid_base64(data: &str) -> bool {
     BASE64_STANDARD.decode(data).is_ok()
 }
 
+/// Compute the decoded size of base64 data from its length alone
+///
+/// # Arguments
+/// * `data` - The base64 string
+///
+/// # Returns
+/// * `usize` - The number of bytes the data decodes to
+pub fn base64_decoded_len(data: &str) -> usize {
+    let unpadded = data.trim_end_matches('=').len();
+    unpadded / 4 * 3 + (unpadded % 4 * 3 / 4)
+}
+
+/// Check that base64 data decodes to at most `max_bytes` without decoding it
+///
+/// # Arguments
+/// * `data` - The base64 string
+/// * `max_bytes` - Maximum decoded size in bytes
+///
+/// # Returns
+/// * `bool` - True if the decoded data fits the limit
+pub fn base64_within_limit(data: &str, max_bytes: usize) -> bool {
+    base64_decoded_len(data) <= max_bytes
+}
+
 /// Convert raw bytes to base64 ImageSource
 ///
 /// # Arguments
@@ -240,6 +572,178 @@ pub fn bytes_to_image_source(bytes: &[u8], media_type: &str) -> ImageSource {
     }
 }
 
+/// Detect the media type of raw bytes from their magic number
+///
+/// Recognizes PNG, JPEG, GIF, WebP (lossy, lossless and extended RIFF
+/// containers), HEIC/HEIF and PDF.
+///
+/// # Arguments
+/// * `bytes` - The raw file bytes
+///
+/// # Returns
+/// * `Option<String>` - The detected media type, or None if unknown
+pub fn detect_media_type(bytes: &[u8]) -> Option<String> {
+    let media_type = if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "image/jpeg"
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        "image/gif"
+    } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+        "image/webp"
+    } else if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" {
+        match &bytes[8..12] {
+            b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" => "image/heic",
+            b"heif" | b"mif1" | b"msf1" => "image/heif",
+            _ => return None,
+        }
+    } else if bytes.starts_with(b"%PDF") {
+        "application/pdf"
+    } else {
+        return None;
+    };
+    Some(media_type.to_string())
+}
+
+/// Read the pixel dimensions of an image from its header
+///
+/// Supports PNG, JPEG, GIF and WebP without decoding the image itself.
+///
+/// # Arguments
+/// * `bytes` - The raw image bytes
+///
+/// # Returns
+/// * `Option<(u32, u32)>` - The width and height, or None if unknown
+pub fn image_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let be16 = |i: usize| Some(u16::from_be_bytes(bytes.get(i..i + 2)?.try_into().ok()?) as u32);
+    let le16 = |i: usize| Some(u16::from_le_bytes(bytes.get(i..i + 2)?.try_into().ok()?) as u32);
+    let le24 = |i: usize| {
+        let b = bytes.get(i..i + 3)?;
+        Some(u32::from_le_bytes([b[0], b[1], b[2], 0]))
+    };
+    match detect_media_type(bytes)?.as_str() {
+        "image/png" => {
+            let be32 = |i: usize| Some(u32::from_be_bytes(bytes.get(i..i + 4)?.try_into().ok()?));
+            Some((be32(16)?, be32(20)?))
+        }
+        "image/gif" => Some((le16(6)?, le16(8)?)),
+        "image/webp" => match bytes.get(12..16)? {
+            b"VP8 " => Some((le16(26)? & 0x3FFF, le16(28)? & 0x3FFF)),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(bytes.get(21..25)?.try_into().ok()?);
+                Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+            }
+            b"VP8X" => Some((le24(24)? + 1, le24(27)? + 1)),
+            _ => None,
+        },
+        "image/jpeg" => {
+            // Walk the segments until a start-of-frame marker
+            let mut i = 2;
+            while i + 4 <= bytes.len() {
+                if bytes[i] != 0xFF {
+                    return None;
+                }
+                let marker = bytes[i + 1];
+                match marker {
+                    0xFF => i += 1,
+                    0x01 | 0xD0..=0xD9 => i += 2,
+                    0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                        return Some((be16(i + 7)?, be16(i + 5)?));
+                    }
+                    _ => i += 2 + be16(i + 2)? as usize,
+                }
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Bytes decoded from the start of an image to read its header
+///
+/// Large enough to reach the JPEG frame header past a typical EXIF segment.
+const IMAGE_HEADER_BYTES: usize = 64 * 1024;
+
+/// Decode at most `max_bytes` from the start of base64 data
+///
+/// The prefix is cut at a whole base64 quad, so only the header of a large
+/// payload is decoded.
+///
+/// # Returns
+/// * `Option<Vec<u8>>` - The decoded bytes, or None if the prefix is not valid base64
+pub fn decode_base64_prefix(data: &str, max_bytes: usize) -> Option<Vec<u8>> {
+    let end = data.len().min(max_bytes.div_ceil(3) * 4);
+    BASE64_STANDARD.decode(&data.as_bytes()[..end]).ok()
+}
+
+/// Read the pixel dimensions of a base64 image source
+///
+/// # Arguments
+/// * `source` - The image source
+///
+/// # Returns
+/// * `Option<(u32, u32)>` - The width and height, or None if not decodable
+pub fn image_source_dimensions(source: &ImageSource) -> Option<(u32, u32)> {
+    if source.type_ != "base64" {
+        return None;
+    }
+    image_dimensions(&decode_base64_prefix(&source.data, IMAGE_HEADER_BYTES)?)
+}
+
+/// Estimate the input tokens of the images in a conversation
+///
+/// Images are only counted when their dimensions can be read.
+///
+/// # Arguments
+/// * `messages` - The messages to scan
+///
+/// # Returns
+/// * `u32` - The estimated token count of all images
+pub fn count_image_tokens(messages: &[Message]) -> u32 {
+    messages
+        .iter()
+        .filter_map(|msg| match &msg.content {
+            MessageContent::Blocks { content } => Some(content),
+            MessageContent::Text { .. } => None,
+        })
+        .flatten()
+        .filter_map(|block| match block {
+            ContentBlock::Image { source, .. } => image_source_dimensions(source),
+            _ => None,
+        })
+        .map(|(width, height)| estimate_image_tokens(width, height))
+        .sum()
+}
+
+/// Estimate the input token cost of an image
+///
+/// Anthropic approximates image tokens as `width * height / 750`.
+///
+/// # Arguments
+/// * `width` - Image width in pixels
+/// * `height` - Image height in pixels
+///
+/// # Returns
+/// * `u32` - The estimated token count
+pub fn estimate_image_tokens(width: u32, height: u32) -> u32 {
+    (width as u64 * height as u64 / 750).min(u32::MAX as u64) as u32
+}
+
+/// Convert raw bytes to base64 ImageSource, sniffing the media type
+///
+/// Falls back to `application/octet-stream` when the type cannot be detected.
+///
+/// # Arguments
+/// * `bytes` - The raw image bytes
+///
+/// # Returns
+/// * `ImageSource` - The image source with base64 encoded data
+pub fn bytes_to_image_source_sniffed(bytes: &[u8]) -> ImageSource {
+    let media_type =
+        detect_media_type(bytes).unwrap_or_else(|| "application/octet-stream".to_string());
+    bytes_to_image_source(bytes, &media_type)
+}
+
 /// Process content blocks and extract/convert images
 ///
 /// This function processes a vector of content blocks and:
@@ -250,22 +754,22 @@ pub fn bytes_to_image_source(bytes: &[u8], media_type: &str) -> ImageSource {
 ///
 /// # Arguments
 /// * `blocks` - The content blocks to process
+/// * `default_media_type` - Media type of bare base64 images whose format can't be detected
 ///
 /// # Returns
 /// * `Vec<ContentBlock>` - Processed content blocks
-pub fn process_image_blocks(blocks: Vec<ContentBlock>) -> Vec<ContentBlock> {
+pub fn process_image_blocks(
+    blocks: Vec<ContentBlock>,
+    default_media_type: &str,
+) -> Vec<ContentBlock> {
     blocks
         .into_iter()
         .map(|block| {
             match block {
                 ContentBlock::ImageUrl { image_url } => {
-                    // Try to convert to native format
-                    if let Some(converted) = oai_image_url_to_claude(&image_url) {
-                        converted
-                    } else {
-                        // Keep as-is if conversion fails
-                        ContentBlock::ImageUrl { image_url }
-                    }
+                    // Try to convert to native format, keep as-is if conversion fails
+                    oai_image_url_to_claude(&image_url, default_media_type)
+                        .unwrap_or(ContentBlock::ImageUrl { image_url })
                 }
                 ContentBlock::Document { source, cache_control } => {
                     // Check if document type is supported
@@ -341,6 +845,73 @@ pub fn bytes_to_content_block(bytes: &[u8], media_type: &str, validate: bool) ->
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_base64_size_precheck() {
+        assert_eq!(base64_decoded_len("aGVsbG8="), 5);
+        assert_eq!(base64_decoded_len("aGVsbG8"), 5);
+        assert_eq!(base64_decoded_len("aGVsbG8h"), 6);
+        assert!(base64_within_limit("aGVsbG8=", 5));
+
+        // Not valid base64 at all, so a rejection proves nothing was decoded
+        let oversized = "!".repeat(4 * 1024);
+        assert!(!base64_within_limit(&oversized, 1024));
+    }
+
+    #[test]
+    fn test_estimate_image_tokens() {
+        assert_eq!(estimate_image_tokens(1000, 1000), 1333);
+        assert_eq!(estimate_image_tokens(200, 200), 53);
+        assert_eq!(estimate_image_tokens(0, 512), 0);
+    }
+
+    #[test]
+    fn test_image_dimensions() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&640u32.to_be_bytes());
+        png.extend_from_slice(&480u32.to_be_bytes());
+        assert_eq!(image_dimensions(&png), Some((640, 480)));
+
+        let gif = b"GIF89a\x20\x03\x58\x02";
+        assert_eq!(image_dimensions(gif), Some((800, 600)));
+
+        // SOI, an APP0 segment, then SOF0 with height 300 and width 400
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01,
+            0x2C, 0x01, 0x90,
+        ];
+        assert_eq!(image_dimensions(&jpeg), Some((400, 300)));
+
+        let source = bytes_to_image_source(&png, "image/png");
+        assert_eq!(image_source_dimensions(&source), Some((640, 480)));
+        assert_eq!(image_dimensions(b"%PDF-1.7"), None);
+
+        // Only the header of a large image is decoded
+        png.resize(IMAGE_HEADER_BYTES * 4, 0);
+        let source = bytes_to_image_source(&png, "image/png");
+        assert_eq!(decode_base64_prefix(&source.data, 32).map(|b| b.len()), Some(33));
+        assert_eq!(image_source_dimensions(&source), Some((640, 480)));
+    }
+
+    #[test]
+    fn test_count_image_tokens() {
+        use crate::types::claude::Role;
+
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&1092u32.to_be_bytes());
+        png.extend_from_slice(&1092u32.to_be_bytes());
+        let messages = vec![
+            Message::new_text(Role::User, "no images here"),
+            Message::new_blocks(
+                Role::User,
+                vec![ContentBlock::Image {
+                    source: bytes_to_image_source(&png, "image/png"),
+                    cache_control: None,
+                }],
+            ),
+        ];
+        assert_eq!(count_image_tokens(&messages), estimate_image_tokens(1092, 1092));
+    }
+
     #[test]
     fn test_extract_image_from_data_uri() {
         let uri = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUg==";
@@ -353,6 +924,207 @@ mod tests {
         assert_eq!(source.data, "iVBORw0KGgoAAAANSUhEUg==");
     }
 
+    #[test]
+    fn test_collect_image_sources() {
+        use crate::types::claude::Role;
+
+        let image_url = |url: &str| ContentBlock::ImageUrl {
+            image_url: ImageUrl {
+                url: url.to_string(),
+                detail: None,
+            },
+        };
+        let messages = vec![
+            Message::new_text(Role::User, "no images here"),
+            Message::new_blocks(
+                Role::User,
+                vec![
+                    ContentBlock::Image {
+                        source: bytes_to_image_source(b"GIF87a", "image/gif"),
+                        cache_control: None,
+                    },
+                    image_url("https://example.com/remote.png"),
+                    image_url("data:image/png;base64,iVBORw0KGgo="),
+                ],
+            ),
+            Message::new_blocks(
+                Role::User,
+                vec![
+                    ContentBlock::Document {
+                        source: DocumentSource {
+                            type_: "base64".to_string(),
+                            media_type: Some("application/pdf".to_string()),
+                            data: Some("JVBERi0xLjQ=".to_string()),
+                            url: None,
+                        },
+                        cache_control: None,
+                    },
+                    ContentBlock::Document {
+                        source: DocumentSource {
+                            type_: "base64".to_string(),
+                            media_type: None,
+                            data: Some("JVBERi0xLjQ=".to_string()),
+                            url: None,
+                        },
+                        cache_control: None,
+                    },
+                ],
+            ),
+        ];
+
+        let media_types: Vec<String> = collect_image_sources(&messages)
+            .into_iter()
+            .map(|source| source.media_type)
+            .collect();
+        // The remote URL can't be uploaded and is skipped, and a document
+        // without a media type is treated as a PDF
+        assert_eq!(
+            media_types,
+            vec!["image/gif", "image/png", "application/pdf", "application/pdf"]
+        );
+    }
+
+    #[test]
+    fn test_bare_base64_image_url() {
+        let image_url = |url: String| ImageUrl { url, detail: None };
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.resize(64, 0);
+
+        let convert = |url: String| oai_image_url_to_claude(&image_url(url), "image/jpeg");
+        match convert(BASE64_STANDARD.encode(&png)) {
+            Some(ContentBlock::Image { source, .. }) => {
+                assert_eq!(source.type_, "base64");
+                assert_eq!(source.media_type, "image/png");
+            }
+            other => panic!("unexpected block: {other:?}"),
+        }
+
+        // Undetected bytes get the configured default media type
+        let source = bare_base64_image(&BASE64_STANDARD.encode([7u8; 64]), "image/jpeg").unwrap();
+        assert_eq!(source.media_type, "image/jpeg");
+
+        // Too short or not base64 at all
+        assert!(convert("aGVsbG8=".to_string()).is_none());
+        assert!(convert("not base64! ".repeat(10)).is_none());
+    }
+
+    #[test]
+    fn test_keep_last_images() {
+        let mut images: Vec<ImageSource> = ["image/png", "image/gif", "image/jpeg"]
+            .into_iter()
+            .map(|media_type| bytes_to_image_source(b"img", media_type))
+            .collect();
+
+        assert_eq!(keep_last_images(&mut images, 5), 0);
+        assert_eq!(images.len(), 3);
+        assert_eq!(keep_last_images(&mut images, 2), 1);
+        let media_types: Vec<&str> = images.iter().map(|i| i.media_type.as_str()).collect();
+        assert_eq!(media_types, vec!["image/gif", "image/jpeg"]);
+    }
+
+    #[test]
+    fn test_drop_oldest_image_blocks() {
+        use crate::types::claude::Role;
+
+        let image = |media_type: &str| ContentBlock::Image {
+            source: bytes_to_image_source(b"img", media_type),
+            cache_control: None,
+        };
+        let mut messages = vec![
+            Message::new_blocks(Role::User, vec![image("image/png"), ContentBlock::text("a")]),
+            Message::new_blocks(
+                Role::User,
+                vec![ContentBlock::ToolResult {
+                    tool_use_id: "toolu_1".to_string(),
+                    content: serde_json::json!([
+                        {
+                            "type": "image",
+                            "source": {"type": "base64", "media_type": "image/gif", "data": "aW1n"}
+                        },
+                        {"type": "text", "text": "done"}
+                    ]),
+                    is_error: None,
+                    cache_control: None,
+                }],
+            ),
+            Message::new_blocks(Role::User, vec![image("image/jpeg")]),
+        ];
+
+        assert_eq!(count_image_blocks(&messages), 3);
+        assert_eq!(drop_oldest_image_blocks(&mut messages, 5), 0);
+        assert_eq!(drop_oldest_image_blocks(&mut messages, 1), 2);
+        assert_eq!(count_image_blocks(&messages), 1);
+        let MessageContent::Blocks { content } = &messages[0].content else {
+            panic!("expected blocks");
+        };
+        assert!(matches!(content.as_slice(), [ContentBlock::Text { .. }]));
+        let MessageContent::Blocks { content } = &messages[2].content else {
+            panic!("expected blocks");
+        };
+        assert!(matches!(content.as_slice(), [ContentBlock::Image { .. }]));
+    }
+
+    #[test]
+    fn test_collect_tool_result_images() {
+        use crate::types::claude::Role;
+
+        let tool_result = |content: Value| ContentBlock::ToolResult {
+            tool_use_id: "toolu_1".to_string(),
+            content,
+            is_error: None,
+            cache_control: None,
+        };
+        let messages = vec![
+            Message::new_text(Role::User, "data:image/png;base64,iVBORw0KGgo= in plain text"),
+            Message::new_blocks(
+                Role::User,
+                vec![
+                    tool_result(Value::String(
+                        "Screenshot: data:image/png;base64,iVBORw0KGgo=\nDone".to_string(),
+                    )),
+                    tool_result(serde_json::json!([{
+                        "type": "text",
+                        "text": r#"{"thumb": "data:image/jpeg;base64,/9j/4AAQ", "ok": true}"#
+                    }])),
+                    tool_result(Value::String("data:image/png;base64,".to_string())),
+                ],
+            ),
+        ];
+
+        let images = collect_tool_result_images(&messages);
+        assert_eq!(
+            images,
+            vec![
+                ImageSource {
+                    type_: "base64".to_string(),
+                    media_type: "image/png".to_string(),
+                    data: "iVBORw0KGgo=".to_string(),
+                },
+                ImageSource {
+                    type_: "base64".to_string(),
+                    media_type: "image/jpeg".to_string(),
+                    data: "/9j/4AAQ".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data_uri_without_media_type_gets_default() {
+        let source = extract_image_from_data_uri("data:;base64,iVBORw0KGgo=").unwrap();
+        assert_eq!(source.media_type, "");
+
+        let mut blocks = vec![ContentBlock::Image {
+            source,
+            cache_control: None,
+        }];
+        apply_default_media_type(&mut blocks, "image/png");
+        match &blocks[0] {
+            ContentBlock::Image { source, .. } => assert_eq!(source.media_type, "image/png"),
+            other => panic!("Expected Image block, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_infer_media_type() {
         assert_eq!(
@@ -386,6 +1158,22 @@ mod tests {
         assert!(!is_supported_image_type("text/plain"));
     }
 
+    #[test]
+    fn test_heic_is_recognized_but_unsupported() {
+        for media_type in ["image/heic", "image/HEIF", "image/heic-sequence"] {
+            assert!(is_heif_image_type(media_type));
+            assert!(!is_supported_image_type(media_type));
+        }
+        assert!(!is_heif_image_type("image/jpeg"));
+        assert_eq!(infer_media_type_from_url("https://example.com/IMG_0001.HEIC"), "image/heic");
+
+        let blocks = vec![ContentBlock::Image {
+            source: bytes_to_image_source(b"\x00\x00\x00\x18ftypheic", "image/heic"),
+            cache_control: None,
+        }];
+        assert!(has_heif_image(&blocks));
+    }
+
     #[test]
     fn test_is_supported_document_type() {
         assert!(is_supported_document_type("application/pdf"));
@@ -407,8 +1195,9 @@ mod tests {
     fn test_oai_to_claude_data_uri() {
         let image_url = ImageUrl {
             url: "data:image/png;base64,iVBORw0KGgo=".to_string(),
+            detail: None,
         };
-        let result = oai_image_url_to_claude(&image_url);
+        let result = oai_image_url_to_claude(&image_url, "image/png");
 
         assert!(result.is_some());
         if let Some(ContentBlock::Image { source, .. }) = result {
@@ -422,8 +1211,9 @@ mod tests {
     fn test_oai_to_claude_http_url() {
         let image_url = ImageUrl {
             url: "https://example.com/image.png".to_string(),
+            detail: None,
         };
-        let result = oai_image_url_to_claude(&image_url);
+        let result = oai_image_url_to_claude(&image_url, "image/png");
 
         assert!(result.is_some());
         if let Some(ContentBlock::ImageUrl { .. }) = result {
@@ -463,4 +1253,34 @@ mod tests {
         let source = result.unwrap();
         assert_eq!(source.media_type, "application/pdf");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_detect_media_type_magic_bytes() {
+        let cases: &[(&[u8], Option<&str>)] = &[
+            (b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR", Some("image/png")),
+            (&[0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10], Some("image/jpeg")),
+            (b"GIF89a\x01\x00\x01\x00", Some("image/gif")),
+            (b"RIFF\x24\x00\x00\x00WEBPVP8L", Some("image/webp")),
+            (b"RIFF\x24\x00\x00\x00WEBPVP8 ", Some("image/webp")),
+            (b"RIFF\x24\x00\x00\x00WAVEfmt ", None),
+            (b"\x00\x00\x00\x18ftypheic\x00\x00\x00\x00", Some("image/heic")),
+            (b"\x00\x00\x00\x18ftypmif1\x00\x00\x00\x00", Some("image/heif")),
+            (b"\x00\x00\x00\x18ftypisom\x00\x00\x00\x00", None),
+            (b"%PDF-1.7", Some("application/pdf")),
+            (b"plain text", None),
+            (b"", None),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(detect_media_type(bytes).as_deref(), *expected);
+        }
+    }
+
+    #[test]
+    fn test_bytes_to_image_source_sniffed() {
+        let source = bytes_to_image_source_sniffed(b"GIF87a\x01\x00\x01\x00");
+        assert_eq!(source.media_type, "image/gif");
+
+        let source = bytes_to_image_source_sniffed(b"unknown");
+        assert_eq!(source.media_type, "application/octet-stream");
+    }
+}
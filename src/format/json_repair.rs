@@ -0,0 +1,141 @@
+//! Best-effort repair of truncated JSON
+//!
+//! Streamed tool call arguments arrive as `input_json_delta` fragments. When a
+//! stream is cut short the accumulated string is not valid JSON, so instead of
+//! discarding everything this module closes open strings, arrays and objects,
+//! dropping an incomplete trailing member if needed.
+
+use serde_json::Value;
+
+/// Result of scanning a JSON prefix
+struct Scan {
+    /// Open `{` / `[` in nesting order
+    open: Vec<char>,
+    /// Whether the prefix ends inside a string
+    in_string: bool,
+    /// Whether the prefix ends right after a backslash inside a string
+    escaped: bool,
+    /// Byte offsets where the prefix can be cut to drop a trailing member
+    cut_points: Vec<usize>,
+}
+
+fn scan(text: &str) -> Scan {
+    let mut scan = Scan {
+        open: Vec::new(),
+        in_string: false,
+        escaped: false,
+        cut_points: Vec::new(),
+    };
+    for (i, c) in text.char_indices() {
+        if scan.in_string {
+            match c {
+                _ if scan.escaped => scan.escaped = false,
+                '\\' => scan.escaped = true,
+                '"' => scan.in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => scan.in_string = true,
+            '{' | '[' => {
+                scan.open.push(c);
+                scan.cut_points.push(i + 1);
+            }
+            '}' | ']' => {
+                scan.open.pop();
+            }
+            ',' => scan.cut_points.push(i),
+            _ => {}
+        }
+    }
+    scan
+}
+
+/// Close every open string, array and object of a JSON prefix
+fn close(text: &str) -> String {
+    let scan = scan(text);
+    let mut closed = text.to_string();
+    if scan.in_string {
+        if scan.escaped {
+            closed.pop();
+        }
+        closed.push('"');
+    }
+    for open in scan.open.iter().rev() {
+        closed.push(if *open == '{' { '}' } else { ']' });
+    }
+    closed
+}
+
+/// Parse JSON, repairing it if it was truncated
+///
+/// Valid JSON is returned as-is. Otherwise open strings and containers are
+/// closed, and if that is not enough the trailing incomplete member is
+/// dropped, retrying at earlier cut points.
+///
+/// # Arguments
+/// * `text` - The possibly truncated JSON text
+///
+/// # Returns
+/// The parsed value and whether repair was needed, or `None` if unrecoverable
+pub fn parse_or_repair_json(text: &str) -> Option<(Value, bool)> {
+    if let Ok(value) = serde_json::from_str(text) {
+        return Some((value, false));
+    }
+    let text = text.trim_end();
+    if let Ok(value) = serde_json::from_str(&close(text)) {
+        return Some((value, true));
+    }
+    scan(text)
+        .cut_points
+        .into_iter()
+        .rev()
+        .find_map(|cut| serde_json::from_str(&close(&text[..cut])).ok())
+        .map(|value| (value, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_valid_json_untouched() {
+        assert_eq!(
+            parse_or_repair_json(r#"{"a": [1, 2]}"#),
+            Some((json!({"a": [1, 2]}), false))
+        );
+    }
+
+    #[test]
+    fn test_closes_open_string_and_objects() {
+        assert_eq!(
+            parse_or_repair_json(r#"{"city": "Par"#),
+            Some((json!({"city": "Par"}), true))
+        );
+        assert_eq!(
+            parse_or_repair_json(r#"{"filter": {"tags": ["a", "b"#),
+            Some((json!({"filter": {"tags": ["a", "b"]}}), true))
+        );
+    }
+
+    #[test]
+    fn test_drops_incomplete_member() {
+        assert_eq!(
+            parse_or_repair_json(r#"{"city": "Paris", "unit": "#),
+            Some((json!({"city": "Paris"}), true))
+        );
+        assert_eq!(
+            parse_or_repair_json(r#"{"city": "Paris", "ok": tr"#),
+            Some((json!({"city": "Paris"}), true))
+        );
+        assert_eq!(parse_or_repair_json(r#"{"ci"#), Some((json!({}), true)));
+    }
+
+    #[test]
+    fn test_unrecoverable() {
+        assert_eq!(parse_or_repair_json("not json"), None);
+        assert_eq!(parse_or_repair_json(""), None);
+    }
+}
@@ -2,13 +2,19 @@
 //!
 //! This module provides utilities for format conversion between Claude and OpenAI APIs,
 //! including signature management, schema cleaning, parameter remapping, thinking utilities,
-//! web search result formatting, and image format conversion.
+//! tool ID normalization, tool result splitting, JSON repair, web search result formatting,
+//! image format conversion, stop reason mapping, and conversion reporting.
 
 pub mod image_converter;
+pub mod json_repair;
 pub mod param_remapper;
+pub mod report;
 pub mod schema_cleaner;
 pub mod signature_store;
+pub mod stop_reason;
 pub mod thinking_utils;
+pub mod tool_id;
+pub mod tool_result;
 pub mod web_search;
 
 // Signature store exports
@@ -18,34 +24,67 @@ pub use signature_store::{
 
 // Schema cleaner exports
 pub use schema_cleaner::{
-    clean_json_schema, ensure_valid_schema, expand_refs, move_constraints_to_description,
+    canonical_schema_string, clean_json_schema, clean_json_schema_strict, ensure_valid_schema,
+    expand_refs, move_constraints_to_description, require_all_properties,
 };
 
+// JSON repair exports
+pub use json_repair::parse_or_repair_json;
+
 // Parameter remapper exports
 pub use param_remapper::{remap_function_call_args, remap_oai_to_claude_args, remap_tool_result_args, remap_tool_use};
 
 // Thinking utilities exports
 pub use thinking_utils::{
-    analyze_conversation_state, extract_signatures, has_valid_signature_for_function_calls,
-    message_has_tool_result, message_has_tool_use, message_has_valid_thinking,
-    needs_thinking_recovery, should_disable_thinking_due_to_history, strip_invalid_thinking_blocks,
+    analyze_conversation_state, demote_thinking_to_text, extract_signatures,
+    has_valid_signature_for_function_calls, message_has_tool_result, message_has_tool_use,
+    message_has_valid_thinking, needs_thinking_recovery, should_disable_thinking_due_to_history,
+    strip_invalid_thinking_blocks, strip_non_assistant_thinking, unmatched_tool_use_ids,
     ConversationState, MIN_SIGNATURE_LENGTH,
 };
 
+// Tool ID exports
+pub use tool_id::{
+    is_claude_tool_id, normalize_tool_ids, sanitize_tool_id, ToolIdMap, CLAUDE_TOOL_ID_PREFIX,
+};
+
+// Tool result exports
+pub use tool_result::{
+    decode_json_tool_content, decode_json_tool_results, split_oversized_tool_results,
+    split_tool_result_content, summarize_old_tool_results, ELIDED_TOOL_RESULT,
+    MAX_TOOL_RESULT_PART_BYTES,
+};
+
 // Web search exports
 pub use web_search::{
     annotations_to_web_search_content, citations_to_annotations,
-    extract_citations_from_search_result, extract_citations_from_tool_result,
-    format_citations_as_markdown, merge_citations_into_text, Citation,
+    extract_citations_from_search_result, extract_citations_from_text_citations,
+    extract_citations_from_tool_result, format_citations_as_markdown, merge_citations_into_text,
+    normalize_snippet, truncate_snippet, Citation,
 };
 
+// Stop reason exports
+pub use stop_reason::{openai_to_stop_reason, stop_reason_to_openai};
+
+// Conversion report exports
+pub use report::ConversionReport;
+
 // Image converter exports
 pub use image_converter::{
-    bytes_to_content_block, bytes_to_image_source, claude_image_to_oai, document_to_image_source,
-    extract_image_from_data_uri, infer_media_type_from_url, is_supported_document_type,
-    is_supported_image_type, is_valid_base64, oai_image_url_to_claude, process_image_blocks,
+    apply_default_media_type, bare_base64_image, base64_decoded_len, base64_within_limit,
+    bytes_to_content_block, bytes_to_image_source, bytes_to_image_source_sniffed,
+    claude_image_to_oai, collect_image_sources, collect_tool_result_images, count_image_blocks,
+    count_image_tokens, count_image_urls, decode_base64_prefix, detect_media_type,
+    document_to_image_source, drop_oldest_image_blocks, estimate_image_tokens,
+    extract_image_from_data_uri, find_data_uri_images, has_heif_image, image_dimensions,
+    image_source_dimensions, infer_media_type_from_url, is_heif_image_type,
+    is_supported_document_type, is_supported_image_type, is_valid_base64, keep_last_images,
+    oai_image_url_to_claude, process_image_blocks, HEIF_IMAGE_TYPES, MIN_BARE_BASE64_LEN,
     SUPPORTED_DOCUMENT_TYPES, SUPPORTED_IMAGE_TYPES,
 };
 
-// Re-export cache_control cleaning from types module
-pub use crate::types::claude::clean_cache_control_from_messages;
\ No newline at end of file
+// Re-export cache_control helpers from types module
+pub use crate::types::claude::{
+    MAX_CACHE_BREAKPOINTS, apply_cache_breakpoints, clean_cache_control_from_messages,
+    count_prefix_cache_breakpoints, dedup_system_blocks, normalize_system_cache_ttl, normalize_ttl,
+};
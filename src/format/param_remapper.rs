@@ -21,6 +21,8 @@ use serde_json::Value;
 /// - `Grep`: `query` → `pattern`
 /// - `Glob`: `query` → `pattern`
 /// - `Read`: `path` → `file_path`
+/// - `TodoWrite`: `items`, `tasks` → `todos`
+/// - `Task`: `task`, `instructions` → `prompt`, `title` → `description`
 pub fn remap_function_call_args(tool_name: &str, args: &mut Value) {
     let Some(obj) = args.as_object_mut() else {
         return;
@@ -81,6 +83,34 @@ pub fn remap_function_call_args(tool_name: &str, args: &mut Value) {
                 }
             }
         }
+        "TodoWrite" => {
+            // Gemini sometimes names the todo list "items" or "tasks"
+            for alias in ["items", "tasks"] {
+                if let Some(todos) = obj.remove(alias) {
+                    if !obj.contains_key("todos") {
+                        obj.insert("todos".to_string(), todos);
+                        tracing::debug!("[ParamRemap] TodoWrite: {} → todos", alias);
+                    }
+                }
+            }
+        }
+        "Task" => {
+            // Subagent instructions belong in "prompt", the short summary in "description"
+            for alias in ["task", "instructions"] {
+                if let Some(prompt) = obj.remove(alias) {
+                    if !obj.contains_key("prompt") {
+                        obj.insert("prompt".to_string(), prompt);
+                        tracing::debug!("[ParamRemap] Task: {} → prompt", alias);
+                    }
+                }
+            }
+            if let Some(title) = obj.remove("title") {
+                if !obj.contains_key("description") {
+                    obj.insert("description".to_string(), title);
+                    tracing::debug!("[ParamRemap] Task: title → description");
+                }
+            }
+        }
         _ => {
             // No remapping needed for other tools
         }
@@ -98,30 +128,37 @@ pub fn remap_tool_use(name: &str, input: &mut Value) {
     remap_function_call_args(name, input);
 }
 
-/// Reverse remap function call arguments for OAI → Claude compatibility
+/// Reverse remap function call arguments back to OAI naming
 ///
-/// This is the reverse of `remap_function_call_args`. It converts OAI parameter
-/// names back to Claude's expected format.
-///
-/// Note: For tool results, we generally don't need to remap since the tool
-/// produces the result in its own format. However, this function is provided
-/// for completeness when converting from OAI format back to Claude.
+/// This is the reverse of `remap_function_call_args`. It is only applied for
+/// clients that expect symmetric parameter names, i.e. the same names they
+/// would send themselves.
 ///
 /// # Arguments
-/// * `_tool_use_id` - The tool use ID (for context, not currently used)
-/// * `_args` - The arguments object to remap (modified in place)
+/// * `tool_name` - The name of the tool being called
+/// * `args` - The arguments object to remap (modified in place)
 ///
 /// # Known Remappings (reverse)
-/// - `pattern` → `query` (for Grep, Glob responses)
-/// - `file_path` → `path` (for Read, Write, Edit responses)
-pub fn remap_tool_result_args(_tool_use_id: &str, _args: &mut Value) {
-    // Tool results generally don't need remapping since they're output from
-    // the tool, not input to it. The tool defines its own output format.
-    //
-    // However, if a client sends back modified tool results in a different
-    // format, we might need to handle that here.
-    //
-    // For now, this is a no-op placeholder for future compatibility.
+/// - `Grep`, `Glob`: `pattern` → `query`
+/// - `Read`, `Write`, `Edit`: `file_path` → `path`
+/// - `ListDir`, `LS`: `directory` → `path`
+pub fn remap_tool_result_args(tool_name: &str, args: &mut Value) {
+    let Some(obj) = args.as_object_mut() else {
+        return;
+    };
+
+    let (from, to) = match tool_name {
+        "Grep" | "Glob" => ("pattern", "query"),
+        "Read" | "Write" | "Edit" => ("file_path", "path"),
+        "ListDir" | "LS" => ("directory", "path"),
+        _ => return,
+    };
+    if let Some(value) = obj.remove(from) {
+        if !obj.contains_key(to) {
+            obj.insert(to.to_string(), value);
+            tracing::debug!("[ParamRemap] {}: {} → {}", tool_name, from, to);
+        }
+    }
 }
 
 /// Reverse remap for OAI tool_calls to Claude tool_use
@@ -206,6 +243,51 @@ mod tests {
         assert_eq!(args["file_path"], "/some/file.txt");
     }
 
+    #[test]
+    fn test_todo_write_remapping() {
+        let todos = json!([{"content": "Write tests", "status": "pending"}]);
+        let mut args = json!({ "items": todos });
+
+        remap_function_call_args("TodoWrite", &mut args);
+
+        assert!(args.get("items").is_none());
+        assert_eq!(args["todos"], todos);
+
+        // An existing todos list is never overwritten
+        let mut args = json!({ "todos": todos, "tasks": [] });
+        remap_function_call_args("TodoWrite", &mut args);
+        assert!(args.get("tasks").is_none());
+        assert_eq!(args["todos"], todos);
+    }
+
+    #[test]
+    fn test_task_remapping() {
+        let mut args = json!({
+            "title": "Find usages",
+            "instructions": "Search the repo for callers of foo"
+        });
+
+        remap_function_call_args("Task", &mut args);
+
+        assert_eq!(
+            args,
+            json!({
+                "description": "Find usages",
+                "prompt": "Search the repo for callers of foo"
+            })
+        );
+
+        // Canonical parameters win over aliases
+        let mut args = json!({
+            "description": "Find usages",
+            "prompt": "original",
+            "task": "alias"
+        });
+        remap_function_call_args("Task", &mut args);
+        assert_eq!(args["prompt"], "original");
+        assert!(args.get("task").is_none());
+    }
+
     #[test]
     fn test_no_overwrite_existing() {
         let mut args = json!({
@@ -258,14 +340,20 @@ mod tests {
 
     #[test]
     fn test_remap_tool_result_args() {
-        // Tool result remapping is currently a no-op
-        let mut args = json!({
-            "result": "success"
-        });
-        let original = args.clone();
+        let mut args = json!({"pattern": "*.rs"});
+        remap_tool_result_args("Glob", &mut args);
+        assert_eq!(args, json!({"query": "*.rs"}));
 
-        remap_tool_result_args("call_123", &mut args);
+        let mut args = json!({"file_path": "/some/file.txt"});
+        remap_tool_result_args("Read", &mut args);
+        assert_eq!(args, json!({"path": "/some/file.txt"}));
 
-        assert_eq!(args, original);
+        let mut args = json!({"directory": "/some/dir"});
+        remap_tool_result_args("LS", &mut args);
+        assert_eq!(args, json!({"path": "/some/dir"}));
+
+        let mut args = json!({"result": "success"});
+        remap_tool_result_args("UnknownTool", &mut args);
+        assert_eq!(args, json!({"result": "success"}));
     }
 }
\ No newline at end of file
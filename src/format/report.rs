@@ -0,0 +1,41 @@
+//! Conversion reporting
+//!
+//! Records what the OpenAI → Claude conversion changed or discarded for a
+//! single request, so dropped parameters are visible instead of silently lost.
+
+use serde::Serialize;
+
+/// Per-request record of what the conversion changed or could not forward
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ConversionReport {
+    /// OpenAI `frequency_penalty`, which Claude does not support
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    /// OpenAI `presence_penalty`, which Claude does not support
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    /// OpenAI `store` flag, which has no Claude equivalent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
+    /// OpenAI `logprobs` or `top_logprobs` were requested, which Claude cannot return
+    pub logprobs_ignored: bool,
+    /// Thinking was requested but dropped because of an incompatible history
+    pub thinking_disabled: bool,
+    /// Thinking blocks in the history were repaired so thinking could stay enabled
+    pub thinking_recovered: bool,
+    /// Number of `image_url` blocks converted to native image blocks
+    pub images_converted: usize,
+    /// Number of tool input schemas changed by schema cleaning
+    pub schemas_cleaned: usize,
+    /// Number of tools dropped to stay within the configured tool limit
+    pub tools_dropped: usize,
+    /// Number of tool call IDs rewritten to follow Claude's `toolu_` convention
+    pub tool_ids_rewritten: usize,
+}
+
+impl ConversionReport {
+    /// Whether the client sent sampling penalties that were dropped
+    pub fn penalties_ignored(&self) -> bool {
+        self.frequency_penalty.is_some() || self.presence_penalty.is_some()
+    }
+}
@@ -99,20 +99,77 @@ const VALID_FIELDS: &[&str] = &[
 /// // minLength is kept in description if preserve_constraints is implemented
 /// ```
 pub fn clean_json_schema(schema: &mut Value) {
-    clean_json_schema_recursive(schema);
+    clean_json_schema_recursive(schema, false);
 }
 
-fn clean_json_schema_recursive(schema: &mut Value) {
+/// Clean a JSON Schema of an OpenAI `strict: true` function
+///
+/// Same as [`clean_json_schema`], but `additionalProperties: false` is kept
+/// so the stricter semantics survive the conversion.
+///
+/// # Arguments
+/// * `schema` - The schema to clean (modified in place)
+pub fn clean_json_schema_strict(schema: &mut Value) {
+    clean_json_schema_recursive(schema, true);
+}
+
+/// Mark every property of every object schema as required
+///
+/// OpenAI strict mode treats all properties as required, so they are added
+/// to `required` before the schema is cleaned.
+///
+/// # Arguments
+/// * `schema` - The schema to process (modified in place)
+pub fn require_all_properties(schema: &mut Value) {
+    let Some(obj) = schema.as_object_mut() else {
+        return;
+    };
+
+    if let Some(props) = obj.get("properties").and_then(Value::as_object) {
+        let names: Vec<Value> = props.keys().map(|name| json!(name)).collect();
+        let required = obj.entry("required").or_insert_with(|| json!([]));
+        if let Some(required) = required.as_array_mut() {
+            for name in names {
+                if !required.contains(&name) {
+                    required.push(name);
+                }
+            }
+        }
+    }
+
+    if let Some(props) = obj.get_mut("properties").and_then(Value::as_object_mut) {
+        for prop_schema in props.values_mut() {
+            require_all_properties(prop_schema);
+        }
+    }
+    if let Some(items) = obj.get_mut("items") {
+        require_all_properties(items);
+    }
+    for key in ["anyOf", "oneOf", "allOf"] {
+        if let Some(arr) = obj.get_mut(key).and_then(Value::as_array_mut) {
+            arr.iter_mut().for_each(require_all_properties);
+        }
+    }
+}
+
+fn clean_json_schema_recursive(schema: &mut Value, strict: bool) {
     if !schema.is_object() {
         return;
     }
 
     let obj = schema.as_object_mut().unwrap();
 
-    // Remove unsupported keywords
+    // External refs can't be resolved, replace them before $ref is dropped
+    replace_external_ref(obj);
+
+    // Remove unsupported keywords, strict schemas keep closed objects closed
+    let closed = strict && obj.get("additionalProperties") == Some(&Value::Bool(false));
     for keyword in UNSUPPORTED_KEYWORDS {
         obj.remove(*keyword);
     }
+    if closed {
+        obj.insert("additionalProperties".to_string(), json!(false));
+    }
 
     // Handle type arrays: ["string", "null"] -> "string" with nullable: true
     if let Some(type_val) = obj.get("type").cloned() {
@@ -146,7 +203,7 @@ fn clean_json_schema_recursive(schema: &mut Value) {
     if let Some(props) = obj.get_mut("properties") {
         if let Some(props_obj) = props.as_object_mut() {
             for (_, prop_schema) in props_obj.iter_mut() {
-                clean_json_schema_recursive(prop_schema);
+                clean_json_schema_recursive(prop_schema, strict);
             }
         }
     }
@@ -154,10 +211,10 @@ fn clean_json_schema_recursive(schema: &mut Value) {
     // Process items (for array types)
     if let Some(items) = obj.get_mut("items") {
         if items.is_object() {
-            clean_json_schema_recursive(items);
+            clean_json_schema_recursive(items, strict);
         } else if items.is_array() {
             for item in items.as_array_mut().unwrap() {
-                clean_json_schema_recursive(item);
+                clean_json_schema_recursive(item, strict);
             }
         }
     }
@@ -167,7 +224,7 @@ fn clean_json_schema_recursive(schema: &mut Value) {
         if let Some(arr) = obj.get_mut(key) {
             if let Some(arr) = arr.as_array_mut() {
                 for item in arr.iter_mut() {
-                    clean_json_schema_recursive(item);
+                    clean_json_schema_recursive(item, strict);
                 }
             }
         }
@@ -303,6 +360,35 @@ fn move_constraints_recursive(schema: &mut Value) {
     }
 }
 
+/// Check whether a `$ref` points outside the current schema
+fn is_external_ref(ref_str: &str) -> bool {
+    !ref_str.starts_with('#')
+}
+
+/// Replace an external `$ref` with a generic object schema
+///
+/// Providers reject unresolved refs, so the ref is dropped and noted in the
+/// description instead.
+fn replace_external_ref(obj: &mut serde_json::Map<String, Value>) {
+    let Some(ref_str) = obj
+        .get("$ref")
+        .and_then(Value::as_str)
+        .filter(|r| is_external_ref(r))
+        .map(str::to_owned)
+    else {
+        return;
+    };
+    obj.remove("$ref");
+    tracing::debug!("[SchemaCleaner] Dropping external $ref: {}", ref_str);
+    obj.entry("type").or_insert_with(|| json!("object"));
+    let note = format!("Schema defined externally at {}", ref_str);
+    let description = match obj.get("description").and_then(Value::as_str) {
+        Some(existing) if !existing.is_empty() => format!("{} ({})", existing, note),
+        _ => note,
+    };
+    obj.insert("description".to_string(), json!(description));
+}
+
 /// Expand $ref references inline
 ///
 /// This function resolves $ref references within the schema and
@@ -339,6 +425,8 @@ fn expand_refs_recursive(schema: &mut Value, definitions: &Value) {
 
     let obj = schema.as_object_mut().unwrap();
 
+    replace_external_ref(obj);
+
     // Check for $ref and expand it
     if let Some(ref_path) = obj.remove("$ref") {
         if let Some(ref_str) = ref_path.as_str() {
@@ -384,6 +472,40 @@ fn expand_refs_recursive(schema: &mut Value, definitions: &Value) {
     }
 }
 
+/// Rebuild a value with the keys of every object in sorted order
+fn sort_keys(value: &Value) -> Value {
+    match value {
+        Value::Object(obj) => {
+            let mut entries: Vec<(&String, &Value)> = obj.iter().collect();
+            entries.sort_unstable_by_key(|(key, _)| *key);
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key.to_owned(), sort_keys(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(items) => Value::Array(items.iter().map(sort_keys).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Serialize a schema into a canonical string
+///
+/// Object keys are sorted at every level, so schemas that only differ in key
+/// order produce the same string. Array order is kept, as it is significant
+/// for keywords like `enum` and `required`.
+///
+/// # Arguments
+/// * `schema` - The schema to serialize
+///
+/// # Returns
+/// A compact JSON string suitable as a cache or dedup key
+pub fn canonical_schema_string(schema: &Value) -> String {
+    // Sorting explicitly keeps the output stable even if `preserve_order` is enabled
+    sort_keys(schema).to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -409,6 +531,31 @@ mod tests {
         assert!(schema.get("properties").is_some());
     }
 
+    #[test]
+    fn test_strict_schema_keeps_closed_objects() {
+        let mut schema = json!({
+            "type": "object",
+            "additionalProperties": false,
+            "properties": {
+                "city": { "type": "string" },
+                "unit": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "properties": { "name": { "type": "string" } }
+                }
+            },
+            "required": ["city"]
+        });
+
+        require_all_properties(&mut schema);
+        clean_json_schema_strict(&mut schema);
+
+        assert_eq!(schema["additionalProperties"], false);
+        assert_eq!(schema["required"], json!(["city", "unit"]));
+        assert_eq!(schema["properties"]["unit"]["additionalProperties"], false);
+        assert_eq!(schema["properties"]["unit"]["required"], json!(["name"]));
+    }
+
     #[test]
     fn test_clean_handles_type_arrays() {
         let mut schema = json!({
@@ -495,6 +642,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_external_ref_replaced() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "address": {
+                    "$ref": "https://example.com/schema.json",
+                    "description": "Postal address"
+                }
+            }
+        });
+
+        let expanded = expand_refs(&schema);
+        let address = &expanded["properties"]["address"];
+        assert!(address.get("$ref").is_none());
+        assert_eq!(address["type"], "object");
+        assert_eq!(
+            address["description"],
+            "Postal address (Schema defined externally at https://example.com/schema.json)"
+        );
+
+        let mut schema = json!({
+            "type": "object",
+            "properties": {
+                "config": { "$ref": "other.json#/Config" }
+            }
+        });
+        clean_json_schema(&mut schema);
+        let config = &schema["properties"]["config"];
+        assert!(config.get("$ref").is_none());
+        assert_eq!(config["type"], "object");
+        assert_eq!(config["description"], "Schema defined externally at other.json#/Config");
+    }
+
     #[test]
     fn test_recursive_cleaning() {
         let mut schema = json!({
@@ -518,4 +699,32 @@ mod tests {
         assert_eq!(schema["properties"]["inner"]["properties"]["deep"]["type"], "string");
         assert_eq!(schema["properties"]["inner"]["properties"]["deep"]["nullable"], true);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_canonical_schema_string_ignores_key_order() {
+        let a = json!({
+            "type": "object",
+            "properties": {
+                "city": {"type": "string", "description": "City name"},
+                "unit": {"enum": ["c", "f"], "type": "string"}
+            },
+            "required": ["city"]
+        });
+        let b = json!({
+            "required": ["city"],
+            "properties": {
+                "unit": {"type": "string", "enum": ["c", "f"]},
+                "city": {"description": "City name", "type": "string"}
+            },
+            "type": "object"
+        });
+
+        assert_eq!(canonical_schema_string(&a), canonical_schema_string(&b));
+        assert!(canonical_schema_string(&a).starts_with(r#"{"properties":{"city":"#));
+
+        // Array order is meaningful and kept
+        let mut c = a.clone();
+        c["properties"]["unit"]["enum"] = json!(["f", "c"]);
+        assert_ne!(canonical_schema_string(&a), canonical_schema_string(&c));
+    }
+}
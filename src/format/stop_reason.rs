@@ -0,0 +1,76 @@
+//! Stop reason mapping
+//!
+//! Claude reports why generation ended with a `stop_reason`, while OpenAI uses
+//! a `finish_reason` string. This module maps between the two in both
+//! directions so responses can be translated whichever side produced them.
+
+use crate::types::claude::StopReason;
+
+/// Convert a Claude stop reason to an OpenAI finish reason
+///
+/// Both `end_turn` and `stop_sequence` become `stop`, and a missing reason is
+/// treated as a normal stop.
+///
+/// # Arguments
+/// * `reason` - The Claude stop reason
+///
+/// # Returns
+/// The OpenAI `finish_reason` string
+pub fn stop_reason_to_openai(reason: Option<StopReason>) -> &'static str {
+    match reason {
+        Some(StopReason::EndTurn) | Some(StopReason::StopSequence) | None => "stop",
+        Some(StopReason::MaxTokens) => "length",
+        Some(StopReason::ToolUse) => "tool_calls",
+        Some(StopReason::Refusal) => "content_filter",
+    }
+}
+
+/// Convert an OpenAI finish reason to a Claude stop reason
+///
+/// The legacy `function_call` reason maps to `tool_use`.
+///
+/// # Arguments
+/// * `finish_reason` - The OpenAI `finish_reason` string
+///
+/// # Returns
+/// The Claude stop reason, or None if the finish reason is unknown
+pub fn openai_to_stop_reason(finish_reason: &str) -> Option<StopReason> {
+    match finish_reason {
+        "stop" => Some(StopReason::EndTurn),
+        "length" => Some(StopReason::MaxTokens),
+        "tool_calls" | "function_call" => Some(StopReason::ToolUse),
+        "content_filter" => Some(StopReason::Refusal),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for reason in [
+            StopReason::EndTurn,
+            StopReason::MaxTokens,
+            StopReason::ToolUse,
+            StopReason::Refusal,
+        ] {
+            let finish_reason = stop_reason_to_openai(Some(reason));
+            assert_eq!(openai_to_stop_reason(finish_reason), Some(reason));
+        }
+        for finish_reason in ["stop", "length", "tool_calls", "content_filter"] {
+            let reason = openai_to_stop_reason(finish_reason);
+            assert_eq!(stop_reason_to_openai(reason), finish_reason);
+        }
+    }
+
+    #[test]
+    fn test_lossy_mappings() {
+        // OpenAI has no separate reason for a matched stop sequence
+        assert_eq!(stop_reason_to_openai(Some(StopReason::StopSequence)), "stop");
+        assert_eq!(stop_reason_to_openai(None), "stop");
+        assert_eq!(openai_to_stop_reason("function_call"), Some(StopReason::ToolUse));
+        assert_eq!(openai_to_stop_reason("unknown"), None);
+    }
+}
@@ -150,6 +150,40 @@ pub fn analyze_conversation_state(messages: &[Message]) -> ConversationState {
     state
 }
 
+/// Find the tool_use IDs that never get a tool_result
+///
+/// A tool_result anywhere after the tool_use counts as a match. The tool
+/// calls of a final assistant turn are ignored, since they are legitimately
+/// still awaiting their results.
+///
+/// # Arguments
+/// * `messages` - The message history to check
+///
+/// # Returns
+/// The unmatched tool_use IDs, in conversation order
+pub fn unmatched_tool_use_ids(messages: &[Message]) -> Vec<String> {
+    let end = match messages.last() {
+        Some(last) if last.role == Role::Assistant => messages.len() - 1,
+        _ => messages.len(),
+    };
+    let mut pending: Vec<&str> = Vec::new();
+    for msg in &messages[..end] {
+        let MessageContent::Blocks { content } = &msg.content else {
+            continue;
+        };
+        for block in content {
+            match block {
+                ContentBlock::ToolUse { id, .. } => pending.push(id),
+                ContentBlock::ToolResult { tool_use_id, .. } => {
+                    pending.retain(|id| *id != tool_use_id.as_str())
+                }
+                _ => {}
+            }
+        }
+    }
+    pending.into_iter().map(str::to_owned).collect()
+}
+
 /// Check if thinking should be disabled due to message history
 ///
 /// This is necessary when:
@@ -282,6 +316,79 @@ pub fn strip_invalid_thinking_blocks(messages: &mut [Message]) {
     }
 }
 
+/// Remove thinking blocks from non-assistant messages
+///
+/// Claude only accepts `thinking` and `redacted_thinking` blocks on assistant
+/// turns, so any found on user or system messages are dropped entirely.
+///
+/// # Arguments
+/// * `messages` - The messages to process (modified in place)
+///
+/// # Returns
+/// The number of blocks removed
+pub fn strip_non_assistant_thinking(messages: &mut [Message]) -> usize {
+    let mut removed = 0;
+    for msg in messages.iter_mut() {
+        if msg.role == Role::Assistant {
+            continue;
+        }
+        let MessageContent::Blocks { content } = &mut msg.content else {
+            continue;
+        };
+        let before = content.len();
+        content.retain(|block| {
+            !matches!(
+                block,
+                ContentBlock::Thinking { .. } | ContentBlock::RedactedThinking { .. }
+            )
+        });
+        removed += before - content.len();
+    }
+    removed
+}
+
+/// Convert thinking blocks into plain text notes
+///
+/// Unlike `strip_invalid_thinking_blocks`, the reasoning text is kept. This
+/// suits the web backend, which cannot use signatures but still benefits from
+/// the reasoning in its single-prompt paste. Empty thinking blocks are dropped.
+///
+/// # Arguments
+/// * `messages` - The message history (modified in place)
+/// * `wrap` - Whether to wrap each note in `<thinking>` tags
+pub fn demote_thinking_to_text(messages: &mut [Message], wrap: bool) {
+    for msg in messages.iter_mut() {
+        let MessageContent::Blocks { content } = &mut msg.content else {
+            continue;
+        };
+        if !content.iter().any(|b| matches!(b, ContentBlock::Thinking { .. })) {
+            continue;
+        }
+        *content = std::mem::take(content)
+            .into_iter()
+            .filter_map(|block| match block {
+                ContentBlock::Thinking { thinking, .. } => {
+                    let thinking = thinking.trim();
+                    if thinking.is_empty() {
+                        return None;
+                    }
+                    let text = if wrap {
+                        format!("<thinking>{}</thinking>", thinking)
+                    } else {
+                        thinking.to_string()
+                    };
+                    Some(ContentBlock::Text {
+                        text,
+                        cache_control: None,
+                        citations: None,
+                    })
+                }
+                other => Some(other),
+            })
+            .collect();
+    }
+}
+
 /// Extract all signatures from message history
 ///
 /// # Arguments
@@ -391,6 +498,7 @@ mod tests {
             vec![ContentBlock::Text {
                 text: "hello".to_string(),
                 cache_control: None,
+                citations: None,
             }],
         );
         assert!(!message_has_tool_use(&without_tool));
@@ -448,6 +556,46 @@ mod tests {
         assert!(state.interrupted_tool);
     }
 
+    #[test]
+    fn test_unmatched_tool_use_ids() {
+        let tool_use = |id: &str| ContentBlock::ToolUse {
+            id: id.to_string(),
+            name: "test".to_string(),
+            input: json!({}),
+            signature: None,
+            cache_control: None,
+        };
+        let tool_result = |id: &str| ContentBlock::ToolResult {
+            tool_use_id: id.to_string(),
+            content: json!("result"),
+            is_error: None,
+            cache_control: None,
+        };
+
+        // Every call answered
+        let matched = vec![
+            create_text_message(Role::User, "hello"),
+            create_blocks_message(Role::Assistant, vec![tool_use("a"), tool_use("b")]),
+            create_blocks_message(Role::User, vec![tool_result("b"), tool_result("a")]),
+        ];
+        assert!(unmatched_tool_use_ids(&matched).is_empty());
+
+        // The final assistant turn is still awaiting its results
+        let mut last_turn = matched.clone();
+        last_turn.push(create_blocks_message(Role::Assistant, vec![tool_use("c")]));
+        assert!(unmatched_tool_use_ids(&last_turn).is_empty());
+
+        // "a" was never answered, though the conversation moved on
+        let orphaned = vec![
+            create_text_message(Role::User, "hello"),
+            create_blocks_message(Role::Assistant, vec![tool_use("a"), tool_use("b")]),
+            create_blocks_message(Role::User, vec![tool_result("b")]),
+            create_text_message(Role::Assistant, "done"),
+            create_text_message(Role::User, "thanks"),
+        ];
+        assert_eq!(unmatched_tool_use_ids(&orphaned), vec!["a".to_string()]);
+    }
+
     #[test]
     fn test_should_disable_thinking_due_to_history() {
         // Tool use without thinking -> should disable
@@ -513,6 +661,45 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_demote_thinking_to_text() {
+        let blocks = || {
+            vec![
+                ContentBlock::Thinking {
+                    thinking: " step one ".to_string(),
+                    signature: Some("short".to_string()),
+                    cache_control: None,
+                },
+                ContentBlock::Thinking {
+                    thinking: "  ".to_string(),
+                    signature: None,
+                    cache_control: None,
+                },
+                ContentBlock::text("answer"),
+            ]
+        };
+        let texts = |messages: &[Message]| -> Vec<String> {
+            match &messages[0].content {
+                MessageContent::Blocks { content } => content
+                    .iter()
+                    .map(|b| match b {
+                        ContentBlock::Text { text, .. } => text.clone(),
+                        other => panic!("Expected only text blocks, got {:?}", other),
+                    })
+                    .collect(),
+                _ => panic!("Expected blocks content"),
+            }
+        };
+
+        let mut wrapped = vec![create_blocks_message(Role::Assistant, blocks())];
+        demote_thinking_to_text(&mut wrapped, true);
+        assert_eq!(texts(&wrapped), ["<thinking>step one</thinking>", "answer"]);
+
+        let mut plain = vec![create_blocks_message(Role::Assistant, blocks())];
+        demote_thinking_to_text(&mut plain, false);
+        assert_eq!(texts(&plain), ["step one", "answer"]);
+    }
+
     #[test]
     fn test_strip_invalid_thinking_blocks() {
         let mut messages = vec![create_blocks_message(
@@ -531,6 +718,7 @@ mod tests {
                 ContentBlock::Text {
                     text: "hello".to_string(),
                     cache_control: None,
+                    citations: None,
                 },
             ],
         )];
@@ -546,6 +734,40 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_strip_non_assistant_thinking() {
+        let thinking = || ContentBlock::Thinking {
+            thinking: "reasoning".to_string(),
+            signature: Some("valid_signature_12345".to_string()),
+            cache_control: None,
+        };
+        let mut messages = vec![
+            create_blocks_message(
+                Role::User,
+                vec![
+                    thinking(),
+                    ContentBlock::RedactedThinking {
+                        data: "opaque".to_string(),
+                    },
+                    ContentBlock::Text {
+                        text: "question".to_string(),
+                        cache_control: None,
+                        citations: None,
+                    },
+                ],
+            ),
+            create_blocks_message(Role::Assistant, vec![thinking()]),
+        ];
+
+        assert_eq!(strip_non_assistant_thinking(&mut messages), 2);
+        let MessageContent::Blocks { content } = &messages[0].content else {
+            panic!("Expected Blocks content");
+        };
+        assert!(matches!(content.as_slice(), [ContentBlock::Text { .. }]));
+        // Assistant thinking is left alone
+        assert!(message_has_valid_thinking(&messages[1]));
+    }
+
     #[test]
     fn test_extract_signatures() {
         let messages = vec![
@@ -0,0 +1,257 @@
+//! Tool call ID normalization
+//!
+//! OpenAI clients generate their own tool call IDs (e.g. `call_abc123`), while
+//! some Claude providers only accept IDs following the `toolu_` convention.
+//! This module rewrites non-conforming IDs through a per-request map shared
+//! by tool_use and tool_result blocks, so they stay paired across the
+//! conversation.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::types::claude::{ContentBlock, Message, MessageContent};
+
+/// Prefix of Claude-style tool use IDs
+pub const CLAUDE_TOOL_ID_PREFIX: &str = "toolu_";
+
+/// Check if an ID already follows Claude's `toolu_` convention
+pub fn is_claude_tool_id(id: &str) -> bool {
+    id.strip_prefix(CLAUDE_TOOL_ID_PREFIX).is_some_and(|rest| {
+        !rest.is_empty()
+            && rest
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    })
+}
+
+/// Sanitize a tool ID to match Claude API's required pattern: ^[a-zA-Z0-9_-]+$
+/// Replaces any invalid characters with underscores.
+pub fn sanitize_tool_id(id: &str) -> String {
+    if id.is_empty() {
+        return id.to_string();
+    }
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Get the Claude-style form of a client tool call ID
+///
+/// Conforming IDs are kept as-is, others are sanitized and prefixed. Distinct
+/// IDs may share a form (e.g. `call.1` and `call_1`), so [`ToolIdMap`] makes
+/// them unique within a request.
+pub fn claude_tool_id(id: &str) -> String {
+    if is_claude_tool_id(id) {
+        id.to_string()
+    } else {
+        format!("{CLAUDE_TOOL_ID_PREFIX}{}", sanitize_tool_id(id))
+    }
+}
+
+/// Per-request mapping from client tool call IDs to unique Claude-style IDs
+///
+/// Every tool_use ID gets its own rewritten ID, with a numeric suffix added on
+/// collision. tool_result blocks are rewritten through the same map, so each
+/// result stays paired with its call.
+#[derive(Debug, Default)]
+pub struct ToolIdMap {
+    ids: HashMap<String, String>,
+}
+
+impl ToolIdMap {
+    /// Build the map from the tool_use IDs of a request, in conversation order
+    ///
+    /// Conforming IDs are reserved first, so a rewritten ID never takes the
+    /// place of one the client already uses.
+    pub fn new<'a>(tool_use_ids: impl IntoIterator<Item = &'a str>) -> Self {
+        let tool_use_ids: Vec<&str> = tool_use_ids.into_iter().collect();
+        let mut used: HashSet<String> = tool_use_ids
+            .iter()
+            .filter(|id| is_claude_tool_id(id))
+            .map(|id| id.to_string())
+            .collect();
+        let mut ids = HashMap::new();
+        for &id in &tool_use_ids {
+            if is_claude_tool_id(id) || ids.contains_key(id) {
+                continue;
+            }
+            let base = claude_tool_id(id);
+            let mut rewritten = base.clone();
+            let mut suffix = 2;
+            while !used.insert(rewritten.clone()) {
+                rewritten = format!("{base}_{suffix}");
+                suffix += 1;
+            }
+            ids.insert(id.to_string(), rewritten);
+        }
+        Self { ids }
+    }
+
+    /// Get the rewritten ID for a client ID, or None if it is kept as-is
+    pub fn get(&self, id: &str) -> Option<&str> {
+        self.ids.get(id).map(String::as_str)
+    }
+
+    /// Number of tool_use IDs that are rewritten
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Whether every tool_use ID is kept as-is
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// Rewrite tool_use and tool_result IDs in place to follow Claude's convention
+///
+/// # Arguments
+/// * `messages` - The messages to process
+///
+/// # Returns
+/// The number of tool calls whose ID was rewritten
+pub fn normalize_tool_ids(messages: &mut [Message]) -> usize {
+    let tool_use_ids = messages
+        .iter()
+        .filter_map(|msg| match &msg.content {
+            MessageContent::Blocks { content } => Some(content),
+            MessageContent::Text { .. } => None,
+        })
+        .flatten()
+        .filter_map(|block| match block {
+            ContentBlock::ToolUse { id, .. } => Some(id.as_str()),
+            _ => None,
+        });
+    let map = ToolIdMap::new(tool_use_ids);
+    for msg in messages.iter_mut() {
+        let MessageContent::Blocks { content } = &mut msg.content else {
+            continue;
+        };
+        for block in content.iter_mut() {
+            match block {
+                ContentBlock::ToolUse { id, .. } => {
+                    if let Some(rewritten) = map.get(id) {
+                        *id = rewritten.to_string();
+                    }
+                }
+                ContentBlock::ToolResult { tool_use_id, .. } => {
+                    if let Some(rewritten) = map.get(tool_use_id) {
+                        *tool_use_id = rewritten.to_string();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    map.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::claude::Role;
+    use serde_json::json;
+
+    fn tool_use(id: &str) -> ContentBlock {
+        ContentBlock::ToolUse {
+            id: id.to_string(),
+            name: "get_weather".to_string(),
+            input: json!({}),
+            signature: None,
+            cache_control: None,
+        }
+    }
+
+    fn tool_result(id: &str) -> ContentBlock {
+        ContentBlock::ToolResult {
+            tool_use_id: id.to_string(),
+            content: json!("sunny"),
+            is_error: None,
+            cache_control: None,
+        }
+    }
+
+    #[test]
+    fn test_sanitize_tool_id() {
+        // Normal ID should pass through
+        assert_eq!(sanitize_tool_id("call_123"), "call_123");
+        // Dots should be replaced with underscores
+        assert_eq!(sanitize_tool_id("call_123.456"), "call_123_456");
+        // Mixed invalid chars
+        assert_eq!(sanitize_tool_id("call.123@foo"), "call_123_foo");
+        // Empty string
+        assert_eq!(sanitize_tool_id(""), "");
+        // Already valid with hyphens
+        assert_eq!(sanitize_tool_id("call-123_abc"), "call-123_abc");
+    }
+
+    #[test]
+    fn test_rewrites_non_conforming_ids() {
+        assert_eq!(claude_tool_id("toolu_01abc"), "toolu_01abc");
+        assert_eq!(claude_tool_id("call.123"), "toolu_call_123");
+        assert_eq!(claude_tool_id("call_123"), "toolu_call_123");
+    }
+
+    #[test]
+    fn test_colliding_ids_stay_unique() {
+        let mut messages = vec![
+            Message::new_blocks(
+                Role::Assistant,
+                vec![tool_use("call.1"), tool_use("call_1"), tool_use("toolu_call_1_2")],
+            ),
+            Message::new_blocks(
+                Role::User,
+                vec![
+                    tool_result("call_1"),
+                    tool_result("toolu_call_1_2"),
+                    tool_result("call.1"),
+                ],
+            ),
+        ];
+
+        assert_eq!(normalize_tool_ids(&mut messages), 2);
+
+        let ids = |msg: &Message| match &msg.content {
+            MessageContent::Blocks { content } => content
+                .iter()
+                .filter_map(|b| match b {
+                    ContentBlock::ToolUse { id, .. } => Some(id.to_owned()),
+                    ContentBlock::ToolResult { tool_use_id, .. } => Some(tool_use_id.to_owned()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            MessageContent::Text { .. } => vec![],
+        };
+        assert_eq!(ids(&messages[0]), vec!["toolu_call_1", "toolu_call_1_3", "toolu_call_1_2"]);
+        assert_eq!(ids(&messages[1]), vec!["toolu_call_1_3", "toolu_call_1_2", "toolu_call_1"]);
+    }
+
+    #[test]
+    fn test_preserves_tool_result_pairing() {
+        let mut messages = vec![
+            Message::new_blocks(Role::Assistant, vec![tool_use("call_a"), tool_use("toolu_b")]),
+            Message::new_blocks(Role::User, vec![tool_result("call_a"), tool_result("toolu_b")]),
+        ];
+
+        assert_eq!(normalize_tool_ids(&mut messages), 1);
+
+        let ids = |msg: &Message| match &msg.content {
+            MessageContent::Blocks { content } => content
+                .iter()
+                .filter_map(|b| match b {
+                    ContentBlock::ToolUse { id, .. } => Some(id.to_owned()),
+                    ContentBlock::ToolResult { tool_use_id, .. } => Some(tool_use_id.to_owned()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            MessageContent::Text { .. } => vec![],
+        };
+        assert_eq!(ids(&messages[0]), vec!["toolu_call_a", "toolu_b"]);
+        assert_eq!(ids(&messages[0]), ids(&messages[1]));
+    }
+}
@@ -0,0 +1,382 @@
+//! Tool result utilities
+//!
+//! This module provides helpers for reshaping tool_result content before it is
+//! forwarded upstream, such as splitting oversized tool outputs into several
+//! text parts so that no single part exceeds provider limits, unwrapping
+//! tool outputs that were JSON-encoded by the client, or eliding old tool
+//! outputs in long tool loops.
+
+use serde_json::{Value, json};
+
+use crate::types::claude::{ContentBlock, Message, MessageContent};
+
+/// Maximum size in bytes of a single text part inside a tool_result
+pub const MAX_TOOL_RESULT_PART_BYTES: usize = 64 * 1024;
+
+/// Split a string into chunks of at most `max_bytes`, respecting char boundaries
+fn chunk_text(text: &str, max_bytes: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while rest.len() > max_bytes {
+        let mut end = max_bytes;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            // A single character wider than the limit, keep it whole
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (head, tail) = rest.split_at(end);
+        chunks.push(head);
+        rest = tail;
+    }
+    chunks.push(rest);
+    chunks
+}
+
+fn text_parts(text: &str, max_bytes: usize) -> Vec<Value> {
+    chunk_text(text, max_bytes)
+        .into_iter()
+        .map(|chunk| json!({ "type": "text", "text": chunk }))
+        .collect()
+}
+
+/// Split tool_result content into text parts no larger than `max_bytes`
+///
+/// String content becomes one or more text parts. Array content keeps its
+/// non-text parts as-is and splits every oversized text part in place. Any
+/// other value is returned unchanged as a single element.
+///
+/// # Arguments
+/// * `content` - The tool_result content value
+/// * `max_bytes` - Maximum size of a single text part
+///
+/// # Returns
+/// The content parts, in their original order
+pub fn split_tool_result_content(content: Value, max_bytes: usize) -> Vec<Value> {
+    let max_bytes = max_bytes.max(1);
+    match content {
+        Value::String(text) => text_parts(&text, max_bytes),
+        Value::Array(parts) => parts
+            .into_iter()
+            .flat_map(|part| match part.get("text").and_then(Value::as_str) {
+                Some(text)
+                    if part.get("type").and_then(Value::as_str) == Some("text")
+                        && text.len() > max_bytes =>
+                {
+                    text_parts(text, max_bytes)
+                }
+                _ => vec![part],
+            })
+            .collect(),
+        other => vec![other],
+    }
+}
+
+/// Placeholder that replaces the content of elided tool results
+pub const ELIDED_TOOL_RESULT: &str = "[older tool result elided]";
+
+/// Replace the content of all but the last `keep_last` tool results
+///
+/// The tool_result blocks themselves are kept, so every tool_use stays paired
+/// with its result.
+///
+/// # Arguments
+/// * `messages` - The messages to process
+/// * `keep_last` - Number of most recent tool results left untouched
+///
+/// # Returns
+/// The number of tool results that were elided
+pub fn summarize_old_tool_results(messages: &mut [Message], keep_last: usize) -> usize {
+    let mut results: Vec<&mut Value> = messages
+        .iter_mut()
+        .filter_map(|msg| match &mut msg.content {
+            MessageContent::Blocks { content } => Some(content),
+            MessageContent::Text { .. } => None,
+        })
+        .flatten()
+        .filter_map(|block| match block {
+            ContentBlock::ToolResult { content, .. } => Some(content),
+            _ => None,
+        })
+        .collect();
+    let old = results.len().saturating_sub(keep_last);
+    let mut elided = 0;
+    for content in results.iter_mut().take(old) {
+        if content.as_str() != Some(ELIDED_TOOL_RESULT) {
+            **content = json!(ELIDED_TOOL_RESULT);
+            elided += 1;
+        }
+    }
+    elided
+}
+
+/// Maximum number of JSON string layers unwrapped from a tool result
+const MAX_JSON_DECODE_DEPTH: usize = 4;
+
+/// Check if a value is an array of content blocks
+fn is_content_blocks(value: &Value) -> bool {
+    value.as_array().is_some_and(|parts| {
+        !parts.is_empty() && parts.iter().all(|part| part["type"].is_string())
+    })
+}
+
+/// Decode tool_result text that holds JSON
+///
+/// JSON strings are unwrapped layer by layer, and an array of content blocks
+/// is returned as blocks. The innermost text is kept verbatim, so tool output
+/// that is an object or array is never re-serialized. Text that is not a
+/// JSON-encoded string or blocks array is left alone.
+///
+/// # Arguments
+/// * `text` - The tool_result text
+///
+/// # Returns
+/// The decoded content, or None if the text was not encoded
+pub fn decode_json_tool_content(text: &str) -> Option<Value> {
+    let mut value: Value = serde_json::from_str(text).ok()?;
+    let mut decoded = None;
+    for _ in 0..MAX_JSON_DECODE_DEPTH {
+        let Value::String(inner) = value else {
+            break;
+        };
+        match serde_json::from_str::<Value>(&inner) {
+            Ok(next) => {
+                decoded = Some(inner);
+                value = next;
+            }
+            Err(_) => return Some(Value::String(inner)),
+        }
+    }
+    match value {
+        Value::String(_) => Some(value),
+        _ if is_content_blocks(&value) => Some(value),
+        _ => decoded.map(Value::String),
+    }
+}
+
+/// Decode every tool_result in the conversation whose text is JSON
+///
+/// # Arguments
+/// * `messages` - The messages to process
+///
+/// # Returns
+/// The number of tool_result blocks that were changed
+pub fn decode_json_tool_results(messages: &mut [Message]) -> usize {
+    let mut decoded = 0;
+    for msg in messages.iter_mut() {
+        let MessageContent::Blocks { content } = &mut msg.content else {
+            continue;
+        };
+        for block in content.iter_mut() {
+            if let ContentBlock::ToolResult { content, .. } = block
+                && let Some(value) = content.as_str().and_then(decode_json_tool_content)
+                && value != *content
+            {
+                *content = value;
+                decoded += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Check whether tool_result content holds text larger than `max_bytes`
+fn has_oversized_text(content: &Value, max_bytes: usize) -> bool {
+    match content {
+        Value::String(text) => text.len() > max_bytes,
+        Value::Array(parts) => parts.iter().any(|part| {
+            part.get("text")
+                .and_then(Value::as_str)
+                .is_some_and(|text| text.len() > max_bytes)
+        }),
+        _ => false,
+    }
+}
+
+/// Split every oversized tool_result in the conversation into multiple text parts
+///
+/// Tool results that are already small enough are left untouched.
+///
+/// # Arguments
+/// * `messages` - The messages to process
+/// * `max_bytes` - Maximum size of a single text part
+///
+/// # Returns
+/// The number of tool_result blocks that were split
+pub fn split_oversized_tool_results(messages: &mut [Message], max_bytes: usize) -> usize {
+    let mut split = 0;
+    for msg in messages.iter_mut() {
+        let MessageContent::Blocks { content } = &mut msg.content else {
+            continue;
+        };
+        for block in content.iter_mut() {
+            if let ContentBlock::ToolResult { content, .. } = block
+                && has_oversized_text(content, max_bytes)
+            {
+                let parts = split_tool_result_content(content.take(), max_bytes);
+                *content = Value::Array(parts);
+                split += 1;
+            }
+        }
+    }
+    split
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::claude::Role;
+
+    #[test]
+    fn test_split_large_string_into_parts() {
+        let text = "a".repeat(25);
+        let parts = split_tool_result_content(Value::String(text.clone()), 10);
+
+        assert_eq!(parts.len(), 3);
+        let joined: String = parts
+            .iter()
+            .map(|p| {
+                assert_eq!(p["type"], "text");
+                p["text"].as_str().unwrap()
+            })
+            .collect();
+        assert_eq!(joined, text);
+        assert!(parts.iter().all(|p| p["text"].as_str().unwrap().len() <= 10));
+    }
+
+    #[test]
+    fn test_split_respects_char_boundaries() {
+        let text = "é".repeat(5);
+        let parts = split_tool_result_content(Value::String(text.clone()), 3);
+
+        let joined: String = parts.iter().map(|p| p["text"].as_str().unwrap()).collect();
+        assert_eq!(joined, text);
+        assert_eq!(parts.len(), 5);
+    }
+
+    #[test]
+    fn test_split_array_keeps_non_text_parts() {
+        let content = json!([
+            {"type": "text", "text": "x".repeat(15)},
+            {"type": "image", "source": {"type": "url", "url": "https://example.com/a.png"}}
+        ]);
+        let parts = split_tool_result_content(content, 10);
+
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[2]["type"], "image");
+    }
+
+    #[test]
+    fn test_summarize_old_tool_results() {
+        let tool_result = |id: &str, output: &str| ContentBlock::ToolResult {
+            tool_use_id: id.to_string(),
+            content: json!(output),
+            is_error: None,
+            cache_control: None,
+        };
+        let mut messages = vec![
+            Message::new_blocks(Role::User, vec![tool_result("toolu_1", "first")]),
+            Message::new_text(Role::Assistant, "ok"),
+            Message::new_blocks(
+                Role::User,
+                vec![tool_result("toolu_2", "second"), tool_result("toolu_3", "third")],
+            ),
+        ];
+
+        assert_eq!(summarize_old_tool_results(&mut messages, 2), 1);
+        let results: Vec<(String, Value)> = messages
+            .iter()
+            .filter_map(|m| match &m.content {
+                MessageContent::Blocks { content } => Some(content),
+                MessageContent::Text { .. } => None,
+            })
+            .flatten()
+            .filter_map(|b| match b {
+                ContentBlock::ToolResult {
+                    tool_use_id,
+                    content,
+                    ..
+                } => Some((tool_use_id.to_owned(), content.to_owned())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                ("toolu_1".to_string(), json!(ELIDED_TOOL_RESULT)),
+                ("toolu_2".to_string(), json!("second")),
+                ("toolu_3".to_string(), json!("third")),
+            ]
+        );
+
+        // Already elided results are not counted again
+        assert_eq!(summarize_old_tool_results(&mut messages, 2), 0);
+    }
+
+    #[test]
+    fn test_decode_double_encoded_json() {
+        // The tool output was serialized twice by the client
+        let text = serde_json::to_string(r#"{"a": 1, "b": [true]}"#).unwrap();
+        assert_eq!(decode_json_tool_content(&text), Some(json!(r#"{"a": 1, "b": [true]}"#)));
+
+        // Output that was never double-encoded is kept as sent
+        assert_eq!(decode_json_tool_content(r#"{"z": 1, "a": 12345678901234567890123}"#), None);
+        assert_eq!(decode_json_tool_content("[1, 2]"), None);
+
+        let blocks = r#"[{"type": "text", "text": "done"}]"#;
+        assert_eq!(
+            decode_json_tool_content(blocks),
+            Some(json!([{"type": "text", "text": "done"}]))
+        );
+        assert_eq!(decode_json_tool_content("plain output"), None);
+
+        let mut messages = vec![Message::new_blocks(
+            Role::User,
+            vec![ContentBlock::ToolResult {
+                tool_use_id: "toolu_1".to_string(),
+                content: Value::String(text),
+                is_error: None,
+                cache_control: None,
+            }],
+        )];
+        assert_eq!(decode_json_tool_results(&mut messages), 1);
+        assert_eq!(decode_json_tool_results(&mut messages), 0);
+    }
+
+    #[test]
+    fn test_split_oversized_tool_results_in_messages() {
+        let mut messages = vec![Message::new_blocks(
+            Role::User,
+            vec![
+                ContentBlock::ToolResult {
+                    tool_use_id: "toolu_1".to_string(),
+                    content: Value::String("y".repeat(30)),
+                    is_error: None,
+                    cache_control: None,
+                },
+                ContentBlock::ToolResult {
+                    tool_use_id: "toolu_2".to_string(),
+                    content: Value::String("small".to_string()),
+                    is_error: None,
+                    cache_control: None,
+                },
+            ],
+        )];
+
+        assert_eq!(split_oversized_tool_results(&mut messages, 10), 1);
+        let MessageContent::Blocks { content } = &messages[0].content else {
+            panic!("expected blocks");
+        };
+        match &content[0] {
+            ContentBlock::ToolResult { content, .. } => {
+                assert_eq!(content.as_array().unwrap().len(), 3)
+            }
+            other => panic!("unexpected block: {other:?}"),
+        }
+        match &content[1] {
+            ContentBlock::ToolResult { content, .. } => assert_eq!(content, "small"),
+            other => panic!("unexpected block: {other:?}"),
+        }
+    }
+}
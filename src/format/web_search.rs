@@ -7,6 +7,8 @@
 //! - claude-code-router/packages/core/src/transformer/anthropic.transformer.ts
 //! - Antigravity-Manager/src-tauri/src/proxy/mappers/claude/response.rs
 
+use std::borrow::Cow;
+
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 
@@ -25,6 +27,9 @@ pub struct Citation {
     /// End index in the text where this citation applies
     #[serde(skip_serializing_if = "Option::is_none")]
     pub end_index: Option<usize>,
+    /// How old the source page is, e.g. "2 days ago"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_age: Option<String>,
 }
 
 /// Web search result from Claude's API
@@ -45,6 +50,43 @@ pub struct WebSearchResult {
     pub page_age: Option<String>,
 }
 
+/// Collapse whitespace runs in a snippet to single spaces and trim it
+///
+/// # Arguments
+/// * `snippet` - The raw snippet text
+///
+/// # Returns
+/// The snippet on a single line
+pub fn normalize_snippet(snippet: &str) -> String {
+    snippet.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Truncate a snippet to at most `max_chars` characters
+///
+/// The cut always falls on a char boundary and is marked with "...".
+///
+/// # Arguments
+/// * `snippet` - The snippet text
+/// * `max_chars` - Maximum number of characters kept
+///
+/// # Returns
+/// The snippet, borrowed if it was short enough
+pub fn truncate_snippet(snippet: &str, max_chars: usize) -> Cow<'_, str> {
+    match snippet.char_indices().nth(max_chars) {
+        Some((end, _)) => Cow::Owned(format!("{}...", &snippet[..end])),
+        None => Cow::Borrowed(snippet),
+    }
+}
+
+/// Read a non-empty `page_age` from a search result item
+fn page_age(item: &Value) -> Option<String> {
+    item.get("page_age")
+        .and_then(|v| v.as_str())
+        .map(str::trim)
+        .filter(|age| !age.is_empty())
+        .map(str::to_string)
+}
+
 /// Extract citations from web_search_tool_result data
 ///
 /// Parses Claude's web search result format and extracts individual citations.
@@ -69,14 +111,16 @@ pub fn extract_citations_from_tool_result(data: &Value) -> Vec<Citation> {
                     citations.push(Citation {
                         url: url.to_string(),
                         title: title.to_string(),
-                        snippet: item
-                            .get("snippet")
-                            .or_else(|| item.get("encrypted_content"))
-                            .and_then(|v| v.as_str())
-                            .unwrap_or("")
-                            .to_string(),
+                        snippet: normalize_snippet(
+                            item
+                                .get("snippet")
+                                .or_else(|| item.get("encrypted_content"))
+                                .and_then(|v| v.as_str())
+                                .unwrap_or(""),
+                        ),
                         start_index: None,
                         end_index: None,
+                        page_age: page_age(item),
                     });
                 }
             }
@@ -94,13 +138,12 @@ pub fn extract_citations_from_tool_result(data: &Value) -> Vec<Citation> {
                 citations.push(Citation {
                     url: url.to_string(),
                     title: title.to_string(),
-                    snippet: result
-                        .get("snippet")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .to_string(),
+                    snippet: normalize_snippet(
+                        result.get("snippet").and_then(|v| v.as_str()).unwrap_or(""),
+                    ),
                     start_index: None,
                     end_index: None,
+                    page_age: page_age(result),
                 });
             }
         }
@@ -139,9 +182,10 @@ pub fn extract_citations_from_search_result(data: &Value) -> Vec<Citation> {
             citations.push(Citation {
                 url: url.to_string(),
                 title: title.to_string(),
-                snippet: content,
+                snippet: normalize_snippet(&content),
                 start_index: None,
                 end_index: None,
+                page_age: None,
             });
         }
     }
@@ -149,14 +193,52 @@ pub fn extract_citations_from_search_result(data: &Value) -> Vec<Citation> {
     citations
 }
 
+/// Extract citations from the `citations` array of a text block
+///
+/// Only locations that point to a URL (`web_search_result_location` and
+/// `search_result_location`) are kept; document locations have no link.
+///
+/// # Arguments
+/// * `citations` - The raw citation objects from the text block
+///
+/// # Returns
+/// Vector of extracted citations
+pub fn extract_citations_from_text_citations(citations: &[Value]) -> Vec<Citation> {
+    citations
+        .iter()
+        .filter_map(|item| {
+            let url = item
+                .get("url")
+                .or_else(|| item.get("source"))
+                .and_then(|v| v.as_str())?;
+            let title = item
+                .get("title")
+                .or_else(|| item.get("document_title"))
+                .and_then(|v| v.as_str())
+                .unwrap_or(url);
+            Some(Citation {
+                url: url.to_string(),
+                title: title.to_string(),
+                snippet: normalize_snippet(
+                    item.get("cited_text").and_then(|v| v.as_str()).unwrap_or(""),
+                ),
+                start_index: None,
+                end_index: None,
+                page_age: None,
+            })
+        })
+        .collect()
+}
+
 /// Convert citations to OpenAI annotations format
 ///
 /// # Arguments
 /// * `citations` - The citations to convert
+/// * `max_snippet_chars` - Maximum characters of each annotation's content
 ///
 /// # Returns
 /// Vector of JSON values in OpenAI annotation format
-pub fn citations_to_annotations(citations: &[Citation]) -> Vec<Value> {
+pub fn citations_to_annotations(citations: &[Citation], max_snippet_chars: usize) -> Vec<Value> {
     citations
         .iter()
         .map(|c| {
@@ -165,7 +247,7 @@ pub fn citations_to_annotations(citations: &[Citation]) -> Vec<Value> {
                 "url_citation": {
                     "url": c.url,
                     "title": c.title,
-                    "content": c.snippet,
+                    "content": truncate_snippet(&c.snippet, max_snippet_chars),
                     "start_index": c.start_index.unwrap_or(0),
                     "end_index": c.end_index.unwrap_or(0)
                 }
@@ -207,10 +289,15 @@ pub fn annotations_to_web_search_content(annotations: &[Value]) -> Vec<Value> {
 /// # Arguments
 /// * `citations` - The citations to format
 /// * `search_query` - Optional search query to display
+/// * `max_snippet_chars` - Maximum characters of each displayed snippet
 ///
 /// # Returns
 /// Formatted Markdown string
-pub fn format_citations_as_markdown(citations: &[Citation], search_query: Option<&str>) -> String {
+pub fn format_citations_as_markdown(
+    citations: &[Citation],
+    search_query: Option<&str>,
+    max_snippet_chars: usize,
+) -> String {
     if citations.is_empty() {
         return String::new();
     }
@@ -226,19 +313,13 @@ pub fn format_citations_as_markdown(citations: &[Citation], search_query: Option
     md.push_str("**📚 来源：**\n");
 
     for (i, citation) in citations.iter().enumerate() {
-        md.push_str(&format!(
-            "{}. [{}]({})\n",
-            i + 1,
-            citation.title,
-            citation.url
-        ));
+        md.push_str(&format!("{}. [{}]({})", i + 1, citation.title, citation.url));
+        if let Some(age) = &citation.page_age {
+            md.push_str(&format!(" ({})", age));
+        }
+        md.push('\n');
         if !citation.snippet.is_empty() {
-            // Truncate long snippets
-            let snippet = if citation.snippet.len() > 200 {
-                format!("{}...", &citation.snippet[..200])
-            } else {
-                citation.snippet.clone()
-            };
+            let snippet = truncate_snippet(&citation.snippet, max_snippet_chars);
             md.push_str(&format!("   > {}\n", snippet.replace('\n', " ")));
         }
     }
@@ -254,6 +335,7 @@ pub fn format_citations_as_markdown(citations: &[Citation], search_query: Option
 /// * `text` - The original response text
 /// * `citations` - The citations to append
 /// * `search_query` - Optional search query
+/// * `max_snippet_chars` - Maximum characters of each displayed snippet
 ///
 /// # Returns
 /// Text with appended citations
@@ -261,12 +343,13 @@ pub fn merge_citations_into_text(
     text: &str,
     citations: &[Citation],
     search_query: Option<&str>,
+    max_snippet_chars: usize,
 ) -> String {
     if citations.is_empty() {
         return text.to_string();
     }
 
-    let md = format_citations_as_markdown(citations, search_query);
+    let md = format_citations_as_markdown(citations, search_query, max_snippet_chars);
     format!("{}{}", text, md)
 }
 
@@ -302,6 +385,43 @@ mod tests {
         assert_eq!(citations[1].snippet, "Encrypted content here");
     }
 
+    #[test]
+    fn test_page_age_in_citations() {
+        let data = json!({
+            "content": [{
+                "type": "web_search_result",
+                "url": "https://example.com",
+                "title": "Example Site",
+                "page_age": "2 days ago"
+            }]
+        });
+
+        let citations = extract_citations_from_tool_result(&data);
+        assert_eq!(citations[0].page_age.as_deref(), Some("2 days ago"));
+
+        let md = format_citations_as_markdown(&citations, None, 200);
+        assert!(md.contains("1. [Example Site](https://example.com) (2 days ago)\n"));
+    }
+
+    #[test]
+    fn test_normalize_snippet() {
+        assert_eq!(
+            normalize_snippet("  Rust\n\n  is   a\tsystems \r\n language  "),
+            "Rust is a systems language"
+        );
+        assert_eq!(normalize_snippet(" \n\t "), "");
+
+        let data = json!({
+            "results": [{
+                "url": "https://example.com",
+                "title": "Example",
+                "snippet": "line one\n\n   line two"
+            }]
+        });
+        let citations = extract_citations_from_tool_result(&data);
+        assert_eq!(citations[0].snippet, "line one line two");
+    }
+
     #[test]
     fn test_extract_citations_from_results_array() {
         let data = json!({
@@ -339,6 +459,32 @@ mod tests {
         assert!(citations[0].snippet.contains("Second paragraph"));
     }
 
+    #[test]
+    fn test_extract_citations_from_text_citations() {
+        let citations = vec![
+            json!({
+                "type": "web_search_result_location",
+                "url": "https://example.com",
+                "title": "Example Site",
+                "cited_text": "Rust is\n fast",
+                "encrypted_index": "abc"
+            }),
+            json!({
+                "type": "char_location",
+                "document_title": "Notes",
+                "cited_text": "no link",
+                "start_char_index": 0,
+                "end_char_index": 7
+            }),
+        ];
+
+        let extracted = extract_citations_from_text_citations(&citations);
+        assert_eq!(extracted.len(), 1);
+        assert_eq!(extracted[0].url, "https://example.com");
+        assert_eq!(extracted[0].title, "Example Site");
+        assert_eq!(extracted[0].snippet, "Rust is fast");
+    }
+
     #[test]
     fn test_citations_to_annotations() {
         let citations = vec![Citation {
@@ -347,9 +493,10 @@ mod tests {
             snippet: "Snippet".to_string(),
             start_index: Some(10),
             end_index: Some(20),
+            page_age: None,
         }];
 
-        let annotations = citations_to_annotations(&citations);
+        let annotations = citations_to_annotations(&citations, 1000);
         assert_eq!(annotations.len(), 1);
         assert_eq!(annotations[0]["type"], "url_citation");
         assert_eq!(
@@ -359,6 +506,28 @@ mod tests {
         assert_eq!(annotations[0]["url_citation"]["start_index"], 10);
     }
 
+    #[test]
+    fn test_snippet_limits() {
+        let citations = vec![Citation {
+            url: "https://example.com".to_string(),
+            title: "Example".to_string(),
+            snippet: "ééééé".to_string(),
+            start_index: None,
+            end_index: None,
+            page_age: None,
+        }];
+
+        let md = format_citations_as_markdown(&citations, None, 3);
+        assert!(md.contains("   > ééé...\n"));
+        let md = format_citations_as_markdown(&citations, None, 5);
+        assert!(md.contains("   > ééééé\n"));
+
+        let annotations = citations_to_annotations(&citations, 2);
+        assert_eq!(annotations[0]["url_citation"]["content"], "éé...");
+        let annotations = citations_to_annotations(&citations, 10);
+        assert_eq!(annotations[0]["url_citation"]["content"], "ééééé");
+    }
+
     #[test]
     fn test_annotations_to_web_search_content() {
         let annotations = vec![json!({
@@ -385,10 +554,11 @@ mod tests {
                 snippet: "This is a test".to_string(),
                 start_index: None,
                 end_index: None,
+                page_age: None,
             },
         ];
 
-        let md = format_citations_as_markdown(&citations, Some("test query"));
+        let md = format_citations_as_markdown(&citations, Some("test query"), 200);
         assert!(md.contains("🔍 已为您搜索："));
         assert!(md.contains("test query"));
         assert!(md.contains("[Example Site](https://example.com)"));
@@ -404,9 +574,10 @@ mod tests {
             snippet: "Info".to_string(),
             start_index: None,
             end_index: None,
+            page_age: None,
         }];
 
-        let merged = merge_citations_into_text(text, &citations, None);
+        let merged = merge_citations_into_text(text, &citations, None, 200);
         assert!(merged.starts_with("Here is my response."));
         assert!(merged.contains("📚 来源："));
     }
@@ -414,10 +585,10 @@ mod tests {
     #[test]
     fn test_empty_citations() {
         let citations: Vec<Citation> = vec![];
-        let md = format_citations_as_markdown(&citations, None);
+        let md = format_citations_as_markdown(&citations, None, 200);
         assert!(md.is_empty());
 
-        let merged = merge_citations_into_text("text", &citations, None);
+        let merged = merge_citations_into_text("text", &citations, None, 200);
         assert_eq!(merged, "text");
     }
 }
\ No newline at end of file
@@ -1,17 +1,27 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use axum::response::sse::Event;
 use futures::{Stream, TryStreamExt};
 use serde::Serialize;
 use serde_json::{json, Value};
 
+use crate::config::{
+    ClewdrConfig, default_annotation_snippet_chars, default_markdown_snippet_chars,
+    default_reasoning_tag,
+};
 use crate::format::{
-    claude_image_to_oai, extract_citations_from_search_result, extract_citations_from_tool_result,
-    citations_to_annotations, merge_citations_into_text,
-    remap_function_call_args, store_thought_signature, Citation,
+    claude_image_to_oai, extract_citations_from_search_result,
+    extract_citations_from_text_citations, extract_citations_from_tool_result,
+    citations_to_annotations, merge_citations_into_text, parse_or_repair_json,
+    remap_function_call_args, remap_tool_result_args, stop_reason_to_openai,
+    store_thought_signature, Citation,
+};
+use crate::types::claude::{
+    ContentBlock, ContentBlockDelta, CreateMessageResponse, StopReason, StreamError, StreamEvent,
+    Usage,
 };
-use crate::types::claude::{ContentBlock, ContentBlockDelta, CreateMessageResponse, StreamEvent};
 
 /// Represents the data structure for streaming events in OpenAI API format
 /// Contains a choices array with deltas of content
@@ -29,8 +39,15 @@ impl StreamEventData {
     /// # Returns
     /// A new StreamEventData instance with the content wrapped in choices array
     fn new(content: EventContent) -> Self {
+        // Logprobs belong to the choice, next to the delta they describe
+        let (delta, logprobs) = match content {
+            EventContent::Logprobs { content, logprobs } => {
+                (EventContent::Content { content }, Some(logprobs))
+            }
+            content => (content, None),
+        };
         Self {
-            choices: vec![StreamEventDelta { delta: content }],
+            choices: vec![StreamEventDelta { delta, logprobs }],
         }
     }
 }
@@ -40,6 +57,9 @@ impl StreamEventData {
 #[derive(Debug, Serialize)]
 struct StreamEventDelta {
     delta: EventContent,
+    /// Per-token log probabilities of the delta, omitted when unavailable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logprobs: Option<Value>,
 }
 
 /// Content of an event, either regular content, reasoning, tool calls, or annotations
@@ -50,6 +70,11 @@ pub enum EventContent {
     Reasoning { reasoning_content: String },
     ToolCalls { tool_calls: Vec<ToolCallDelta> },
     Annotations { annotations: Vec<Value> },
+    /// Content with its OpenAI `logprobs` object, e.g. `{"content": [...]}`
+    ///
+    /// Claude does not report logprobs yet, this only fixes the wire shape.
+    #[allow(dead_code)]
+    Logprobs { content: String, logprobs: Value },
 }
 
 /// Tool call delta for streaming
@@ -83,6 +108,38 @@ struct WebSearchState {
     citations: Vec<Citation>,
 }
 
+/// Signatures of the thinking blocks still being streamed, keyed by block index
+///
+/// Signature deltas are accumulated per block, so a response with several
+/// thinking blocks never mixes their signatures before they are stored.
+#[derive(Debug, Clone, Default)]
+struct ThinkingSignatures {
+    pending: HashMap<usize, String>,
+}
+
+impl ThinkingSignatures {
+    /// Appends a signature delta to the block at `index`
+    fn push(&mut self, index: usize, signature: &str) {
+        self.pending.entry(index).or_default().push_str(signature);
+    }
+
+    /// Takes the complete signature of a finished block
+    fn finish(&mut self, index: usize) -> Option<String> {
+        self.pending.remove(&index).filter(|sig| !sig.is_empty())
+    }
+}
+
+/// Identical consecutive text deltas closer than this are treated as upstream retries
+const DEDUP_WINDOW: Duration = Duration::from_millis(500);
+
+/// Last text delta seen, used to drop duplicated deltas
+#[derive(Debug, Clone)]
+struct LastTextDelta {
+    index: usize,
+    text: String,
+    at: Instant,
+}
+
 /// Creates an SSE event with the given content in OpenAI format
 ///
 /// # Arguments
@@ -96,11 +153,33 @@ pub fn build_event(content: EventContent) -> Event {
     event.json_data(data).unwrap()
 }
 
+/// Remaps tool call arguments before they are sent to the client
+///
+/// The known Claude Code remaps are always applied; with `symmetric` set they
+/// are reversed again for clients that expect their own parameter names.
+fn remap_tool_call_args(name: &str, args: &mut Value, symmetric: bool) {
+    remap_function_call_args(name, args);
+    if symmetric {
+        remap_tool_result_args(name, args);
+    }
+}
+
 /// Build a tool call event for OpenAI format
-fn build_tool_call_event(state: &ToolCallState, index: usize) -> Event {
+fn build_tool_call_event(state: &ToolCallState, index: usize, symmetric: bool) -> Event {
+    // Truncated streams leave incomplete JSON, salvage what was received
+    let mut args_value = match parse_or_repair_json(&state.arguments) {
+        Some((value, false)) => value,
+        Some((value, true)) => {
+            tracing::warn!("[Format] Repaired truncated arguments of tool call {}", state.id);
+            value
+        }
+        None => {
+            tracing::warn!("[Format] Discarding unparsable arguments of tool call {}", state.id);
+            json!({})
+        }
+    };
     // Apply parameter remapping before sending
-    let mut args_value: Value = serde_json::from_str(&state.arguments).unwrap_or(json!({}));
-    remap_function_call_args(&state.name, &mut args_value);
+    remap_tool_call_args(&state.name, &mut args_value, symmetric);
     let remapped_args = serde_json::to_string(&args_value).unwrap_or(state.arguments.clone());
 
     let tool_call = ToolCallDelta {
@@ -117,12 +196,44 @@ fn build_tool_call_event(state: &ToolCallState, index: usize) -> Event {
     })
 }
 
+/// Build a content event opening or closing the reasoning tag
+///
+/// Returns None when the tag is empty, so reasoning is streamed unwrapped.
+fn build_reasoning_tag_event(tag: &str, closing: bool) -> Option<Event> {
+    if tag.is_empty() {
+        return None;
+    }
+    let content = if closing {
+        format!("\n</{tag}>\n\n")
+    } else {
+        format!("<{tag}>\n")
+    };
+    Some(build_event(EventContent::Content { content }))
+}
+
 /// Build an annotations event for web search results
-fn build_annotations_event(citations: &[Citation]) -> Event {
-    let annotations = citations_to_annotations(citations);
+fn build_annotations_event(citations: &[Citation], max_snippet_chars: usize) -> Event {
+    let annotations = citations_to_annotations(citations, max_snippet_chars);
     build_event(EventContent::Annotations { annotations })
 }
 
+/// Build an OpenAI-style error event from a Claude stream error
+///
+/// OpenAI clients expect mid-stream failures as a chunk carrying an
+/// `error` object instead of `choices`.
+fn build_error_event(error: &StreamError) -> Event {
+    let event = Event::default();
+    event
+        .json_data(json!({
+            "error": {
+                "message": error.message,
+                "type": error.type_,
+                "code": null
+            }
+        }))
+        .unwrap()
+}
+
 /// Transforms a Claude.ai event stream into an OpenAI-compatible event stream
 ///
 /// Extracts content from Claude events and reformats them to match OpenAI's streaming format.
@@ -138,10 +249,20 @@ fn build_annotations_event(citations: &[Citation]) -> Event {
 /// # Type Parameters
 /// * `I` - The input stream type
 /// * `E` - The error type for the stream
-pub fn transform_stream<I, E>(s: I) -> impl Stream<Item = Result<Event, E>>
+pub fn transform_stream<I, E>(
+    s: I,
+    options: &OaiResponseOptions,
+) -> impl Stream<Item = Result<Event, E>>
 where
     I: Stream<Item = Result<eventsource_stream::Event, E>>,
 {
+    let symmetric = options.symmetric_param_names;
+    let dedup = options.dedup_stream_deltas;
+    let annotation_snippet_chars = options.annotation_snippet_chars;
+    // Tag of reasoning streamed as content, None to use `reasoning_content`
+    let reasoning_tag: Option<Arc<str>> = options
+        .reasoning_as_content
+        .then(|| Arc::from(options.reasoning_tag.as_str()));
     // State for accumulating tool call arguments
     let tool_call_buffer: Arc<Mutex<HashMap<usize, ToolCallState>>> =
         Arc::new(Mutex::new(HashMap::new()));
@@ -151,16 +272,59 @@ where
     let web_search_buffer: Arc<Mutex<HashMap<usize, WebSearchState>>> =
         Arc::new(Mutex::new(HashMap::new()));
 
+    // Last text delta, for optional de-duplication
+    let last_text_delta: Arc<Mutex<Option<LastTextDelta>>> = Arc::new(Mutex::new(None));
+
+    // Indexes of thinking blocks whose reasoning tag is still open
+    let open_thinking_blocks: Arc<Mutex<HashSet<usize>>> = Arc::new(Mutex::new(HashSet::new()));
+
+    // Indexes of blocks that received JSON deltas without a tool call
+    let unknown_json_blocks: Arc<Mutex<HashSet<usize>>> = Arc::new(Mutex::new(HashSet::new()));
+
+    // Signatures of thinking blocks, stored once their block is complete
+    let thinking_signatures: Arc<Mutex<ThinkingSignatures>> = Arc::default();
+
     s.try_filter_map(move |eventsource_stream::Event { data, .. }| {
         let buffer = tool_call_buffer.clone();
         let index_counter = tool_call_index.clone();
         let ws_buffer = web_search_buffer.clone();
+        let last_delta = last_text_delta.clone();
+        let reasoning_tag = reasoning_tag.clone();
+        let open_thinking = open_thinking_blocks.clone();
+        let unknown_json = unknown_json_blocks.clone();
+        let signatures = thinking_signatures.clone();
 
         async move {
             let Ok(parsed) = serde_json::from_str::<StreamEvent>(&data) else {
                 return Ok(None);
             };
 
+            if dedup {
+                let mut last = last_delta.lock().unwrap();
+                match &parsed {
+                    StreamEvent::ContentBlockDelta {
+                        index,
+                        delta: ContentBlockDelta::TextDelta { text },
+                    } => {
+                        let now = Instant::now();
+                        if let Some(prev) = last.as_ref()
+                            && prev.index == *index
+                            && prev.text == *text
+                            && now.duration_since(prev.at) < DEDUP_WINDOW
+                        {
+                            tracing::debug!("[Format] Dropping duplicated text delta at {}", index);
+                            return Ok(None);
+                        }
+                        *last = Some(LastTextDelta {
+                            index: *index,
+                            text: text.clone(),
+                            at: now,
+                        });
+                    }
+                    _ => *last = None,
+                }
+            }
+
             match parsed {
                 StreamEvent::ContentBlockStart {
                     index,
@@ -197,6 +361,12 @@ where
                                 WebSearchState { citations },
                             );
                         }
+                        // Open the reasoning tag when thinking is streamed as content
+                        ContentBlock::Thinking { .. } if reasoning_tag.is_some() => {
+                            open_thinking.lock().unwrap().insert(index);
+                            let tag = reasoning_tag.as_deref().unwrap_or_default();
+                            return Ok(build_reasoning_tag_event(tag, false));
+                        }
                         _ => {}
                     }
                     Ok(None)
@@ -207,26 +377,48 @@ where
                             Ok(Some(build_event(EventContent::Content { content: text })))
                         }
                         ContentBlockDelta::ThinkingDelta { thinking } => {
-                            Ok(Some(build_event(EventContent::Reasoning {
-                                reasoning_content: thinking,
-                            })))
+                            let content = if reasoning_tag.is_some() {
+                                EventContent::Content { content: thinking }
+                            } else {
+                                EventContent::Reasoning {
+                                    reasoning_content: thinking,
+                                }
+                            };
+                            Ok(Some(build_event(content)))
                         }
                         ContentBlockDelta::InputJsonDelta { partial_json } => {
                             // Accumulate tool call arguments
                             let mut buf = buffer.lock().unwrap();
                             if let Some(state) = buf.get_mut(&index) {
                                 state.arguments.push_str(&partial_json);
+                            } else if unknown_json.lock().unwrap().insert(index) {
+                                // Warn once per block, its later deltas are dropped silently
+                                tracing::warn!(
+                                    "[Format] Dropping input_json_delta of non-tool block {}",
+                                    index
+                                );
                             }
                             Ok(None)
                         }
                         ContentBlockDelta::SignatureDelta { signature } => {
-                            // Store signature to global storage for future requests
-                            store_thought_signature(&signature);
+                            // Kept with its thinking block until the block stops
+                            signatures.lock().unwrap().push(index, &signature);
                             Ok(None)
                         }
                     }
                 }
                 StreamEvent::ContentBlockStop { index } => {
+                    // Store the finished signature to global storage for future requests
+                    if let Some(signature) = signatures.lock().unwrap().finish(index) {
+                        store_thought_signature(&signature);
+                    }
+
+                    if let Some(tag) = reasoning_tag.as_deref()
+                        && open_thinking.lock().unwrap().remove(&index)
+                    {
+                        return Ok(build_reasoning_tag_event(tag, true));
+                    }
+
                     // Check if this was a tool call block
                     {
                         let mut buf = buffer.lock().unwrap();
@@ -235,7 +427,11 @@ where
                             let mut idx = index_counter.lock().unwrap();
                             let current_idx = *idx;
                             *idx += 1;
-                            return Ok(Some(build_tool_call_event(&state, current_idx)));
+                            return Ok(Some(build_tool_call_event(
+                                &state,
+                                current_idx,
+                                symmetric,
+                            )));
                         }
                     }
                     
@@ -244,19 +440,131 @@ where
                         let mut ws_buf = ws_buffer.lock().unwrap();
                         if let Some(state) = ws_buf.remove(&index) {
                             if !state.citations.is_empty() {
-                                return Ok(Some(build_annotations_event(&state.citations)));
+                                return Ok(Some(build_annotations_event(
+                                    &state.citations,
+                                    annotation_snippet_chars,
+                                )));
                             }
                         }
                     }
                     
                     Ok(None)
                 }
+                StreamEvent::Error { error } => {
+                    tracing::warn!(
+                        "[Format] Upstream stream error: {}: {}",
+                        error.type_,
+                        error.message
+                    );
+                    Ok(Some(build_error_event(&error)))
+                }
                 _ => Ok(None),
             }
         }
     })
 }
 
+/// Converts Claude token usage into an OpenAI usage object
+///
+/// Claude reports cached prompt tokens separately from `input_tokens`, while
+/// OpenAI counts them in `prompt_tokens` and lists cache hits under
+/// `prompt_tokens_details.cached_tokens`.
+///
+/// # Arguments
+/// * `usage` - The Claude usage statistics
+///
+/// # Returns
+/// A JSON Value in OpenAI usage format
+pub fn usage_to_oai(usage: &Usage) -> Value {
+    let cache_creation = usage.cache_creation_input_tokens.unwrap_or(0);
+    let cache_read = usage.cache_read_input_tokens.unwrap_or(0);
+    let prompt_tokens = usage.input_tokens + cache_creation + cache_read;
+    let mut oai_usage = json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": usage.output_tokens,
+        "total_tokens": prompt_tokens + usage.output_tokens
+    });
+    if usage.cache_creation_input_tokens.is_some() || usage.cache_read_input_tokens.is_some() {
+        oai_usage["prompt_tokens_details"] = json!({ "cached_tokens": cache_read });
+    }
+    oai_usage
+}
+
+/// Options controlling how Claude responses are rendered for OpenAI clients
+#[derive(Debug, Clone)]
+pub struct OaiResponseOptions {
+    /// Reverse the known tool parameter remaps back to OpenAI naming
+    pub symmetric_param_names: bool,
+    /// Emit `null` instead of `""` as content of tool-call-only messages
+    pub empty_content_as_null: bool,
+    /// Drop identical consecutive text deltas from the stream
+    pub dedup_stream_deltas: bool,
+    /// Report server-executed tool invocations as tool_calls
+    pub server_tool_calls: bool,
+    /// Maximum characters of a snippet in the markdown source list
+    pub markdown_snippet_chars: usize,
+    /// Maximum characters of a snippet in `url_citation` annotations
+    pub annotation_snippet_chars: usize,
+    /// Stream thinking as `content` instead of `reasoning_content`
+    pub reasoning_as_content: bool,
+    /// Tag wrapping reasoning streamed as content, none when empty
+    pub reasoning_tag: String,
+    /// Report thinking as a structured `reasoning` object in JSON responses
+    pub structured_reasoning: bool,
+}
+
+impl Default for OaiResponseOptions {
+    fn default() -> Self {
+        Self {
+            symmetric_param_names: false,
+            empty_content_as_null: true,
+            dedup_stream_deltas: false,
+            server_tool_calls: false,
+            markdown_snippet_chars: default_markdown_snippet_chars(),
+            annotation_snippet_chars: default_annotation_snippet_chars(),
+            reasoning_as_content: false,
+            reasoning_tag: default_reasoning_tag(),
+            structured_reasoning: false,
+        }
+    }
+}
+
+impl OaiResponseOptions {
+    /// Builds response options from the current configuration
+    pub fn from_config(config: &ClewdrConfig) -> Self {
+        Self {
+            symmetric_param_names: config.symmetric_param_names,
+            empty_content_as_null: config.empty_content_as_null,
+            dedup_stream_deltas: config.dedup_stream_deltas,
+            server_tool_calls: config.server_tool_calls,
+            markdown_snippet_chars: config.markdown_snippet_chars,
+            annotation_snippet_chars: config.annotation_snippet_chars,
+            reasoning_as_content: config.reasoning_as_content,
+            reasoning_tag: config.reasoning_tag.to_owned(),
+            structured_reasoning: config.structured_reasoning,
+        }
+    }
+}
+
+/// Builds an OpenAI tool call from a `server_tool_use` block
+///
+/// The call already ran on the server, so it is marked with `server_executed`
+/// to tell clients not to execute it again.
+fn server_tool_call(data: &Value) -> Option<Value> {
+    let id = data["id"].as_str()?;
+    let name = data["name"].as_str()?;
+    let input = data.get("input").cloned().unwrap_or_else(|| json!({}));
+    Some(json!({
+        "id": id,
+        "type": "function",
+        "server_executed": true,
+        "function": {
+            "name": name,
+            "arguments": serde_json::to_string(&input).unwrap_or_default()
+        }
+    }))
+}
+
 /// Transforms a Claude response to OpenAI format (non-streaming)
 ///
 /// This function converts a complete Claude API response to the OpenAI chat completion format,
@@ -264,18 +572,35 @@ where
 ///
 /// # Arguments
 /// * `input` - The Claude API response
+/// * `options` - Rendering options for the OpenAI response
 ///
 /// # Returns
 /// A JSON Value in OpenAI chat completion format
-pub fn transforms_json(input: CreateMessageResponse) -> Value {
+pub fn transforms_json(input: CreateMessageResponse, options: &OaiResponseOptions) -> Value {
     let mut content_parts = Vec::new();
     let mut tool_calls = Vec::new();
     let mut all_citations: Vec<Citation> = Vec::new();
+    let mut reasoning_parts = Vec::new();
+    let mut image_parts = Vec::new();
+    // Set when a tool_use block splits the text, so the segments don't run together
+    let mut text_interrupted = false;
 
     for block in input.content.iter() {
         match block {
-            ContentBlock::Text { text, .. } => {
+            ContentBlock::Text { text, citations, .. } => {
+                if text_interrupted && !content_parts.is_empty() {
+                    content_parts.push("\n".to_string());
+                }
+                text_interrupted = false;
                 content_parts.push(text.clone());
+                // Inline citations cite the same sources repeatedly, keep one per URL
+                for citation in extract_citations_from_text_citations(
+                    citations.as_deref().unwrap_or_default(),
+                ) {
+                    if !all_citations.iter().any(|c| c.url == citation.url) {
+                        all_citations.push(citation);
+                    }
+                }
             }
             ContentBlock::ToolUse {
                 id,
@@ -288,10 +613,11 @@ pub fn transforms_json(input: CreateMessageResponse) -> Value {
                 if let Some(sig) = signature {
                     store_thought_signature(sig);
                 }
+                text_interrupted = true;
 
                 // Apply parameter remapping
                 let mut remapped_input = input.clone();
-                remap_function_call_args(name, &mut remapped_input);
+                remap_tool_call_args(name, &mut remapped_input, options.symmetric_param_names);
 
                 tool_calls.push(json!({
                     "id": id,
@@ -302,12 +628,25 @@ pub fn transforms_json(input: CreateMessageResponse) -> Value {
                     }
                 }));
             }
-            ContentBlock::Thinking { signature, .. } => {
+            ContentBlock::ServerToolUse { data } if options.server_tool_calls => {
+                if let Some(call) = server_tool_call(data) {
+                    text_interrupted = true;
+                    tool_calls.push(call);
+                }
+            }
+            ContentBlock::Thinking {
+                thinking,
+                signature,
+                ..
+            } => {
                 // Store signature for future requests
                 if let Some(sig) = signature {
                     store_thought_signature(sig);
                 }
-                // Note: thinking content is not included in OpenAI format
+                // Thinking is only reported when structured reasoning is requested
+                if options.structured_reasoning && !thinking.is_empty() {
+                    reasoning_parts.push(json!({ "type": "reasoning_text", "text": thinking }));
+                }
             }
             ContentBlock::WebSearchToolResult { data } => {
                 // Extract citations from web search results
@@ -319,13 +658,19 @@ pub fn transforms_json(input: CreateMessageResponse) -> Value {
                 let citations = extract_citations_from_search_result(data);
                 all_citations.extend(citations);
             }
+            ContentBlock::CodeExecutionToolResult { data }
+            | ContentBlock::BashCodeExecutionToolResult { data } => {
+                // Surface stdout as a code block, other result shapes are skipped
+                if let Some(stdout) = data["content"]["stdout"].as_str()
+                    && !stdout.trim().is_empty()
+                {
+                    content_parts.push(format!("\n```\n{}\n```\n", stdout.trim_end()));
+                }
+            }
             ContentBlock::Image { source, .. } => {
-                // Convert Claude image to OpenAI format (data URI)
-                // Note: OpenAI doesn't have a direct image content type in responses,
-                // but we can include it as a data URI in content if needed
-                let converted = claude_image_to_oai(source);
-                if let ContentBlock::ImageUrl { image_url } = converted {
-                    content_parts.push(format!("![image]({})", image_url.url));
+                // Images are returned as `image_url` parts of an array content
+                if let ContentBlock::ImageUrl { image_url } = claude_image_to_oai(source) {
+                    image_parts.push(json!({ "type": "image_url", "image_url": image_url }));
                 }
             }
             _ => {}
@@ -337,33 +682,33 @@ pub fn transforms_json(input: CreateMessageResponse) -> Value {
         content_parts.join("")
     } else {
         let base_content = content_parts.join("");
-        merge_citations_into_text(&base_content, &all_citations, None)
+        merge_citations_into_text(
+            &base_content,
+            &all_citations,
+            None,
+            options.markdown_snippet_chars,
+        )
     };
 
-    let usage = input.usage.as_ref().map(|u| {
-        json!({
-            "prompt_tokens": u.input_tokens,
-            "completion_tokens": u.output_tokens,
-            "total_tokens": u.input_tokens + u.output_tokens
-        })
-    });
+    let usage = input.usage.as_ref().map(usage_to_oai);
 
-    let finish_reason = match input.stop_reason {
-        Some(crate::types::claude::StopReason::EndTurn) => "stop",
-        Some(crate::types::claude::StopReason::MaxTokens) => "length",
-        Some(crate::types::claude::StopReason::StopSequence) => "stop",
-        Some(crate::types::claude::StopReason::ToolUse) => "tool_calls",
-        Some(crate::types::claude::StopReason::Refusal) => "content_filter",
-        None => "stop",
-    };
+    let finish_reason = stop_reason_to_openai(input.stop_reason);
 
     // Build message object
     let mut message = json!({
         "role": "assistant",
     });
 
-    // Add content (null if empty and has tool calls)
-    if content.is_empty() && !tool_calls.is_empty() {
+    // Add content (null if empty and has tool calls, unless the client wants "")
+    if matches!(input.stop_reason, Some(StopReason::Refusal)) {
+        // OpenAI reports safety refusals in `refusal` with null content
+        message["content"] = Value::Null;
+        message["refusal"] = json!(content);
+    } else if !image_parts.is_empty() {
+        // The text comes first, followed by the images in response order
+        let text_part = (!content.is_empty()).then(|| json!({ "type": "text", "text": content }));
+        message["content"] = json!(text_part.into_iter().chain(image_parts).collect::<Vec<_>>());
+    } else if content.is_empty() && !tool_calls.is_empty() && options.empty_content_as_null {
         message["content"] = Value::Null;
     } else {
         message["content"] = json!(content);
@@ -376,7 +721,14 @@ pub fn transforms_json(input: CreateMessageResponse) -> Value {
 
     // Add annotations if we have citations
     if !all_citations.is_empty() {
-        message["annotations"] = json!(citations_to_annotations(&all_citations));
+        let annotations =
+            citations_to_annotations(&all_citations, options.annotation_snippet_chars);
+        message["annotations"] = json!(annotations);
+    }
+
+    // Add thinking as a reasoning summary object
+    if !reasoning_parts.is_empty() {
+        message["reasoning"] = json!({ "content": reasoning_parts });
     }
 
     json!({
@@ -399,8 +751,59 @@ pub fn transforms_json(input: CreateMessageResponse) -> Value {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::claude::{Role, StopReason, Usage};
+    use crate::types::claude::Role;
+    use axum::response::{IntoResponse, Sse};
     use serde_json::json;
+    use std::convert::Infallible;
+
+    /// Runs Claude stream events through `transform_stream` and returns the
+    /// JSON payloads of the emitted OpenAI chunks
+    async fn collect_oai_chunks(events: Vec<Value>, options: &OaiResponseOptions) -> Vec<Value> {
+        let input = futures::stream::iter(events.into_iter().map(|e| {
+            Ok::<_, Infallible>(eventsource_stream::Event {
+                event: String::new(),
+                data: e.to_string(),
+                id: String::new(),
+                retry: None,
+            })
+        }));
+        let body = Sse::new(transform_stream(input, options)).into_response().into_body();
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_logprobs_event_shape() {
+        let logprobs = json!({
+            "content": [{
+                "token": "Hi",
+                "logprob": -0.25,
+                "bytes": [72, 105],
+                "top_logprobs": [{"token": "Hi", "logprob": -0.25, "bytes": [72, 105]}]
+            }]
+        });
+        let data = StreamEventData::new(EventContent::Logprobs {
+            content: "Hi".to_string(),
+            logprobs: logprobs.clone(),
+        });
+        assert_eq!(
+            serde_json::to_value(&data).unwrap(),
+            json!({"choices": [{"delta": {"content": "Hi"}, "logprobs": logprobs}]})
+        );
+
+        // Regular events carry no logprobs field
+        let data = StreamEventData::new(EventContent::Content {
+            content: "Hi".to_string(),
+        });
+        assert_eq!(
+            serde_json::to_value(&data).unwrap(),
+            json!({"choices": [{"delta": {"content": "Hi"}}]})
+        );
+    }
 
     #[test]
     fn test_transforms_json_basic() {
@@ -408,6 +811,7 @@ mod tests {
             content: vec![ContentBlock::Text {
                 text: "Hello, world!".to_string(),
                 cache_control: None,
+                citations: None,
             }],
             id: "msg_123".to_string(),
             model: "claude-3-opus".to_string(),
@@ -418,10 +822,11 @@ mod tests {
             usage: Some(Usage {
                 input_tokens: 10,
                 output_tokens: 5,
+                ..Default::default()
             }),
         };
 
-        let result = transforms_json(response);
+        let result = transforms_json(response, &OaiResponseOptions::default());
 
         assert_eq!(result["id"], "msg_123");
         assert_eq!(result["choices"][0]["message"]["content"], "Hello, world!");
@@ -447,7 +852,7 @@ mod tests {
             usage: None,
         };
 
-        let result = transforms_json(response);
+        let result = transforms_json(response, &OaiResponseOptions::default());
 
         assert_eq!(result["choices"][0]["finish_reason"], "tool_calls");
         assert!(result["choices"][0]["message"]["tool_calls"].is_array());
@@ -464,6 +869,290 @@ mod tests {
         assert!(args.get("query").is_none());
     }
 
+    #[test]
+    fn test_transforms_json_text_split_by_tool_use() {
+        let response = CreateMessageResponse {
+            content: vec![
+                ContentBlock::Text {
+                    text: "Let me check.".to_string(),
+                    cache_control: None,
+                    citations: None,
+                },
+                ContentBlock::ToolUse {
+                    id: "tool_123".to_string(),
+                    name: "get_weather".to_string(),
+                    input: json!({"city": "Paris"}),
+                    signature: None,
+                    cache_control: None,
+                },
+                ContentBlock::Text {
+                    text: "Done.".to_string(),
+                    cache_control: None,
+                    citations: None,
+                },
+            ],
+            id: "msg_123".to_string(),
+            model: "claude-3-opus".to_string(),
+            role: Role::Assistant,
+            stop_reason: Some(StopReason::ToolUse),
+            stop_sequence: None,
+            type_: "message".to_string(),
+            usage: None,
+        };
+
+        let result = transforms_json(response, &OaiResponseOptions::default());
+        let message = &result["choices"][0]["message"];
+        assert_eq!(message["content"], "Let me check.\nDone.");
+        assert_eq!(message["tool_calls"][0]["id"], "tool_123");
+    }
+
+    #[test]
+    fn test_transforms_json_image_content() {
+        let response: CreateMessageResponse = serde_json::from_value(json!({
+            "id": "msg_123",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-opus",
+            "content": [
+                {"type": "text", "text": "Here is the chart."},
+                {
+                    "type": "image",
+                    "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}
+                }
+            ],
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": null
+        }))
+        .unwrap();
+
+        let result = transforms_json(response, &OaiResponseOptions::default());
+        assert_eq!(
+            result["choices"][0]["message"]["content"],
+            json!([
+                {"type": "text", "text": "Here is the chart."},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_transforms_json_structured_reasoning() {
+        let response = || CreateMessageResponse {
+            content: vec![
+                ContentBlock::Thinking {
+                    thinking: "The user wants a greeting.".to_string(),
+                    signature: None,
+                    cache_control: None,
+                },
+                ContentBlock::text("Hello!"),
+            ],
+            id: "msg_123".to_string(),
+            model: "claude-3-opus".to_string(),
+            role: Role::Assistant,
+            stop_reason: Some(StopReason::EndTurn),
+            stop_sequence: None,
+            type_: "message".to_string(),
+            usage: None,
+        };
+
+        let result = transforms_json(response(), &OaiResponseOptions::default());
+        assert!(result["choices"][0]["message"].get("reasoning").is_none());
+
+        let options = OaiResponseOptions {
+            structured_reasoning: true,
+            ..Default::default()
+        };
+        let result = transforms_json(response(), &options);
+        let message = &result["choices"][0]["message"];
+        assert_eq!(message["content"], "Hello!");
+        assert_eq!(
+            message["reasoning"],
+            json!({
+                "content": [{"type": "reasoning_text", "text": "The user wants a greeting."}]
+            })
+        );
+    }
+
+    #[test]
+    fn test_transforms_json_text_citations() {
+        let response: CreateMessageResponse = serde_json::from_value(json!({
+            "id": "msg_123",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-opus",
+            "content": [
+                {
+                    "type": "text",
+                    "text": "Rust is fast.",
+                    "citations": [{
+                        "type": "web_search_result_location",
+                        "url": "https://www.rust-lang.org",
+                        "title": "Rust",
+                        "cited_text": "Blazingly fast"
+                    }]
+                },
+                {
+                    "type": "text",
+                    "text": " And safe.",
+                    "citations": [{
+                        "type": "web_search_result_location",
+                        "url": "https://www.rust-lang.org",
+                        "title": "Rust",
+                        "cited_text": "Memory safe"
+                    }]
+                }
+            ],
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": null
+        }))
+        .unwrap();
+
+        let result = transforms_json(response, &OaiResponseOptions::default());
+        let message = &result["choices"][0]["message"];
+        let annotations = message["annotations"].as_array().unwrap();
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations[0]["url_citation"]["url"], "https://www.rust-lang.org");
+        let content = message["content"].as_str().unwrap();
+        assert!(content.starts_with("Rust is fast. And safe."));
+        assert!(content.contains("[Rust](https://www.rust-lang.org)"));
+    }
+
+    #[test]
+    fn test_empty_content_with_tool_calls() {
+        let response = || CreateMessageResponse {
+            content: vec![ContentBlock::ToolUse {
+                id: "tool_123".to_string(),
+                name: "get_weather".to_string(),
+                input: json!({"city": "Paris"}),
+                signature: None,
+                cache_control: None,
+            }],
+            id: "msg_123".to_string(),
+            model: "claude-3-opus".to_string(),
+            role: Role::Assistant,
+            stop_reason: Some(StopReason::ToolUse),
+            stop_sequence: None,
+            type_: "message".to_string(),
+            usage: None,
+        };
+
+        let result = transforms_json(response(), &OaiResponseOptions::default());
+        assert!(result["choices"][0]["message"]["content"].is_null());
+
+        let options = OaiResponseOptions {
+            empty_content_as_null: false,
+            ..Default::default()
+        };
+        let result = transforms_json(response(), &options);
+        assert_eq!(result["choices"][0]["message"]["content"], "");
+    }
+
+    #[test]
+    fn test_server_tool_use_becomes_tool_call() {
+        let response = || CreateMessageResponse {
+            content: vec![
+                ContentBlock::ServerToolUse {
+                    data: json!({
+                        "id": "srvtoolu_01",
+                        "name": "web_search",
+                        "input": {"query": "rust 2024 edition"}
+                    }),
+                },
+                ContentBlock::Text {
+                    text: "Rust 2024 shipped in 1.85.".to_string(),
+                    cache_control: None,
+                    citations: None,
+                },
+            ],
+            id: "msg_123".to_string(),
+            model: "claude-3-opus".to_string(),
+            role: Role::Assistant,
+            stop_reason: Some(StopReason::EndTurn),
+            stop_sequence: None,
+            type_: "message".to_string(),
+            usage: None,
+        };
+
+        let result = transforms_json(response(), &OaiResponseOptions::default());
+        assert!(result["choices"][0]["message"]["tool_calls"].is_null());
+
+        let options = OaiResponseOptions {
+            server_tool_calls: true,
+            ..Default::default()
+        };
+        let result = transforms_json(response(), &options);
+        let message = &result["choices"][0]["message"];
+        let call = &message["tool_calls"][0];
+        assert_eq!(call["id"], "srvtoolu_01");
+        assert_eq!(call["function"]["name"], "web_search");
+        assert_eq!(call["server_executed"], true);
+        let args: Value =
+            serde_json::from_str(call["function"]["arguments"].as_str().unwrap()).unwrap();
+        assert_eq!(args, json!({"query": "rust 2024 edition"}));
+        assert_eq!(message["content"], "Rust 2024 shipped in 1.85.");
+        assert_eq!(result["choices"][0]["finish_reason"], "stop");
+    }
+
+    #[test]
+    fn test_refusal_populates_refusal_field() {
+        let response = CreateMessageResponse {
+            content: vec![ContentBlock::Text {
+                text: "I can't help with that.".to_string(),
+                cache_control: None,
+                citations: None,
+            }],
+            id: "msg_123".to_string(),
+            model: "claude-3-opus".to_string(),
+            role: Role::Assistant,
+            stop_reason: Some(StopReason::Refusal),
+            stop_sequence: None,
+            type_: "message".to_string(),
+            usage: None,
+        };
+
+        let result = transforms_json(response, &OaiResponseOptions::default());
+        let message = &result["choices"][0]["message"];
+        assert!(message["content"].is_null());
+        assert_eq!(message["refusal"], "I can't help with that.");
+        assert_eq!(result["choices"][0]["finish_reason"], "content_filter");
+    }
+
+    #[test]
+    fn test_symmetric_param_names() {
+        let response = || CreateMessageResponse {
+            content: vec![ContentBlock::ToolUse {
+                id: "tool_123".to_string(),
+                name: "Read".to_string(),
+                input: json!({"file_path": "/some/file.txt"}),
+                signature: None,
+                cache_control: None,
+            }],
+            id: "msg_123".to_string(),
+            model: "claude-3-opus".to_string(),
+            role: Role::Assistant,
+            stop_reason: Some(StopReason::ToolUse),
+            stop_sequence: None,
+            type_: "message".to_string(),
+            usage: None,
+        };
+        let arguments = |result: &Value| -> Value {
+            let args = &result["choices"][0]["message"]["tool_calls"][0]["function"]["arguments"];
+            serde_json::from_str(args.as_str().unwrap()).unwrap()
+        };
+
+        let off = transforms_json(response(), &OaiResponseOptions::default());
+        assert_eq!(arguments(&off), json!({"file_path": "/some/file.txt"}));
+
+        let options = OaiResponseOptions {
+            symmetric_param_names: true,
+            ..Default::default()
+        };
+        let on = transforms_json(response(), &options);
+        assert_eq!(arguments(&on), json!({"path": "/some/file.txt"}));
+    }
+
     #[test]
     fn test_stop_reason_mapping() {
         let test_cases = vec![
@@ -487,7 +1176,7 @@ mod tests {
                 usage: None,
             };
 
-            let result = transforms_json(response);
+            let result = transforms_json(response, &OaiResponseOptions::default());
             assert_eq!(
                 result["choices"][0]["finish_reason"], expected,
                 "Failed for stop_reason: {:?}",
@@ -495,4 +1184,290 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_transforms_json_cache_usage() {
+        let response = CreateMessageResponse {
+            content: vec![ContentBlock::Text {
+                text: "cached".to_string(),
+                cache_control: None,
+                citations: None,
+            }],
+            id: "msg_123".to_string(),
+            model: "claude-3-opus".to_string(),
+            role: Role::Assistant,
+            stop_reason: Some(StopReason::EndTurn),
+            stop_sequence: None,
+            type_: "message".to_string(),
+            usage: Some(Usage {
+                input_tokens: 10,
+                output_tokens: 5,
+                cache_creation_input_tokens: Some(20),
+                cache_read_input_tokens: Some(100),
+            }),
+        };
+
+        let result = transforms_json(response, &OaiResponseOptions::default());
+        let usage = &result["usage"];
+
+        assert_eq!(usage["prompt_tokens"], 130);
+        assert_eq!(usage["completion_tokens"], 5);
+        assert_eq!(usage["total_tokens"], 135);
+        assert_eq!(usage["prompt_tokens_details"]["cached_tokens"], 100);
+    }
+
+    #[tokio::test]
+    async fn test_transform_stream_surfaces_error_event() {
+        let events = vec![
+            json!({
+                "type": "content_block_delta",
+                "index": 0,
+                "delta": {"type": "text_delta", "text": "partial"}
+            }),
+            json!({
+                "type": "error",
+                "error": {"type": "overloaded_error", "message": "Overloaded"}
+            }),
+        ];
+        let chunks = collect_oai_chunks(events, &OaiResponseOptions::default()).await;
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0]["choices"][0]["delta"]["content"], "partial");
+        assert_eq!(chunks[1]["error"]["type"], "overloaded_error");
+        assert_eq!(chunks[1]["error"]["message"], "Overloaded");
+    }
+
+    #[tokio::test]
+    async fn test_transform_stream_repairs_truncated_arguments() {
+        let events = vec![
+            json!({
+                "type": "content_block_start",
+                "index": 0,
+                "content_block": {
+                    "type": "tool_use",
+                    "id": "toolu_01",
+                    "name": "get_weather",
+                    "input": {}
+                }
+            }),
+            json!({
+                "type": "content_block_delta",
+                "index": 0,
+                "delta": {"type": "input_json_delta", "partial_json": "{\"city\": \"Par"}
+            }),
+            json!({
+                "type": "content_block_delta",
+                "index": 0,
+                "delta": {"type": "input_json_delta", "partial_json": "is\", \"unit\": "}
+            }),
+            json!({"type": "content_block_stop", "index": 0}),
+        ];
+        let chunks = collect_oai_chunks(events, &OaiResponseOptions::default()).await;
+
+        assert_eq!(chunks.len(), 1);
+        let call = &chunks[0]["choices"][0]["delta"]["tool_calls"][0];
+        assert_eq!(call["id"], "toolu_01");
+        let args: Value =
+            serde_json::from_str(call["function"]["arguments"].as_str().unwrap()).unwrap();
+        assert_eq!(args, json!({"city": "Paris"}));
+    }
+
+    #[tokio::test]
+    async fn test_transform_stream_drops_json_delta_for_unknown_index() {
+        let json_delta = |index: usize, partial_json: &str| {
+            json!({
+                "type": "content_block_delta",
+                "index": index,
+                "delta": {"type": "input_json_delta", "partial_json": partial_json}
+            })
+        };
+        let events = vec![
+            // No tool_use block was started at index 3
+            json_delta(3, "{\"stray\": true}"),
+            json!({
+                "type": "content_block_start",
+                "index": 0,
+                "content_block": {
+                    "type": "tool_use",
+                    "id": "toolu_01",
+                    "name": "get_weather",
+                    "input": {}
+                }
+            }),
+            json_delta(0, "{\"city\": \"Paris\"}"),
+            json_delta(3, "{}"),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "content_block_stop", "index": 3}),
+        ];
+        let chunks = collect_oai_chunks(events, &OaiResponseOptions::default()).await;
+
+        assert_eq!(chunks.len(), 1);
+        let call = &chunks[0]["choices"][0]["delta"]["tool_calls"][0];
+        assert_eq!(call["id"], "toolu_01");
+        assert_eq!(call["function"]["arguments"], "{\"city\":\"Paris\"}");
+    }
+
+    #[tokio::test]
+    async fn test_transform_stream_dedups_text_deltas() {
+        let delta = |text: &str| {
+            json!({
+                "type": "content_block_delta",
+                "index": 0,
+                "delta": {"type": "text_delta", "text": text}
+            })
+        };
+        let events = vec![delta("Hello"), delta("Hello"), delta(" world")];
+
+        let options = OaiResponseOptions {
+            dedup_stream_deltas: true,
+            ..Default::default()
+        };
+        let chunks = collect_oai_chunks(events.clone(), &options).await;
+        let texts: Vec<_> = chunks
+            .iter()
+            .map(|c| c["choices"][0]["delta"]["content"].as_str().unwrap())
+            .collect();
+        assert_eq!(texts, ["Hello", " world"]);
+
+        // Off by default, repeated tokens pass through
+        let chunks = collect_oai_chunks(events, &OaiResponseOptions::default()).await;
+        assert_eq!(chunks.len(), 3);
+    }
+
+    #[test]
+    fn test_thinking_signatures_per_block() {
+        let mut signatures = ThinkingSignatures::default();
+        // Two thinking blocks whose signature deltas arrive interleaved
+        signatures.push(0, "sig_first_");
+        signatures.push(1, "sig_second_");
+        signatures.push(0, "block");
+        signatures.push(1, "block_longer");
+
+        assert_eq!(signatures.finish(0).as_deref(), Some("sig_first_block"));
+        assert_eq!(signatures.finish(1).as_deref(), Some("sig_second_block_longer"));
+        // A block is only finished once, and blocks without a signature have none
+        assert_eq!(signatures.finish(0), None);
+        signatures.push(2, "");
+        assert_eq!(signatures.finish(2), None);
+    }
+
+    #[tokio::test]
+    async fn test_transform_stream_stores_each_thinking_signature() {
+        use crate::format::{clear_thought_signature, get_thought_signature};
+        use futures::StreamExt;
+
+        let first = "sig_first_block_".repeat(20);
+        let second = "sig_second_block_".repeat(30);
+        let thinking_block = |index: usize, thinking: &str, signature: &str| {
+            let (head, tail) = signature.split_at(signature.len() / 2);
+            vec![
+                json!({
+                    "type": "content_block_start",
+                    "index": index,
+                    "content_block": {"type": "thinking", "thinking": ""}
+                }),
+                json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {"type": "thinking_delta", "thinking": thinking}
+                }),
+                json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {"type": "signature_delta", "signature": head}
+                }),
+                json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {"type": "signature_delta", "signature": tail}
+                }),
+                json!({"type": "content_block_stop", "index": index}),
+            ]
+        };
+        let mut events = thinking_block(0, "First.", &first);
+        events.extend(thinking_block(1, "Second.", &second));
+        events.push(json!({
+            "type": "content_block_delta",
+            "index": 2,
+            "delta": {"type": "text_delta", "text": "Answer"}
+        }));
+        let input = futures::stream::iter(events.into_iter().map(|e| {
+            Ok::<_, Infallible>(eventsource_stream::Event {
+                event: String::new(),
+                data: e.to_string(),
+                id: String::new(),
+                retry: None,
+            })
+        }));
+        clear_thought_signature();
+        let mut stream = std::pin::pin!(transform_stream(input, &OaiResponseOptions::default()));
+
+        // Each chunk is emitted once the previous thinking block has stopped
+        assert!(stream.next().await.is_some());
+        assert!(stream.next().await.is_some());
+        assert_eq!(get_thought_signature().as_deref(), Some(first.as_str()));
+        assert!(stream.next().await.is_some());
+        assert_eq!(get_thought_signature().as_deref(), Some(second.as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_transform_stream_reasoning_modes() {
+        let events = vec![
+            json!({
+                "type": "content_block_start",
+                "index": 0,
+                "content_block": {"type": "thinking", "thinking": ""}
+            }),
+            json!({
+                "type": "content_block_delta",
+                "index": 0,
+                "delta": {"type": "thinking_delta", "thinking": "Let me think."}
+            }),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({
+                "type": "content_block_delta",
+                "index": 1,
+                "delta": {"type": "text_delta", "text": "Answer"}
+            }),
+        ];
+        let deltas = |chunks: Vec<Value>| -> Vec<Value> {
+            chunks
+                .into_iter()
+                .map(|c| c["choices"][0]["delta"].to_owned())
+                .collect()
+        };
+
+        // By default reasoning goes to its own field
+        let chunks = collect_oai_chunks(events.clone(), &OaiResponseOptions::default()).await;
+        assert_eq!(
+            deltas(chunks),
+            [
+                json!({"reasoning_content": "Let me think."}),
+                json!({"content": "Answer"})
+            ]
+        );
+
+        let options = OaiResponseOptions {
+            reasoning_as_content: true,
+            ..Default::default()
+        };
+        let chunks = collect_oai_chunks(events.clone(), &options).await;
+        let content: String = deltas(chunks)
+            .iter()
+            .map(|d| d["content"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(content, "<think>\nLet me think.\n</think>\n\nAnswer");
+
+        // An empty tag streams the reasoning unwrapped
+        let options = OaiResponseOptions {
+            reasoning_as_content: true,
+            reasoning_tag: String::new(),
+            ..Default::default()
+        };
+        let chunks = collect_oai_chunks(events, &options).await;
+        assert_eq!(
+            deltas(chunks),
+            [json!({"content": "Let me think."}), json!({"content": "Answer"})]
+        );
+    }
 }
@@ -2,14 +2,17 @@ mod claude2oai;
 mod request;
 mod response;
 mod stop_sequences;
+mod validate;
 
 pub(crate) use claude2oai::*;
+pub use claude2oai::{OaiResponseOptions, transform_stream, transforms_json};
 pub use request::*;
 pub use response::*;
 pub use stop_sequences::*;
+pub use validate::*;
 use strum::Display;
 
-use crate::types::claude::Usage;
+use crate::{format::ConversionReport, types::claude::Usage};
 
 /// Represents the format of the API response
 ///
@@ -60,6 +63,13 @@ impl ClaudeContext {
         }
     }
 
+    pub fn use_real_roles(&self) -> Option<bool> {
+        match self {
+            ClaudeContext::Web(ctx) => ctx.use_real_roles,
+            ClaudeContext::Code(_) => None,
+        }
+    }
+
     pub fn system_prompt_hash(&self) -> Option<u64> {
         match self {
             ClaudeContext::Web(_) => None,
@@ -73,4 +83,11 @@ impl ClaudeContext {
             ClaudeContext::Code(ctx) => &ctx.usage,
         }
     }
+
+    pub fn report(&self) -> &ConversionReport {
+        match self {
+            ClaudeContext::Web(ctx) => &ctx.report,
+            ClaudeContext::Code(ctx) => &ctx.report,
+        }
+    }
 }
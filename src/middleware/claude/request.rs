@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     hash::{DefaultHasher, Hash, Hasher},
     mem,
     sync::LazyLock,
@@ -13,21 +14,31 @@ use http::header::USER_AGENT;
 use serde_json::{Value, json};
 
 use crate::{
-    config::CLEWDR_CONFIG,
+    config::{CLEWDR_CONFIG, ClewdrConfig},
     error::ClewdrError,
     format::{
-        analyze_conversation_state, clean_cache_control_from_messages,
-        extract_signatures, get_thought_signature, has_valid_signature_for_function_calls,
-        message_has_tool_result, needs_thinking_recovery, process_image_blocks,
-        should_disable_thinking_due_to_history, strip_invalid_thinking_blocks,
+        ConversionReport, MAX_TOOL_RESULT_PART_BYTES, analyze_conversation_state,
+        apply_cache_breakpoints, apply_default_media_type, clean_cache_control_from_messages,
+        collect_image_sources, collect_tool_result_images, count_image_blocks, count_image_tokens,
+        count_image_urls, count_prefix_cache_breakpoints, dedup_system_blocks,
+        demote_thinking_to_text, drop_oldest_image_blocks, extract_signatures,
+        get_thought_signature, has_heif_image, has_valid_signature_for_function_calls,
+        message_has_tool_result, needs_thinking_recovery, normalize_system_cache_ttl,
+        process_image_blocks, should_disable_thinking_due_to_history, split_oversized_tool_results,
+        strip_invalid_thinking_blocks, strip_non_assistant_thinking, summarize_old_tool_results,
+    },
+    middleware::claude::{
+        ClaudeApiFormat, ClaudeContext, MIN_THINKING_BUDGET, normalize_stop_sequences,
+        validate_max_tokens, validate_request,
     },
-    middleware::claude::{ClaudeApiFormat, ClaudeContext},
     types::{
         claude::{
-            ContentBlock, CreateMessageParams, Message, MessageContent, Role, Thinking, Usage,
+            ContentBlock, CreateMessageParams, Message, MessageContent, Role, Thinking, ToolChoice,
+            ToolChoiceObject, ToolChoiceSimple, Usage,
         },
-        oai::OaiCreateMessageParams,
+        oai::{OaiCreateMessageParams, OaiRequestOptions},
     },
+    utils::{redact_for_log, redact_value_for_log},
 };
 
 /// A custom extractor that unifies different API formats
@@ -61,6 +72,22 @@ pub struct ClaudeWebContext {
     pub(super) stop_sequences: Vec<String>,
     /// User information about input and output tokens
     pub(super) usage: Usage,
+    /// What the request normalization changed or discarded
+    pub(super) report: ConversionReport,
+    /// Per-request override of the `use_real_roles` setting
+    pub(super) use_real_roles: Option<bool>,
+}
+
+/// Header that overrides the `use_real_roles` setting for a single request
+pub const REAL_ROLES_HEADER: &str = "x-clewdr-real-roles";
+
+/// Parses a boolean header value such as `true`, `1`, `off`
+fn parse_bool_header(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
 }
 
 /// Predefined test message in Claude format for connection testing
@@ -74,6 +101,7 @@ static TEST_MESSAGE_CLAUDE: LazyLock<Message> = LazyLock::new(|| {
         vec![ContentBlock::Text {
             text: "Hi".to_string(),
             cache_control: None,
+            citations: None,
         }],
     )
 });
@@ -81,7 +109,83 @@ static TEST_MESSAGE_CLAUDE: LazyLock<Message> = LazyLock::new(|| {
 /// Predefined test message in OpenAI format for connection testing
 static TEST_MESSAGE_OAI: LazyLock<Message> = LazyLock::new(|| Message::new_text(Role::User, "Hi"));
 
-struct NormalizeRequest(CreateMessageParams, ClaudeApiFormat);
+/// A parsed request body with the settings to normalize it
+struct NormalizeRequest(CreateMessageParams, ClaudeApiFormat, ConversionReport, NormalizeOptions);
+
+/// Drops thinking when the conversation history cannot carry it
+///
+/// # Returns
+/// true if thinking was requested but had to be disabled
+fn disable_thinking_for_history(body: &mut CreateMessageParams) -> bool {
+    if body.thinking.is_some() && should_disable_thinking_due_to_history(&body.messages) {
+        tracing::info!("[Format] Disabling thinking mode due to incompatible history");
+        body.thinking = None;
+        return true;
+    }
+    false
+}
+
+/// Clamps an enabled thinking budget to `[MIN_THINKING_BUDGET, max_budget]`
+fn clamp_thinking_budget(body: &mut CreateMessageParams, max_budget: Option<u64>) {
+    let Some(thinking) = body.thinking.as_mut().filter(|t| t.is_enabled()) else {
+        return;
+    };
+    let max_budget = max_budget.unwrap_or(u64::MAX).max(MIN_THINKING_BUDGET);
+    let clamped = thinking.budget_tokens.clamp(MIN_THINKING_BUDGET, max_budget);
+    if clamped != thinking.budget_tokens {
+        tracing::info!(
+            "[Format] Clamping thinking budget from {} to {}",
+            thinking.budget_tokens,
+            clamped
+        );
+        thinking.budget_tokens = clamped;
+    }
+}
+
+/// Treats an empty tools array as absent and drops a tool_choice without tools
+///
+/// Claude rejects a tool_choice when no tools are defined, and `tools: []`
+/// carries no more meaning than leaving the field out.
+///
+/// # Returns
+/// true if the tools or the tool_choice were changed
+fn drop_empty_tools(body: &mut CreateMessageParams) -> bool {
+    let mut changed = false;
+    if body.tools.as_ref().is_some_and(Vec::is_empty) {
+        body.tools = None;
+        changed = true;
+    }
+    if body.tools.is_none() && body.tool_choice.take().is_some() {
+        changed = true;
+    }
+    changed
+}
+
+/// Removes the tool definitions of a request whose tool_choice is `none`
+///
+/// The tools are kept when the history holds tool calls, since Claude rejects
+/// tool_use and tool_result blocks without tool definitions.
+///
+/// # Returns
+/// true if the tools were removed
+fn drop_tools_for_none_choice(body: &mut CreateMessageParams) -> bool {
+    let is_none = matches!(
+        body.tool_choice,
+        Some(
+            ToolChoice::Simple(ToolChoiceSimple::None) | ToolChoice::Object(ToolChoiceObject::None)
+        )
+    );
+    let has_tool_calls = body.messages.iter().any(|msg| match &msg.content {
+        MessageContent::Blocks { content } => content.iter().any(|block| {
+            matches!(block, ContentBlock::ToolUse { .. } | ContentBlock::ToolResult { .. })
+        }),
+        MessageContent::Text { .. } => false,
+    });
+    if !is_none || has_tool_calls {
+        return false;
+    }
+    body.tools.take().is_some()
+}
 
 fn sanitize_messages(msgs: Vec<Message>) -> Vec<Message> {
     msgs.into_iter()
@@ -104,7 +208,11 @@ fn sanitize_messages(msgs: Vec<Message>) -> Vec<Message> {
                                 if t.is_empty() {
                                     None
                                 } else {
-                                    Some(ContentBlock::Text { text: t, cache_control: None })
+                                    Some(ContentBlock::Text {
+                                        text: t,
+                                        cache_control: None,
+                                        citations: None,
+                                    })
                                 }
                             }
                             other => Some(other),
@@ -123,6 +231,59 @@ fn sanitize_messages(msgs: Vec<Message>) -> Vec<Message> {
         .collect()
 }
 
+/// Converts message content into blocks, dropping empty text
+fn into_blocks(content: MessageContent) -> Vec<ContentBlock> {
+    match content {
+        MessageContent::Text { content } if content.is_empty() => vec![],
+        MessageContent::Text { content } => vec![ContentBlock::Text {
+            text: content,
+            cache_control: None,
+            citations: None,
+        }],
+        MessageContent::Blocks { content } => content,
+    }
+}
+
+/// Merges adjacent messages that share a role into a single message
+///
+/// Claude requires user and assistant turns to alternate. Two text messages
+/// are joined with a blank line, otherwise their blocks are concatenated.
+fn coalesce_consecutive_roles(msgs: Vec<Message>) -> Vec<Message> {
+    let mut merged: Vec<Message> = Vec::with_capacity(msgs.len());
+    for msg in msgs {
+        let Some(last) = merged.last_mut().filter(|last| last.role == msg.role) else {
+            merged.push(msg);
+            continue;
+        };
+        let previous = mem::replace(&mut last.content, MessageContent::Blocks { content: vec![] });
+        last.content = match (previous, msg.content) {
+            (MessageContent::Text { content: a }, MessageContent::Text { content: b }) => {
+                MessageContent::Text {
+                    content: format!("{a}\n\n{b}"),
+                }
+            }
+            (a, b) => {
+                let mut content = into_blocks(a);
+                content.extend(into_blocks(b));
+                MessageContent::Blocks { content }
+            }
+        };
+    }
+    merged
+}
+
+/// Prepends a user turn when the conversation starts with the assistant
+///
+/// # Returns
+/// true if a placeholder user message was inserted
+fn prepend_user_turn(msgs: &mut Vec<Message>, placeholder: &str) -> bool {
+    if msgs.first().is_none_or(|m| m.role != Role::Assistant) {
+        return false;
+    }
+    msgs.insert(0, Message::new_text(Role::User, placeholder));
+    true
+}
+
 /// Validate tool_result/tool_use pairing in messages.
 ///
 /// Claude API requires every `tool_result` block's `tool_use_id` to have a matching
@@ -190,6 +351,358 @@ fn validate_tool_pairing(msgs: Vec<Message>) -> Vec<Message> {
         .collect()
 }
 
+/// Settings for the request normalization pipeline
+pub struct NormalizeOptions {
+    /// Send Claude-format requests nearly verbatim
+    strict_passthrough: bool,
+    /// Stop sequences added to every request
+    stop_sequences: Vec<String>,
+    /// Keep thinking as text instead of stripping unsigned blocks (web backend)
+    demote_thinking: bool,
+    /// Upper bound for an enabled thinking budget
+    max_thinking_budget: Option<u64>,
+    /// Media type for data URI images that do not specify one
+    default_media_type: String,
+    /// Client model names mapped to the Claude model to use
+    model_aliases: HashMap<String, String>,
+    /// Number of cache breakpoints placed on the last message blocks
+    cache_breakpoints: usize,
+    /// Merge adjacent messages that share a role
+    coalesce_roles: bool,
+    /// Text of the user turn prepended before a leading assistant message
+    leading_user_placeholder: String,
+    /// System prompt injected for the Claude Code backend instead of the default prelude
+    custom_system: Option<String>,
+    /// Inject the Claude Code prelude when the system prompt lacks it
+    inject_prelude: bool,
+    /// Number of recent tool results kept intact, older ones are elided
+    keep_tool_results: Option<usize>,
+    /// Model name patterns that reject `temperature` and `top_p` together
+    exclusive_sampling_models: Vec<String>,
+    /// Glob patterns of the models clients may request, any model when None
+    allowed_models: Option<Vec<String>>,
+    /// Remove the tool definitions when tool_choice is `none`
+    drop_tools_for_none_choice: bool,
+    /// Maximum number of images sent per request, unlimited if None
+    max_images: Option<usize>,
+    /// Reject requests over `max_images` instead of dropping the oldest images
+    reject_excess_images: bool,
+    /// Images are uploaded from the merged web prompt, which drops the excess itself
+    web_images: bool,
+    /// Data URI images in tool results are uploaded as images too
+    tool_result_images: bool,
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> Self {
+        Self::from_config(&ClewdrConfig::default(), ClaudeApiFormat::Claude, false)
+    }
+}
+
+impl NormalizeOptions {
+    pub fn from_config(config: &ClewdrConfig, format: ClaudeApiFormat, web_backend: bool) -> Self {
+        Self {
+            strict_passthrough: config.strict_passthrough && format == ClaudeApiFormat::Claude,
+            stop_sequences: config.stop_sequences.to_owned(),
+            demote_thinking: web_backend && config.demote_web_thinking,
+            max_thinking_budget: config.max_thinking_budget,
+            default_media_type: config.default_image_media_type.to_owned(),
+            model_aliases: config.model_aliases.to_owned(),
+            cache_breakpoints: config.cache_breakpoints,
+            coalesce_roles: config.coalesce_consecutive_roles,
+            leading_user_placeholder: config.leading_user_placeholder.to_owned(),
+            custom_system: config.custom_system.to_owned(),
+            inject_prelude: config.inject_claude_code_prelude,
+            keep_tool_results: config.keep_tool_results,
+            exclusive_sampling_models: config.exclusive_sampling_models.to_owned(),
+            allowed_models: config.allowed_models.to_owned(),
+            drop_tools_for_none_choice: config.drop_tools_for_none_choice,
+            max_images: config.max_images_per_request,
+            reject_excess_images: config.reject_excess_images,
+            web_images: web_backend,
+            tool_result_images: web_backend && config.tool_result_images,
+        }
+    }
+}
+
+/// Counts the images a request will send to the backend
+///
+/// The web backend uploads every collected image source, plus data URIs found
+/// in tool results when enabled. The API backends send the image blocks,
+/// including the ones nested in tool results.
+fn count_sent_images(messages: &[Message], options: &NormalizeOptions) -> usize {
+    if !options.web_images {
+        return count_image_blocks(messages);
+    }
+    let tool_result_images = if options.tool_result_images {
+        collect_tool_result_images(messages).len()
+    } else {
+        0
+    };
+    collect_image_sources(messages).len() + tool_result_images
+}
+
+/// Checks whether a model matches any pattern of models that reject
+/// `temperature` and `top_p` together, ignoring case
+fn is_exclusive_sampling_model(model: &str, patterns: &[String]) -> bool {
+    let model = model.to_ascii_lowercase();
+    patterns
+        .iter()
+        .any(|pattern| model.contains(&pattern.to_ascii_lowercase()))
+}
+
+/// Matches a model name against a glob pattern, ignoring case
+///
+/// `*` matches any run of characters and `?` matches a single character.
+fn model_matches_glob(pattern: &str, model: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_ascii_lowercase().chars().collect();
+    let model: Vec<char> = model.to_ascii_lowercase().chars().collect();
+    let (mut p, mut m) = (0, 0);
+    // Position of the last `*` and the model position it was tried at
+    let mut backtrack = None;
+    while m < model.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, m));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == model[m] => {
+                p += 1;
+                m += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character
+                Some((star, from)) => {
+                    backtrack = Some((star, from + 1));
+                    p = star + 1;
+                    m = from + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Checks that a resolved model is allowed by the configured allowlist
+///
+/// # Errors
+/// If an allowlist is configured and no pattern matches the model
+fn check_allowed_model(model: &str, allowed: Option<&[String]>) -> Result<(), ClewdrError> {
+    let Some(patterns) = allowed else {
+        return Ok(());
+    };
+    if patterns.iter().any(|pattern| model_matches_glob(pattern, model)) {
+        return Ok(());
+    }
+    tracing::warn!("[Format] Rejected model {} not in allowed_models", model);
+    Err(ClewdrError::BadRequest {
+        msg: "Requested model is not allowed",
+    })
+}
+
+/// Longest model name accepted from clients
+const MAX_MODEL_NAME_LEN: usize = 256;
+
+/// Strips the `-thinking` model suffix and enables thinking for it
+///
+/// Only a single suffix is removed, so `foo-thinking-thinking` selects the
+/// model `foo-thinking`.
+///
+/// # Errors
+/// If the model name is too long or nothing is left after the suffix
+fn apply_thinking_suffix(body: &mut CreateMessageParams) -> Result<(), ClewdrError> {
+    if body.model.len() > MAX_MODEL_NAME_LEN {
+        return Err(ClewdrError::BadRequest {
+            msg: "Model name is too long",
+        });
+    }
+    let Some(model) = body.model.strip_suffix("-thinking") else {
+        return Ok(());
+    };
+    if model.trim().is_empty() {
+        return Err(ClewdrError::BadRequest {
+            msg: "Model name is empty after removing the -thinking suffix",
+        });
+    }
+    body.model = model.to_string();
+    body.thinking.get_or_insert(Thinking::new(4096));
+    Ok(())
+}
+
+/// Normalizes a parsed request body before it is dispatched
+///
+/// In strict passthrough mode only cache_control cleaning, stop sequence
+/// merging and the `-thinking` model suffix are applied.
+///
+/// # Errors
+/// If the requested model name is invalid
+fn normalize_body(
+    body: &mut CreateMessageParams,
+    options: &NormalizeOptions,
+    report: &mut ConversionReport,
+) -> Result<(), ClewdrError> {
+    if !options.strict_passthrough {
+        if options.drop_tools_for_none_choice && drop_tools_for_none_choice(body) {
+            tracing::debug!("[Format] Removed tools since tool_choice is none");
+        }
+        if drop_empty_tools(body) {
+            tracing::debug!("[Format] Removed empty tools and dangling tool_choice");
+        }
+
+        // Claude rejects thinking blocks outside of assistant turns
+        let stripped = strip_non_assistant_thinking(&mut body.messages);
+        if stripped > 0 {
+            tracing::debug!(
+                "[Format] Removed {} thinking blocks outside assistant turns",
+                stripped
+            );
+        }
+
+        // Sanitize messages: trim whitespace and drop whitespace-only assistant turns
+        body.messages = sanitize_messages(mem::take(&mut body.messages));
+
+        // Validate tool_result/tool_use pairing: remove orphaned tool_result blocks
+        body.messages = validate_tool_pairing(mem::take(&mut body.messages));
+
+        // Dropped turns may leave two messages with the same role next to each other
+        if options.coalesce_roles {
+            body.messages = coalesce_consecutive_roles(mem::take(&mut body.messages));
+        }
+
+        // The first message must come from the user
+        if prepend_user_turn(&mut body.messages, &options.leading_user_placeholder) {
+            tracing::debug!("[Format] Prepended a user turn before the leading assistant message");
+        }
+
+        // Process image_url blocks in messages (OpenAI -> Claude conversion)
+        for msg in body.messages.iter_mut() {
+            if let MessageContent::Blocks { content } = &mut msg.content {
+                let image_urls = count_image_urls(content);
+                *content = process_image_blocks(mem::take(content), &options.default_media_type);
+                report.images_converted += image_urls.saturating_sub(count_image_urls(content));
+                apply_default_media_type(content, &options.default_media_type);
+                if has_heif_image(content) {
+                    return Err(ClewdrError::BadRequest {
+                        msg: "HEIC/HEIF images are not supported, convert them to JPEG or PNG",
+                    });
+                }
+            }
+        }
+
+        if let Some(max_images) = options.max_images {
+            let images = count_sent_images(&body.messages, options);
+            if images > max_images && options.reject_excess_images {
+                return Err(ClewdrError::BadRequest {
+                    msg: "Too many images in the request",
+                });
+            }
+            if images > max_images && !options.web_images {
+                let dropped = drop_oldest_image_blocks(&mut body.messages, max_images);
+                tracing::warn!(
+                    "[Format] Dropped {} oldest images over the limit of {}",
+                    dropped,
+                    max_images
+                );
+            }
+        }
+
+        // Merged system messages often repeat the same text block
+        if let Some(system) = body.system.as_mut() {
+            dedup_system_blocks(system);
+        }
+
+        // Long tool loops only need the most recent outputs in full
+        if let Some(keep_last) = options.keep_tool_results {
+            let elided = summarize_old_tool_results(&mut body.messages, keep_last);
+            if elided > 0 {
+                tracing::debug!("[Format] Elided {} older tool results", elided);
+            }
+        }
+
+        // Split oversized tool outputs so no single text part exceeds provider limits
+        let split = split_oversized_tool_results(&mut body.messages, MAX_TOOL_RESULT_PART_BYTES);
+        if split > 0 {
+            tracing::debug!("[Format] Split {} oversized tool results", split);
+        }
+    }
+
+    // Clean cache_control from historical messages (prevents API errors)
+    clean_cache_control_from_messages(&mut body.messages);
+    if options.cache_breakpoints > 0 && !options.strict_passthrough {
+        let reserved = count_prefix_cache_breakpoints(body.system.as_ref(), body.tools.as_deref());
+        let placed =
+            apply_cache_breakpoints(&mut body.messages, options.cache_breakpoints, reserved);
+        tracing::debug!("[Format] Placed {} cache breakpoints", placed);
+    }
+
+    // Merge client stop sequences with configured defaults
+    let stop_sequences = normalize_stop_sequences(
+        body.stop_sequences.take().unwrap_or_default(),
+        options.stop_sequences.to_owned(),
+    );
+    body.stop_sequences = (!stop_sequences.is_empty()).then_some(stop_sequences);
+
+    // Handle thinking mode, then map client model names to Claude models
+    apply_thinking_suffix(body)?;
+    if let Some(alias) = options.model_aliases.get(&body.model) {
+        tracing::debug!("[Format] Model alias {} -> {}", body.model, alias);
+        body.model = alias.to_owned();
+        // An alias may itself select thinking mode
+        apply_thinking_suffix(body)?;
+    }
+    check_allowed_model(&body.model, options.allowed_models.as_deref())?;
+
+    if options.strict_passthrough {
+        tracing::debug!("[Format] Strict passthrough, skipping thinking normalization");
+        return Ok(());
+    }
+
+    clamp_thinking_budget(body, options.max_thinking_budget);
+
+    // Check if thinking should be disabled due to conversation history
+    report.thinking_disabled = disable_thinking_for_history(body);
+
+    if options.demote_thinking {
+        // Signatures are useless on the web backend, keep the reasoning as text
+        demote_thinking_to_text(&mut body.messages, true);
+    } else {
+        // Strip invalid thinking blocks from history
+        strip_invalid_thinking_blocks(&mut body.messages);
+    }
+
+    // Analyze conversation state
+    let state = analyze_conversation_state(&body.messages);
+    if state.in_tool_loop {
+        tracing::debug!("[Format] In tool loop with {} results", state.tool_result_count);
+    }
+
+    // Log tool result status for debugging
+    if let Some(last_user) = body.messages.iter().rev().find(|m| m.role == Role::User) {
+        if message_has_tool_result(last_user) {
+            tracing::debug!("[Format] Last user message contains tool result");
+        }
+    }
+
+    // Extract and log all signatures for debugging
+    let signatures = extract_signatures(&body.messages);
+    if !signatures.is_empty() {
+        tracing::debug!("[Format] Found {} signatures in history", signatures.len());
+    }
+
+    // Check if thinking recovery is needed
+    if body.thinking.is_some() && needs_thinking_recovery(&body.messages) {
+        let global_sig = get_thought_signature();
+        if has_valid_signature_for_function_calls(&body.messages, &global_sig) {
+            tracing::debug!("[Format] Valid signature available for thinking recovery");
+            report.thinking_recovered = true;
+        } else {
+            tracing::warn!("[Format] Thinking recovery needed but no valid signature found");
+        }
+    }
+    Ok(())
+}
+
 impl<S> FromRequest<S> for NormalizeRequest
 where
     S: Send + Sync,
@@ -203,20 +716,38 @@ where
         } else {
             ClaudeApiFormat::Claude
         };
+        let web_backend = !uri.contains("/code/");
         
         // Extract raw bytes first for debugging
         let bytes = axum::body::Bytes::from_request(req, &()).await
             .map_err(|e| ClewdrError::InternalError { msg: format!("Failed to read body: {e}") })?;
         
+        let mut report = ConversionReport::default();
+
         // Parse JSON based on format
         let Json(mut body) = match format {
             ClaudeApiFormat::OpenAI => {
                 match serde_json::from_slice::<OaiCreateMessageParams>(&bytes) {
-                    Ok(json) => Json(json.into()),
+                    Ok(json) => {
+                        json.check_modalities()?;
+                        let options =
+                            OaiRequestOptions::from_config(&CLEWDR_CONFIG.load(), web_backend);
+                        let (params, conversion_report) = json.convert_with_report(&options);
+                        report = conversion_report;
+                        if report.penalties_ignored() {
+                            tracing::info!(
+                                "[Format] Ignoring unsupported penalties: frequency={:?}, presence={:?}",
+                                report.frequency_penalty,
+                                report.presence_penalty
+                            );
+                        }
+                        Json(params)
+                    }
                     Err(e) => {
                         // Save raw request for debugging
                         let debug_path = "log/debug_raw_request.json";
-                        if let Ok(json_value) = serde_json::from_slice::<serde_json::Value>(&bytes) {
+                        if let Ok(mut json_value) = serde_json::from_slice::<serde_json::Value>(&bytes) {
+                            redact_value_for_log(&mut json_value, None);
                             let _ = std::fs::write(debug_path, serde_json::to_string_pretty(&json_value).unwrap_or_default());
                             tracing::error!("[DEBUG] Saved raw request to {} - Error: {}", debug_path, e);
                         } else {
@@ -232,7 +763,8 @@ where
                     Ok(json) => Json(json),
                     Err(e) => {
                         let debug_path = "log/debug_raw_request.json";
-                        if let Ok(json_value) = serde_json::from_slice::<serde_json::Value>(&bytes) {
+                        if let Ok(mut json_value) = serde_json::from_slice::<serde_json::Value>(&bytes) {
+                            redact_value_for_log(&mut json_value, None);
                             let _ = std::fs::write(debug_path, serde_json::to_string_pretty(&json_value).unwrap_or_default());
                             tracing::error!("[DEBUG] Saved raw request to {} - Error: {}", debug_path, e);
                         } else {
@@ -244,76 +776,55 @@ where
                 }
             }
         };
-        // Sanitize messages: trim whitespace and drop whitespace-only assistant turns
-        body.messages = sanitize_messages(body.messages);
+        let options = NormalizeOptions::from_config(&CLEWDR_CONFIG.load(), format, web_backend);
 
-        // Validate tool_result/tool_use pairing: remove orphaned tool_result blocks
-        body.messages = validate_tool_pairing(body.messages);
+        Ok(Self(body, format, report, options))
+    }
+}
 
-        // Process image_url blocks in messages (OpenAI -> Claude conversion)
-        body.messages = body
-            .messages
-            .into_iter()
-            .map(|mut msg| {
-                if let MessageContent::Blocks { content } = msg.content {
-                    // Use process_image_blocks for conversion
-                    msg.content = MessageContent::Blocks {
-                        content: process_image_blocks(content),
-                    };
-                }
-                msg
-            })
-            .collect();
-        
-        // Clean cache_control from historical messages (prevents API errors)
-        clean_cache_control_from_messages(&mut body.messages);
-        
-        // Handle thinking mode
-        if body.model.ends_with("-thinking") {
-            body.model = body.model.trim_end_matches("-thinking").to_string();
-            body.thinking.get_or_insert(Thinking::new(4096));
-        }
-        
-        // Check if thinking should be disabled due to conversation history
-        if body.thinking.is_some() && should_disable_thinking_due_to_history(&body.messages) {
-            tracing::info!("[Format] Disabling thinking mode due to incompatible history");
-            body.thinking = None;
-        }
-        
-        // Strip invalid thinking blocks from history
-        strip_invalid_thinking_blocks(&mut body.messages);
-        
-        // Analyze conversation state
-        let state = analyze_conversation_state(&body.messages);
-        if state.in_tool_loop {
-            tracing::debug!("[Format] In tool loop with {} results", state.tool_result_count);
-        }
-        
-        // Log tool result status for debugging
-        if let Some(last_user) = body.messages.iter().rev().find(|m| m.role == Role::User) {
-            if message_has_tool_result(last_user) {
-                tracing::debug!("[Format] Last user message contains tool result");
-            }
-        }
-        
-        // Extract and log all signatures for debugging
-        let signatures = extract_signatures(&body.messages);
-        if !signatures.is_empty() {
-            tracing::debug!("[Format] Found {} signatures in history", signatures.len());
-        }
-        
-        // Check if thinking recovery is needed
-        if body.thinking.is_some() && needs_thinking_recovery(&body.messages) {
-            let global_sig = get_thought_signature();
-            if has_valid_signature_for_function_calls(&body.messages, &global_sig) {
-                tracing::debug!("[Format] Valid signature available for thinking recovery");
-            } else {
-                tracing::warn!("[Format] Thinking recovery needed but no valid signature found");
-            }
-        }
-        
-        Ok(Self(body, format))
+/// Checks for the connection test message sent by clients like SillyTavern
+fn is_test_message(body: &CreateMessageParams) -> bool {
+    !body.stream.unwrap_or_default()
+        && (body.messages == vec![TEST_MESSAGE_CLAUDE.to_owned()]
+            || body.messages == vec![TEST_MESSAGE_OAI.to_owned()])
+}
+
+/// Normalizes a parsed request for the claude.ai web backend
+///
+/// This is the transformation behind [`ClaudeWebPreprocess`], free of any
+/// request extraction or global configuration.
+///
+/// # Errors
+/// [`ClewdrError::TestMessage`] if the body is a client connection test, or a
+/// validation error if the normalized request is invalid
+pub fn preprocess_web(
+    mut body: CreateMessageParams,
+    format: ClaudeApiFormat,
+    options: &NormalizeOptions,
+    mut report: ConversionReport,
+) -> Result<(CreateMessageParams, ClaudeWebContext), ClewdrError> {
+    normalize_body(&mut body, options, &mut report)?;
+    validate_request(&body)?;
+
+    // Check for test messages and respond appropriately
+    if is_test_message(&body) {
+        return Err(ClewdrError::TestMessage);
     }
+
+    let input_tokens = body.count_tokens() + count_image_tokens(&body.messages);
+    let info = ClaudeWebContext {
+        stream: body.stream.unwrap_or_default(),
+        api_format: format,
+        stop_sequences: body.stop_sequences.to_owned().unwrap_or_default(),
+        usage: Usage {
+            input_tokens,
+            output_tokens: 0, // Placeholder for output token count
+            ..Default::default()
+        },
+        report,
+        use_real_roles: None,
+    };
+    Ok((body, info))
 }
 
 impl<S> FromRequest<S> for ClaudeWebPreprocess
@@ -323,30 +834,16 @@ where
     type Rejection = ClewdrError;
 
     async fn from_request(req: Request, _: &S) -> Result<Self, Self::Rejection> {
-        let NormalizeRequest(body, format) = NormalizeRequest::from_request(req, &()).await?;
-
-        // Check for test messages and respond appropriately
-        if !body.stream.unwrap_or_default()
-            && (body.messages == vec![TEST_MESSAGE_CLAUDE.to_owned()]
-                || body.messages == vec![TEST_MESSAGE_OAI.to_owned()])
-        {
-            // Respond with a test message
-            return Err(ClewdrError::TestMessage);
-        }
+        let use_real_roles = req
+            .headers()
+            .get(REAL_ROLES_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_bool_header);
+        let NormalizeRequest(body, format, report, options) =
+            NormalizeRequest::from_request(req, &()).await?;
 
-        // Determine streaming status and API format
-        let stream = body.stream.unwrap_or_default();
-
-        let input_tokens = body.count_tokens();
-        let info = ClaudeWebContext {
-            stream,
-            api_format: format,
-            stop_sequences: body.stop_sequences.to_owned().unwrap_or_default(),
-            usage: Usage {
-                input_tokens,
-                output_tokens: 0, // Placeholder for output token count
-            },
-        };
+        let (body, mut info) = preprocess_web(body, format, &options, report)?;
+        info.use_real_roles = use_real_roles;
 
         Ok(Self(body, ClaudeContext::Web(info)))
     }
@@ -362,6 +859,153 @@ pub struct ClaudeCodeContext {
     pub(super) system_prompt_hash: Option<u64>,
     // Usage information for the request
     pub(super) usage: Usage,
+    /// What the request normalization changed or discarded
+    pub(super) report: ConversionReport,
+}
+
+/// Hashes the cached blocks of a system prompt
+///
+/// Blocks are compared by their serialized form and sorted first, so the same
+/// set of blocks hashes equally regardless of order.
+///
+/// # Returns
+/// None if no system block carries cache_control
+fn system_prompt_hash(system: Option<&Value>) -> Option<u64> {
+    // Only array system prompts can carry cache_control blocks
+    let mut cache_systems = system
+        .and_then(Value::as_array)
+        .map(|systems| {
+            systems
+                .iter()
+                .filter(|s| s["cache_control"].as_object().is_some())
+                .map(Value::to_string)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    if cache_systems.is_empty() {
+        return None;
+    }
+    cache_systems.sort_unstable();
+    let mut hasher = DefaultHasher::new();
+    cache_systems.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Normalizes a parsed request for the Claude Code backend
+///
+/// This is the transformation behind [`ClaudeCodePreprocess`], free of any
+/// request extraction or global configuration. It also injects the Claude
+/// Code prelude into the system prompt.
+///
+/// # Errors
+/// [`ClewdrError::TestMessage`] for a client connection test, or a validation
+/// error if the normalized request is invalid
+pub fn preprocess_code(
+    mut body: CreateMessageParams,
+    format: ClaudeApiFormat,
+    options: &NormalizeOptions,
+    mut report: ConversionReport,
+) -> Result<(CreateMessageParams, ClaudeCodeContext), ClewdrError> {
+    normalize_body(&mut body, options, &mut report)?;
+
+    // temperature and top_p cannot be used together on these models
+    if body.temperature.is_some()
+        && body.top_p.is_some()
+        && is_exclusive_sampling_model(&body.model, &options.exclusive_sampling_models)
+    {
+        tracing::debug!("[Format] Removing top_p as {} rejects it with temperature", body.model);
+        body.top_p = None;
+    }
+
+    // Check for test messages and respond appropriately
+    if is_test_message(&body) {
+        return Err(ClewdrError::TestMessage);
+    }
+
+    // Determine streaming status and API format
+    let stream = body.stream.unwrap_or_default();
+
+    // Check if system prompt already contains Claude Code identifier
+    // The official Claude Code system prompt contains: "You are an agent for Claude Code"
+    let has_claude_code_system = match &body.system {
+        Some(Value::String(s)) => s.contains("Claude Code"),
+        Some(Value::Array(arr)) => arr.iter().any(|v| {
+            v.get("text")
+                .and_then(|t| t.as_str())
+                .map(|s| s.contains("Claude Code"))
+                .unwrap_or(false)
+        }),
+        _ => false,
+    };
+
+    tracing::info!("[CLAUDE_CODE_PREPROCESS] Has Claude Code system prompt: {}", has_claude_code_system);
+
+    // Add Claude Code prelude if not already present
+    // This is required for Claude Code API to work correctly
+    // NOTE: The system prompt MUST contain "Claude Code, Anthropic's official CLI for Claude"
+    // for the OAuth token to be accepted by Anthropic API
+    if !has_claude_code_system && !options.inject_prelude {
+        tracing::info!("[CLAUDE_CODE_PREPROCESS] Prelude injection disabled by config");
+    } else if !has_claude_code_system {
+        const PRELUDE_TEXT: &str = "You are Claude Code, Anthropic's official CLI for Claude.";
+        let prelude_blk = ContentBlock::Text {
+            text: options
+                .custom_system
+                .clone()
+                .unwrap_or_else(|| PRELUDE_TEXT.to_string()),
+            cache_control: None,
+            citations: None,
+        };
+        tracing::info!("[CLAUDE_CODE_PREPROCESS] Injecting Claude Code prelude system prompt");
+        match body.system {
+            Some(Value::String(ref text)) => {
+                let text_content = ContentBlock::Text {
+                    text: text.to_owned(),
+                    cache_control: None,
+                    citations: None,
+                };
+                body.system = Some(json!([prelude_blk, text_content]));
+            }
+            Some(Value::Array(ref mut a)) => {
+                a.insert(0, json!(prelude_blk));
+            }
+            _ => {
+                body.system = Some(json!([prelude_blk]));
+            }
+        }
+    }
+
+    // Log the final system prompt after processing
+    if let Some(ref system) = body.system {
+        let system_str = system.to_string();
+        tracing::debug!("[CLAUDE_CODE_PREPROCESS] Final system prompt length: {} chars", system_str.len());
+    }
+
+    validate_request(&body)?;
+    validate_max_tokens(&body)?;
+
+    // Cache breakpoints on the system prompt are kept, so make their TTLs acceptable
+    if let Some(system) = body.system.as_mut() {
+        normalize_system_cache_ttl(system);
+    }
+
+    let system_prompt_hash = system_prompt_hash(body.system.as_ref());
+
+    let input_tokens = body.count_tokens() + count_image_tokens(&body.messages);
+
+    let info = ClaudeCodeContext {
+        stream,
+        api_format: format,
+        system_prompt_hash,
+        usage: Usage {
+            input_tokens,
+            output_tokens: 0, // Placeholder for output token count
+            ..Default::default()
+        },
+        report,
+    };
+
+    Ok((body, info))
 }
 
 pub struct ClaudeCodePreprocess(pub CreateMessageParams, pub ClaudeContext);
@@ -385,10 +1029,13 @@ where
         tracing::info!("[CLAUDE_CODE_PREPROCESS] User-Agent: {}", ua);
         tracing::info!("[CLAUDE_CODE_PREPROCESS] Is from Claude Code client: {}", is_from_cc);
 
-        let NormalizeRequest(mut body, format) = NormalizeRequest::from_request(req, &()).await?;
+        let NormalizeRequest(body, format, report, options) =
+            NormalizeRequest::from_request(req, &()).await?;
 
         // Log the incoming request body for debugging
-        if let Ok(json_str) = serde_json::to_string_pretty(&body) {
+        if !CLEWDR_CONFIG.load().no_fs
+            && let Ok(json_str) = serde_json::to_string_pretty(&redact_for_log(&body, None))
+        {
             let log_path = "log/claude_code_incoming_request.json";
             if let Err(e) = std::fs::write(log_path, &json_str) {
                 tracing::warn!("[CLAUDE_CODE_PREPROCESS] Failed to write incoming request log: {}", e);
@@ -397,121 +1044,693 @@ where
             }
         }
 
-        // Handle thinking mode by modifying the model name
-        if (body.model.contains("opus-4-1")
-            || body.model.contains("sonnet-4-5")
-            || body.model.contains("opus-4-5"))
-            && body.temperature.is_some()
+        let (body, info) = preprocess_code(body, format, &options, report)?;
+
+        // Save the processed request (with injected system prompt) for debugging
+        if !CLEWDR_CONFIG.load().no_fs
+            && let Ok(json_str) = serde_json::to_string_pretty(&redact_for_log(&body, None))
         {
-            body.top_p = None; // temperature and top_p cannot be used together in Opus-4-1
+            let log_path = "log/claude_code_processed_request.json";
+            if let Err(e) = std::fs::write(log_path, &json_str) {
+                tracing::warn!("[CLAUDE_CODE_PREPROCESS] Failed to write processed request log: {}", e);
+            } else {
+                tracing::info!("[CLAUDE_CODE_PREPROCESS] Processed request saved to {}", log_path);
+            }
         }
 
-        // Check for test messages and respond appropriately
-        if !body.stream.unwrap_or_default()
-            && (body.messages == vec![TEST_MESSAGE_CLAUDE.to_owned()]
-                || body.messages == vec![TEST_MESSAGE_OAI.to_owned()])
-        {
-            // Respond with a test message
-            return Err(ClewdrError::TestMessage);
+        Ok(Self(body, ClaudeContext::Code(info)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_thinking_disabled_for_tool_use_without_thinking() {
+        let mut body: CreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "thinking": {"type": "enabled", "budget_tokens": 4096},
+            "messages": [
+                {"role": "user", "content": "list files"},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "toolu_01", "name": "LS", "input": {}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_01", "content": "a.txt"}
+                ]}
+            ]
+        }))
+        .unwrap();
+
+        assert!(disable_thinking_for_history(&mut body));
+        assert!(body.thinking.is_none());
+
+        // Nothing to report when thinking was never requested
+        assert!(!disable_thinking_for_history(&mut body));
+    }
+
+    #[test]
+    fn test_report_records_image_conversion() {
+        let mut body: CreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "thinking": {"type": "enabled", "budget_tokens": 4096},
+            "messages": [
+                {"role": "user", "content": "hi"},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "unsigned reasoning"},
+                    {"type": "text", "text": "hello"}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "describe this"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}}
+                ]}
+            ]
+        }))
+        .unwrap();
+        let mut report = ConversionReport::default();
+
+        normalize_body(&mut body, &NormalizeOptions::default(), &mut report).unwrap();
+        // Stripping the unsigned block is not a recovery
+        assert!(!report.thinking_recovered);
+        assert!(!report.thinking_disabled);
+        assert_eq!(report.images_converted, 1);
+    }
+
+    #[test]
+    fn test_thinking_budget_clamped() {
+        let mut body = CreateMessageParams {
+            thinking: Some(Thinking::new(100_000)),
+            ..Default::default()
+        };
+        clamp_thinking_budget(&mut body, Some(16_000));
+        assert_eq!(body.thinking.as_ref().unwrap().budget_tokens, 16_000);
+
+        body.thinking = Some(Thinking::new(256));
+        clamp_thinking_budget(&mut body, Some(16_000));
+        assert_eq!(body.thinking.as_ref().unwrap().budget_tokens, MIN_THINKING_BUDGET);
+
+        // Without a configured maximum only the floor applies
+        body.thinking = Some(Thinking::new(100_000));
+        clamp_thinking_budget(&mut body, None);
+        assert_eq!(body.thinking.as_ref().unwrap().budget_tokens, 100_000);
+    }
+
+    #[test]
+    fn test_strict_passthrough_keeps_thinking_blocks() {
+        let body = || -> CreateMessageParams {
+            serde_json::from_value(json!({
+                "model": "claude-sonnet-4-5",
+                "messages": [
+                    {"role": "user", "content": "hi"},
+                    {"role": "assistant", "content": [
+                        {"type": "thinking", "thinking": "  pondering  ", "signature": "short"},
+                        {"type": "text", "text": "hello"}
+                    ]},
+                    {"role": "user", "content": "again"}
+                ]
+            }))
+            .unwrap()
+        };
+        let thinking_blocks = |body: &CreateMessageParams| -> Vec<ContentBlock> {
+            body.messages
+                .iter()
+                .filter_map(|m| match &m.content {
+                    MessageContent::Blocks { content } => Some(content.to_owned()),
+                    _ => None,
+                })
+                .flatten()
+                .filter(|b| matches!(b, ContentBlock::Thinking { .. }))
+                .collect()
+        };
+
+        let passthrough = NormalizeOptions {
+            strict_passthrough: true,
+            ..Default::default()
+        };
+        let mut kept = body();
+        normalize_body(&mut kept, &passthrough, &mut ConversionReport::default()).unwrap();
+        match thinking_blocks(&kept).as_slice() {
+            [ContentBlock::Thinking {
+                thinking,
+                signature,
+                ..
+            }] => {
+                assert_eq!(thinking, "  pondering  ");
+                assert_eq!(signature.as_deref(), Some("short"));
+            }
+            other => panic!("Expected the thinking block to survive, got {:?}", other),
         }
 
-        // Determine streaming status and API format
-        let stream = body.stream.unwrap_or_default();
-
-        // Check if system prompt already contains Claude Code identifier
-        // The official Claude Code system prompt contains: "You are an agent for Claude Code"
-        let has_claude_code_system = match &body.system {
-            Some(Value::String(s)) => s.contains("Claude Code"),
-            Some(Value::Array(arr)) => arr.iter().any(|v| {
-                v.get("text")
-                    .and_then(|t| t.as_str())
-                    .map(|s| s.contains("Claude Code"))
-                    .unwrap_or(false)
-            }),
-            _ => false,
-        };
-
-        tracing::info!("[CLAUDE_CODE_PREPROCESS] Has Claude Code system prompt: {}", has_claude_code_system);
-
-        // Add Claude Code prelude if not already present
-        // This is required for Claude Code API to work correctly
-        // NOTE: The system prompt MUST contain "Claude Code, Anthropic's official CLI for Claude"
-        // for the OAuth token to be accepted by Anthropic API
-        if !has_claude_code_system {
-            const PRELUDE_TEXT: &str = "You are Claude Code, Anthropic's official CLI for Claude.";
-            let prelude_blk = ContentBlock::Text {
-                text: CLEWDR_CONFIG
-                    .load()
-                    .custom_system
-                    .clone()
-                    .unwrap_or_else(|| PRELUDE_TEXT.to_string()),
-                cache_control: None,
-            };
-            tracing::info!("[CLAUDE_CODE_PREPROCESS] Injecting Claude Code prelude system prompt");
-            match body.system {
-                Some(Value::String(ref text)) => {
-                    let text_content = ContentBlock::Text {
-                        text: text.to_owned(),
-                        cache_control: None,
-                    };
-                    body.system = Some(json!([prelude_blk, text_content]));
-                }
-                Some(Value::Array(ref mut a)) => {
-                    a.insert(0, json!(prelude_blk));
+        let normal = NormalizeOptions::default();
+        let mut stripped = body();
+        normalize_body(&mut stripped, &normal, &mut ConversionReport::default()).unwrap();
+        assert!(thinking_blocks(&stripped).is_empty());
+    }
+
+    #[test]
+    fn test_coalesce_consecutive_user_messages() {
+        let messages = vec![
+            Message::new_text(Role::User, "first"),
+            Message::new_text(Role::User, "second"),
+            Message::new_text(Role::Assistant, "reply"),
+        ];
+
+        let merged = coalesce_consecutive_roles(messages);
+        assert_eq!(merged.len(), 2);
+        match &merged[0].content {
+            MessageContent::Text { content } => assert_eq!(content, "first\n\nsecond"),
+            other => panic!("Expected text content, got {:?}", other),
+        }
+        assert_eq!(merged[1].role, Role::Assistant);
+    }
+
+    #[test]
+    fn test_coalesce_consecutive_assistant_messages() {
+        let messages = vec![
+            Message::new_text(Role::User, "hi"),
+            Message::new_text(Role::Assistant, "let me check"),
+            Message::new_blocks(
+                Role::Assistant,
+                vec![ContentBlock::ToolUse {
+                    id: "toolu_01".to_string(),
+                    name: "LS".to_string(),
+                    input: json!({}),
+                    signature: None,
+                    cache_control: None,
+                }],
+            ),
+        ];
+
+        let merged = coalesce_consecutive_roles(messages);
+        assert_eq!(merged.len(), 2);
+        match &merged[1].content {
+            MessageContent::Blocks { content } => match content.as_slice() {
+                [ContentBlock::Text { text, .. }, ContentBlock::ToolUse { id, .. }] => {
+                    assert_eq!(text, "let me check");
+                    assert_eq!(id, "toolu_01");
                 }
-                _ => {
-                    body.system = Some(json!([prelude_blk]));
+                other => panic!("Unexpected blocks: {:?}", other),
+            },
+            other => panic!("Expected block content, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_leading_assistant_gets_user_turn() {
+        let options = NormalizeOptions {
+            leading_user_placeholder: "(start)".to_string(),
+            ..Default::default()
+        };
+        let mut body = CreateMessageParams {
+            messages: vec![
+                Message::new_text(Role::Assistant, "Hello, how can I help?"),
+                Message::new_text(Role::User, "Summarize this"),
+            ],
+            ..Default::default()
+        };
+        normalize_body(&mut body, &options, &mut ConversionReport::default()).unwrap();
+
+        let roles: Vec<_> = body.messages.iter().map(|m| m.role).collect();
+        assert_eq!(roles, vec![Role::User, Role::Assistant, Role::User]);
+        match &body.messages[0].content {
+            MessageContent::Text { content } => assert_eq!(content, "(start)"),
+            other => panic!("Expected text content, got {:?}", other),
+        }
+
+        // Conversations that already start with the user are untouched
+        assert!(!prepend_user_turn(&mut body.messages, "(start)"));
+        assert_eq!(body.messages.len(), 3);
+    }
+
+    #[test]
+    fn test_preprocess_web() {
+        let body: CreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5-thinking",
+            "stream": true,
+            "messages": [
+                {"role": "user", "content": "  describe this  "},
+                {"role": "assistant", "content": "   "},
+                {"role": "user", "content": [
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}}
+                ]}
+            ]
+        }))
+        .unwrap();
+        let options = NormalizeOptions {
+            coalesce_roles: true,
+            ..Default::default()
+        };
+
+        let (body, info) =
+            preprocess_web(body, ClaudeApiFormat::Claude, &options, ConversionReport::default())
+                .unwrap();
+        assert!(info.stream);
+        assert_eq!(body.model, "claude-sonnet-4-5");
+        assert!(body.thinking.is_some());
+        // The blank assistant turn is dropped and the two user turns merged
+        assert_eq!(body.messages.len(), 1);
+        match &body.messages[0].content {
+            MessageContent::Blocks { content } => match content.as_slice() {
+                [ContentBlock::Text { text, .. }, ContentBlock::Image { source, .. }] => {
+                    assert_eq!(text, "describe this");
+                    assert_eq!(source.media_type, "image/png");
                 }
-            }
+                other => panic!("Unexpected blocks: {:?}", other),
+            },
+            other => panic!("Expected block content, got {:?}", other),
         }
+    }
+
+    #[test]
+    fn test_preprocess_detects_test_message() {
+        let body = CreateMessageParams {
+            messages: vec![TEST_MESSAGE_OAI.to_owned()],
+            ..Default::default()
+        };
+        let result = preprocess_web(
+            body,
+            ClaudeApiFormat::OpenAI,
+            &NormalizeOptions::default(),
+            ConversionReport::default(),
+        );
+        assert!(matches!(result, Err(ClewdrError::TestMessage)));
+    }
+
+    #[test]
+    fn test_preprocess_web_validates_request() {
+        let preprocess = |body: Value| {
+            preprocess_web(
+                serde_json::from_value(body).unwrap(),
+                ClaudeApiFormat::Claude,
+                &NormalizeOptions::default(),
+                ConversionReport::default(),
+            )
+        };
+
+        let empty = preprocess(json!({"model": "claude-sonnet-4-5"}));
+        assert!(matches!(
+            empty,
+            Err(ClewdrError::BadRequest { msg: "Messages must not be empty" })
+        ));
+
+        // The web backend ignores max_tokens, so a larger default budget is fine
+        let budget = preprocess(json!({
+            "model": "claude-sonnet-4-5-thinking",
+            "max_tokens": 1024,
+            "messages": [{"role": "user", "content": "hello"}]
+        }));
+        assert!(budget.is_ok());
+    }
+
+    #[test]
+    fn test_preprocess_code_injects_prelude() {
+        let body: CreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-opus-4-1",
+            "max_tokens": 1024,
+            "temperature": 0.5,
+            "top_p": 0.9,
+            "system": "Be terse.",
+            "messages": [{"role": "user", "content": "hello"}]
+        }))
+        .unwrap();
+
+        let (body, _) = preprocess_code(
+            body,
+            ClaudeApiFormat::Claude,
+            &NormalizeOptions::default(),
+            ConversionReport::default(),
+        )
+        .unwrap();
+        assert!(body.top_p.is_none());
+        let system = body.system.unwrap();
+        assert_eq!(system[0]["text"], "You are Claude Code, Anthropic's official CLI for Claude.");
+        assert_eq!(system[1]["text"], "Be terse.");
+    }
+
+    #[test]
+    fn test_exclusive_sampling_models_from_config() {
+        let body = |model: &str| -> CreateMessageParams {
+            serde_json::from_value(json!({
+                "model": model,
+                "max_tokens": 1024,
+                "temperature": 0.5,
+                "top_p": 0.9,
+                "messages": [{"role": "user", "content": "hello"}]
+            }))
+            .unwrap()
+        };
+        let mut config = ClewdrConfig::default();
+        config.exclusive_sampling_models = vec!["My-Custom-Model".to_string()];
+        let options = NormalizeOptions::from_config(&config, ClaudeApiFormat::Claude, false);
+        let preprocess = |model: &str| {
+            preprocess_code(body(model), ClaudeApiFormat::Claude, &options, Default::default())
+                .unwrap()
+                .0
+        };
+
+        let custom = preprocess("my-custom-model-v2");
+        assert_eq!(custom.temperature, Some(0.5));
+        assert!(custom.top_p.is_none());
+
+        // Models outside the configured list keep both parameters
+        let opus = preprocess("claude-opus-4-5");
+        assert_eq!(opus.top_p, Some(0.9));
+
+        // The default list covers every Claude model
+        let options = NormalizeOptions::from_config(
+            &ClewdrConfig::default(),
+            ClaudeApiFormat::Claude,
+            false,
+        );
+        let preprocess = |model: &str| {
+            preprocess_code(body(model), ClaudeApiFormat::Claude, &options, Default::default())
+                .unwrap()
+                .0
+        };
+        assert!(preprocess("claude-sonnet-4-5").top_p.is_none());
+        assert!(preprocess("claude-sonnet-4-20250514").top_p.is_none());
+        assert!(preprocess("claude-haiku-4-5").top_p.is_none());
+        assert!(preprocess("claude-opus-4-6").top_p.is_none());
+    }
 
-        // Log the final system prompt after processing
-        if let Some(ref system) = body.system {
-            let system_str = system.to_string();
-            tracing::debug!("[CLAUDE_CODE_PREPROCESS] Final system prompt length: {} chars", system_str.len());
+    #[test]
+    fn test_preprocess_code_without_prelude() {
+        let mut config = ClewdrConfig::default();
+        config.inject_claude_code_prelude = false;
+        let options = NormalizeOptions::from_config(&config, ClaudeApiFormat::Claude, false);
+        let body: CreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "system": "Be terse.",
+            "messages": [{"role": "user", "content": "hello"}]
+        }))
+        .unwrap();
+
+        let (body, _) =
+            preprocess_code(body, ClaudeApiFormat::Claude, &options, ConversionReport::default())
+                .unwrap();
+        assert_eq!(body.system, Some(json!("Be terse.")));
+    }
+
+    #[test]
+    fn test_system_prompt_hash_ignores_order() {
+        let a = json!({"type": "text", "text": "rules", "cache_control": {"type": "ephemeral"}});
+        let b = json!({"type": "text", "text": "tools", "cache_control": {"type": "ephemeral"}});
+        let plain = json!({"type": "text", "text": "uncached"});
+
+        let hash = system_prompt_hash(Some(&json!([a, plain, b])));
+        assert!(hash.is_some());
+        assert_eq!(hash, system_prompt_hash(Some(&json!([b, a]))));
+        assert_ne!(hash, system_prompt_hash(Some(&json!([a]))));
+        assert_eq!(system_prompt_hash(Some(&json!([plain]))), None);
+        assert_eq!(system_prompt_hash(None), None);
+    }
+
+    #[test]
+    fn test_heic_image_is_rejected() {
+        let image = ContentBlock::Image {
+            source: crate::format::bytes_to_image_source(b"\x00\x00\x00\x18ftypheic", "image/heic"),
+            cache_control: None,
+        };
+        let mut body = CreateMessageParams {
+            messages: vec![Message::new_blocks(Role::User, vec![image])],
+            ..Default::default()
+        };
+        let options = NormalizeOptions::default();
+        assert!(matches!(
+            normalize_body(&mut body, &options, &mut ConversionReport::default()),
+            Err(ClewdrError::BadRequest { .. })
+        ));
+    }
+
+    #[test]
+    fn test_thinking_suffix_parsing() {
+        let mut body = CreateMessageParams {
+            model: "claude-opus-4-1-thinking-thinking".to_string(),
+            ..Default::default()
+        };
+        apply_thinking_suffix(&mut body).unwrap();
+        assert_eq!(body.model, "claude-opus-4-1-thinking");
+        assert!(body.thinking.is_some());
+
+        for model in ["-thinking".to_string(), "x".repeat(MAX_MODEL_NAME_LEN + 1)] {
+            let mut body = CreateMessageParams {
+                model,
+                ..Default::default()
+            };
+            assert!(matches!(
+                apply_thinking_suffix(&mut body),
+                Err(ClewdrError::BadRequest { .. })
+            ));
         }
+    }
 
-        // Save the processed request (with injected system prompt) for debugging
-        if let Ok(json_str) = serde_json::to_string_pretty(&body) {
-            let log_path = "log/claude_code_processed_request.json";
-            if let Err(e) = std::fs::write(log_path, &json_str) {
-                tracing::warn!("[CLAUDE_CODE_PREPROCESS] Failed to write processed request log: {}", e);
-            } else {
-                tracing::info!("[CLAUDE_CODE_PREPROCESS] Processed request saved to {}", log_path);
+    #[test]
+    fn test_parse_bool_header() {
+        assert_eq!(parse_bool_header("true"), Some(true));
+        assert_eq!(parse_bool_header(" 0 "), Some(false));
+        assert_eq!(parse_bool_header("Off"), Some(false));
+        assert_eq!(parse_bool_header("maybe"), None);
+    }
+
+    #[test]
+    fn test_model_aliases() {
+        let options = NormalizeOptions {
+            model_aliases: HashMap::from([
+                ("gpt-4o".to_string(), "claude-sonnet-4-5".to_string()),
+                ("o1".to_string(), "claude-opus-4-1-thinking".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let normalized = |model: &str| {
+            let mut body = CreateMessageParams {
+                model: model.to_string(),
+                ..Default::default()
+            };
+            normalize_body(&mut body, &options, &mut ConversionReport::default()).unwrap();
+            body
+        };
+
+        let body = normalized("gpt-4o");
+        assert_eq!(body.model, "claude-sonnet-4-5");
+        assert!(body.thinking.is_none());
+
+        let body = normalized("gpt-4o-thinking");
+        assert_eq!(body.model, "claude-sonnet-4-5");
+        assert!(body.thinking.is_some());
+
+        let body = normalized("o1");
+        assert_eq!(body.model, "claude-opus-4-1");
+        assert!(body.thinking.is_some());
+
+        let body = normalized("claude-haiku-4-5");
+        assert_eq!(body.model, "claude-haiku-4-5");
+    }
+
+    #[test]
+    fn test_drop_empty_tools() {
+        let body = |tools: Option<Value>, tool_choice: Option<Value>| {
+            let mut body = json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 16,
+                "messages": [{"role": "user", "content": "Hi"}]
+            });
+            if let Some(tools) = tools {
+                body["tools"] = tools;
+            }
+            if let Some(tool_choice) = tool_choice {
+                body["tool_choice"] = tool_choice;
             }
+            serde_json::from_value::<CreateMessageParams>(body).unwrap()
+        };
+        let weather = json!([{
+            "name": "get_weather",
+            "input_schema": {"type": "object", "properties": {}}
+        }]);
+
+        // An empty tools array is removed along with its tool_choice
+        let mut empty = body(Some(json!([])), Some(json!({"type": "auto"})));
+        assert!(drop_empty_tools(&mut empty));
+        assert!(empty.tools.is_none());
+        assert!(empty.tool_choice.is_none());
+
+        // A tool_choice without any tools is dangling
+        let mut dangling = body(None, Some(json!("any")));
+        assert!(drop_empty_tools(&mut dangling));
+        assert!(dangling.tool_choice.is_none());
+
+        let mut kept = body(Some(weather), Some(json!({"type": "auto"})));
+        assert!(!drop_empty_tools(&mut kept));
+        assert_eq!(kept.tools.as_ref().map(Vec::len), Some(1));
+        assert!(kept.tool_choice.is_some());
+
+        let mut absent = body(None, None);
+        assert!(!drop_empty_tools(&mut absent));
+    }
+
+    #[test]
+    fn test_drop_tools_for_none_choice() {
+        let body = |tool_choice: Value, messages: Value| {
+            serde_json::from_value::<CreateMessageParams>(json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 16,
+                "messages": messages,
+                "tools": [{
+                    "name": "get_weather",
+                    "input_schema": {"type": "object", "properties": {}}
+                }],
+                "tool_choice": tool_choice
+            }))
+            .unwrap()
+        };
+        let hi = json!([{"role": "user", "content": "Hi"}]);
+        let enabled = NormalizeOptions {
+            drop_tools_for_none_choice: true,
+            ..Default::default()
+        };
+        let normalize = |mut body: CreateMessageParams, options: &NormalizeOptions| {
+            normalize_body(&mut body, options, &mut ConversionReport::default()).unwrap();
+            body
+        };
+
+        for tool_choice in [json!("none"), json!({"type": "none"})] {
+            let normalized = normalize(body(tool_choice, hi.clone()), &enabled);
+            assert!(normalized.tools.is_none());
+            assert!(normalized.tool_choice.is_none());
         }
 
-        let cache_systems = body
-            .system
-            .as_ref()
-            .ok_or(ClewdrError::BadRequest {
-                msg: "Empty system prompt",
-            })?
-            .as_array()
-            .ok_or(ClewdrError::BadRequest {
-                msg: "System prompt is not an array",
-            })?
-            .iter()
-            .filter(|s| s["cache_control"].as_object().is_some())
-            .collect::<Vec<_>>();
-        let system_prompt_hash = (!cache_systems.is_empty()).then(|| {
-            let mut hasher = DefaultHasher::new();
-            cache_systems.hash(&mut hasher);
-            hasher.finish()
-        });
+        // Kept by default, for other choices, and when the history holds tool calls
+        let kept = normalize(body(json!("none"), hi.clone()), &NormalizeOptions::default());
+        assert!(kept.tools.is_some());
+        let kept = normalize(body(json!({"type": "auto"}), hi.clone()), &enabled);
+        assert!(kept.tools.is_some());
+        let history = json!([
+            {"role": "user", "content": "Weather?"},
+            {"role": "assistant", "content": [
+                {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {}}
+            ]},
+            {"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": "toolu_1", "content": "sunny"}
+            ]}
+        ]);
+        let mut with_history = body(json!("none"), history);
+        assert!(!drop_tools_for_none_choice(&mut with_history));
+        assert!(with_history.tools.is_some());
+    }
 
-        let input_tokens = body.count_tokens();
+    #[test]
+    fn test_reject_excess_images() {
+        use crate::types::claude::ImageSource;
 
-        let info = ClaudeCodeContext {
-            stream,
-            api_format: format,
-            system_prompt_hash,
-            usage: Usage {
-                input_tokens,
-                output_tokens: 0, // Placeholder for output token count
+        let image = || ContentBlock::Image {
+            source: ImageSource {
+                type_: "base64".to_string(),
+                media_type: "image/png".to_string(),
+                data: "iVBORw0KGgo=".to_string(),
             },
+            cache_control: None,
+        };
+        let normalize = |images: usize, options: &NormalizeOptions| {
+            let mut body = CreateMessageParams {
+                messages: vec![Message::new_blocks(Role::User, vec![image(); images])],
+                ..Default::default()
+            };
+            normalize_body(&mut body, options, &mut ConversionReport::default()).map(|()| body)
+        };
+        let sent_images = |images: usize, options: &NormalizeOptions| {
+            normalize(images, options)
+                .ok()
+                .map(|body| count_image_blocks(&body.messages))
         };
+        let mut config = ClewdrConfig::default();
+        config.max_images_per_request = Some(2);
 
-        Ok(Self(body, ClaudeContext::Code(info)))
+        // Without reject_excess_images the web backend drops the excess while merging
+        let dropping = NormalizeOptions::from_config(&config, ClaudeApiFormat::Claude, true);
+        assert_eq!(sent_images(3, &dropping), Some(3));
+        // The API backends drop the oldest images before sending
+        let dropping = NormalizeOptions::from_config(&config, ClaudeApiFormat::Claude, false);
+        assert_eq!(sent_images(3, &dropping), Some(2));
+
+        config.reject_excess_images = true;
+        for web_backend in [true, false] {
+            let rejecting =
+                NormalizeOptions::from_config(&config, ClaudeApiFormat::Claude, web_backend);
+            assert!(normalize(2, &rejecting).is_ok());
+            assert!(matches!(
+                normalize(3, &rejecting),
+                Err(ClewdrError::BadRequest { .. })
+            ));
+        }
+
+        // Data URIs in tool results count when the web backend uploads them
+        config.tool_result_images = true;
+        let rejecting = NormalizeOptions::from_config(&config, ClaudeApiFormat::Claude, true);
+        let tool_result = ContentBlock::ToolResult {
+            tool_use_id: "toolu_1".to_string(),
+            content: json!("Screenshot: data:image/png;base64,iVBORw0KGgo="),
+            is_error: None,
+            cache_control: None,
+        };
+        let mut body = CreateMessageParams {
+            messages: vec![
+                Message::new_blocks(
+                    Role::Assistant,
+                    vec![ContentBlock::ToolUse {
+                        id: "toolu_1".to_string(),
+                        name: "screenshot".to_string(),
+                        input: json!({}),
+                        signature: None,
+                        cache_control: None,
+                    }],
+                ),
+                Message::new_blocks(Role::User, vec![tool_result, image(), image()]),
+            ],
+            ..Default::default()
+        };
+        assert!(matches!(
+            normalize_body(&mut body, &rejecting, &mut ConversionReport::default()),
+            Err(ClewdrError::BadRequest { .. })
+        ));
+    }
+
+    #[test]
+    fn test_model_matches_glob() {
+        assert!(model_matches_glob("claude-*", "claude-sonnet-4-5"));
+        assert!(model_matches_glob("*sonnet*", "Claude-Sonnet-4-5"));
+        assert!(model_matches_glob("claude-opus-4-?", "claude-opus-4-1"));
+        assert!(model_matches_glob("claude-*-4-*", "claude-opus-4-4-5"));
+        assert!(!model_matches_glob("claude-opus-4-?", "claude-opus-4-10"));
+        assert!(!model_matches_glob("claude-haiku-*", "claude-sonnet-4-5"));
+    }
+
+    #[test]
+    fn test_allowed_models() {
+        let options = NormalizeOptions {
+            model_aliases: HashMap::from([("gpt-4o".to_string(), "claude-sonnet-4-5".to_string())]),
+            allowed_models: Some(vec!["claude-sonnet-*".to_string()]),
+            ..Default::default()
+        };
+        let normalize = |model: &str| {
+            let mut body = CreateMessageParams {
+                model: model.to_string(),
+                ..Default::default()
+            };
+            normalize_body(&mut body, &options, &mut ConversionReport::default()).map(|_| body)
+        };
+
+        // Checked after alias and thinking suffix resolution
+        assert_eq!(normalize("claude-sonnet-4-5").unwrap().model, "claude-sonnet-4-5");
+        assert_eq!(normalize("gpt-4o-thinking").unwrap().model, "claude-sonnet-4-5");
+        assert!(matches!(
+            normalize("claude-opus-4-1"),
+            Err(ClewdrError::BadRequest { .. })
+        ));
+        assert!(matches!(
+            normalize("claude-opus-4-1-thinking"),
+            Err(ClewdrError::BadRequest { .. })
+        ));
     }
 }
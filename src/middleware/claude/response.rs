@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use axum::{
     Json,
     body::{self, Body},
@@ -10,8 +12,9 @@ use tracing::warn;
 
 use super::{ClaudeApiFormat, transform_stream};
 use crate::{
-    middleware::claude::{ClaudeContext, transforms_json},
-    types::claude::{CreateMessageResponse, StreamEvent},
+    config::CLEWDR_CONFIG,
+    middleware::claude::{ClaudeContext, OaiResponseOptions, transforms_json},
+    types::claude::{ContentBlock, ContentBlockDelta, CreateMessageResponse, StreamEvent},
 };
 
 async fn parse_response<T>(resp: Response) -> Result<T, Response>
@@ -52,27 +55,31 @@ where
 ///
 /// The original or transformed response as appropriate
 pub async fn to_oai(resp: Response) -> impl IntoResponse {
-    let Some(cx) = resp.extensions().get::<ClaudeContext>() else {
+    let Some(cx) = resp.extensions().get::<ClaudeContext>().cloned() else {
         return resp;
     };
     if ClaudeApiFormat::Claude == cx.api_format() {
         return resp;
     }
-    if !cx.is_stream() {
+    let options = OaiResponseOptions::from_config(&CLEWDR_CONFIG.load());
+    let mut resp = if !cx.is_stream() {
         match parse_response::<CreateMessageResponse>(resp).await {
-            Ok(response) => return Json(transforms_json(response)).into_response(),
+            Ok(response) => Json(transforms_json(response, &options)).into_response(),
             Err(resp) => return resp,
         }
-    }
-    let stream = resp.into_body().into_data_stream().eventsource();
-    let stream = transform_stream(stream);
-    Sse::new(stream)
-        .keep_alive(Default::default())
-        .into_response()
+    } else {
+        let stream = resp.into_body().into_data_stream().eventsource();
+        let stream = transform_stream(stream, &options);
+        Sse::new(stream)
+            .keep_alive(Default::default())
+            .into_response()
+    };
+    resp.extensions_mut().insert(cx);
+    resp
 }
 
 pub async fn add_usage_info(resp: Response) -> impl IntoResponse {
-    let Some(cx) = resp.extensions().get::<ClaudeContext>() else {
+    let Some(cx) = resp.extensions().get::<ClaudeContext>().cloned() else {
         return resp;
     };
     let (mut usage, stream) = (cx.usage().to_owned(), cx.is_stream());
@@ -84,7 +91,9 @@ pub async fn add_usage_info(resp: Response) -> impl IntoResponse {
         let output_tokens = response.count_tokens();
         usage.output_tokens = output_tokens;
         response.usage = Some(usage);
-        return Json(response).into_response();
+        let mut resp = Json(response).into_response();
+        resp.extensions_mut().insert(cx);
+        return resp;
     }
     let stream = resp
         .into_body()
@@ -122,9 +131,107 @@ pub async fn add_usage_info(resp: Response) -> impl IntoResponse {
             }
         });
 
-    Sse::new(stream)
+    let mut resp = Sse::new(stream)
+        .keep_alive(Default::default())
+        .into_response();
+    resp.extensions_mut().insert(cx);
+    resp
+}
+
+/// Remove control characters other than newline and tab from text
+fn strip_control_chars(text: &str) -> Cow<'_, str> {
+    let is_stripped = |c: char| c.is_control() && c != '\n' && c != '\t';
+    if text.contains(is_stripped) {
+        Cow::Owned(text.replace(is_stripped, ""))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// Strip control characters from the text blocks of a response
+fn strip_response_control_chars(response: &mut CreateMessageResponse) {
+    for block in response.content.iter_mut() {
+        if let ContentBlock::Text { text, .. } = block
+            && let Cow::Owned(stripped) = strip_control_chars(text)
+        {
+            *text = stripped;
+        }
+    }
+}
+
+/// Strip control characters from a streamed text delta
+///
+/// # Returns
+/// The cleaned event, or None if the event is not a text delta or is clean
+fn strip_event_control_chars(data: &str) -> Option<StreamEvent> {
+    let StreamEvent::ContentBlockDelta {
+        index,
+        delta: ContentBlockDelta::TextDelta { text },
+    } = serde_json::from_str::<StreamEvent>(data).ok()?
+    else {
+        return None;
+    };
+    let Cow::Owned(text) = strip_control_chars(&text) else {
+        return None;
+    };
+    Some(StreamEvent::ContentBlockDelta {
+        index,
+        delta: ContentBlockDelta::TextDelta { text },
+    })
+}
+
+/// Removes stray control characters from response text
+///
+/// The `\x08` sentinel injected for real roles, among other control
+/// characters, can leak into the completion and break rendering. Newlines and
+/// tabs are kept. Disabled by `strip_control_chars`.
+///
+/// # Arguments
+/// * `resp` - The response to clean
+///
+/// # Returns
+/// The response with control characters removed from its text
+pub async fn strip_output_control_chars(resp: Response) -> Response {
+    let Some(cx) = resp.extensions().get::<ClaudeContext>().cloned() else {
+        return resp;
+    };
+    if !CLEWDR_CONFIG.load().strip_control_chars {
+        return resp;
+    }
+    if !cx.is_stream() {
+        let mut response = match parse_response::<CreateMessageResponse>(resp).await {
+            Ok(response) => response,
+            Err(resp) => return resp,
+        };
+        strip_response_control_chars(&mut response);
+        let mut resp = Json(response).into_response();
+        resp.extensions_mut().insert(cx);
+        return resp;
+    }
+    let stream = resp
+        .into_body()
+        .into_data_stream()
+        .eventsource()
+        .map_ok(|event| {
+            let new_event = axum::response::sse::Event::default()
+                .event(event.event)
+                .id(event.id);
+            let new_event = if let Some(retry) = event.retry {
+                new_event.retry(retry)
+            } else {
+                new_event
+            };
+            match strip_event_control_chars(&event.data) {
+                Some(stripped) => new_event.json_data(stripped).unwrap(),
+                None => new_event.data(event.data),
+            }
+        });
+
+    let mut resp = Sse::new(stream)
         .keep_alive(Default::default())
-        .into_response()
+        .into_response();
+    resp.extensions_mut().insert(cx);
+    resp
 }
 
 pub async fn check_overloaded(mut resp: Response) -> Response {
@@ -144,3 +251,76 @@ pub async fn check_overloaded(mut resp: Response) -> Response {
     }
     resp
 }
+
+/// Header set when thinking was dropped because of an incompatible history
+pub const THINKING_DISABLED_HEADER: &str = "x-clewdr-thinking-disabled";
+
+/// Surfaces conversion report flags as response headers
+///
+/// # Arguments
+/// * `resp` - The response to annotate
+///
+/// # Returns
+/// The response with any applicable `x-clewdr-*` headers
+pub async fn add_conversion_headers(mut resp: Response) -> Response {
+    let Some(cx) = resp.extensions().get::<ClaudeContext>() else {
+        return resp;
+    };
+    if cx.report().thinking_disabled {
+        resp.headers_mut().insert(
+            THINKING_DISABLED_HEADER,
+            http::HeaderValue::from_static("true"),
+        );
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_strip_control_chars_removes_backspace() {
+        assert_eq!(strip_control_chars("\x08Hello\x08 world"), "Hello world");
+        assert_eq!(strip_control_chars("a\u{7}b\x1b"), "ab");
+        assert!(matches!(
+            strip_control_chars("line\n\tindented"),
+            Cow::Borrowed("line\n\tindented")
+        ));
+
+        let mut response: CreateMessageResponse = serde_json::from_value(json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-sonnet-4-20250514",
+            "content": [{"type": "text", "text": "\x08Hi\nthere"}],
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": null
+        }))
+        .unwrap();
+        strip_response_control_chars(&mut response);
+        assert!(matches!(
+            &response.content[0],
+            ContentBlock::Text { text, .. } if text == "Hi\nthere"
+        ));
+
+        let event = json!({
+            "type": "content_block_delta",
+            "index": 0,
+            "delta": {"type": "text_delta", "text": "\x08Hi"}
+        })
+        .to_string();
+        assert!(matches!(
+            strip_event_control_chars(&event),
+            Some(StreamEvent::ContentBlockDelta {
+                index: 0,
+                delta: ContentBlockDelta::TextDelta { text },
+            }) if text == "Hi"
+        ));
+        let clean = event.replace("\\b", "");
+        assert!(strip_event_control_chars(&clean).is_none());
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use async_stream::try_stream;
 use axum::response::{IntoResponse, Response, Sse, sse::Event};
 use eventsource_stream::{Event as SourceEvent, Eventsource};
@@ -8,6 +10,56 @@ use crate::{
     types::claude::{ContentBlockDelta, MessageDeltaContent, StopReason, StreamEvent},
 };
 
+/// Maximum number of stop sequences forwarded to the provider
+pub const MAX_STOP_SEQUENCES: usize = 8;
+
+/// Maximum length in characters of a single stop sequence
+pub const MAX_STOP_SEQUENCE_LEN: usize = 256;
+
+/// Merges client stop sequences with operator-configured defaults
+///
+/// Client sequences come first so they survive truncation. Empty strings,
+/// duplicates and sequences longer than [`MAX_STOP_SEQUENCE_LEN`] are
+/// dropped, and the result is capped at [`MAX_STOP_SEQUENCES`].
+///
+/// # Arguments
+/// * `client` - Stop sequences sent by the client
+/// * `config_defaults` - Stop sequences from the configuration
+///
+/// # Returns
+/// The merged list of stop sequences
+pub fn normalize_stop_sequences(client: Vec<String>, config_defaults: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut sequences: Vec<String> = client
+        .into_iter()
+        .chain(config_defaults)
+        .filter(|s| !s.is_empty() && seen.insert(s.to_owned()))
+        .filter(|s| {
+            let len = s.chars().count();
+            if len > MAX_STOP_SEQUENCE_LEN {
+                tracing::warn!(
+                    "[Format] Dropping stop sequence of {} characters over the limit of {}",
+                    len,
+                    MAX_STOP_SEQUENCE_LEN
+                );
+                return false;
+            }
+            true
+        })
+        .collect();
+    if sequences.len() > MAX_STOP_SEQUENCES {
+        for dropped in &sequences[MAX_STOP_SEQUENCES..] {
+            tracing::warn!(
+                "[Format] Dropping stop sequence {:?} over the limit of {}",
+                dropped,
+                MAX_STOP_SEQUENCES
+            );
+        }
+        sequences.truncate(MAX_STOP_SEQUENCES);
+    }
+    sequences
+}
+
 type EventResult<T> = Result<T, eventsource_stream::EventStreamError<axum::Error>>;
 
 fn stop_stream(
@@ -102,3 +154,41 @@ pub async fn apply_stop_sequences(resp: Response) -> Response {
     resp.extensions_mut().insert(f);
     resp
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_normalize_stop_sequences_dedup() {
+        let result = normalize_stop_sequences(
+            strings(&["\n\nHuman:", "", "END"]),
+            strings(&["END", "\n\nHuman:", "STOP"]),
+        );
+        assert_eq!(result, strings(&["\n\nHuman:", "END", "STOP"]));
+    }
+
+    #[test]
+    fn test_normalize_stop_sequences_cap() {
+        let client: Vec<String> = (0..MAX_STOP_SEQUENCES).map(|i| format!("c{i}")).collect();
+        let result = normalize_stop_sequences(client.clone(), strings(&["default"]));
+        assert_eq!(result.len(), MAX_STOP_SEQUENCES);
+        // Client sequences take priority over configured defaults
+        assert_eq!(result, client);
+    }
+
+    #[test]
+    fn test_normalize_stop_sequences_drops_overlong() {
+        let overlong = "x".repeat(MAX_STOP_SEQUENCE_LEN + 1);
+        let longest = "é".repeat(MAX_STOP_SEQUENCE_LEN);
+        let result = normalize_stop_sequences(
+            vec![overlong, "END".to_string(), longest.clone()],
+            strings(&["STOP"]),
+        );
+        assert_eq!(result, vec!["END".to_string(), longest, "STOP".to_string()]);
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+
+use serde_json::Value;
+
+use crate::{
+    error::ClewdrError,
+    types::claude::{ContentBlock, CreateMessageParams, MessageContent, Role},
+};
+
+/// Smallest thinking budget accepted by the Claude API
+pub const MIN_THINKING_BUDGET: u64 = 1024;
+
+/// Validates a normalized request before it is dispatched to a backend
+///
+/// Checks run in order and the first problem found is returned:
+///
+/// - The conversation has at least one message
+/// - The system prompt, if present, is a string or a non-empty array
+/// - An enabled thinking budget is at least [`MIN_THINKING_BUDGET`]
+/// - Every `tool_result` follows a matching `tool_use`
+///
+/// The web backend ignores `max_tokens`, so the budget is checked against it
+/// separately by [`validate_max_tokens`].
+///
+/// # Arguments
+/// * `params` - The request to validate
+///
+/// # Returns
+/// * `Ok(())` if the request is valid
+/// * `Err(ClewdrError::BadRequest)` describing the first problem otherwise
+pub fn validate_request(params: &CreateMessageParams) -> Result<(), ClewdrError> {
+    if params.messages.is_empty() {
+        return Err(ClewdrError::BadRequest {
+            msg: "Messages must not be empty",
+        });
+    }
+
+    match params.system {
+        Some(Value::Array(ref blocks)) if blocks.is_empty() => {
+            return Err(ClewdrError::BadRequest {
+                msg: "Empty system prompt",
+            });
+        }
+        None | Some(Value::String(_)) | Some(Value::Array(_)) => {}
+        Some(_) => {
+            return Err(ClewdrError::BadRequest {
+                msg: "System prompt must be a string or an array",
+            });
+        }
+    }
+
+    if let Some(thinking) = params.thinking.as_ref().filter(|t| t.is_enabled())
+        && thinking.budget_tokens < MIN_THINKING_BUDGET
+    {
+        return Err(ClewdrError::BadRequest {
+            msg: "Thinking budget must be at least 1024 tokens",
+        });
+    }
+
+    let mut tool_use_ids = HashSet::new();
+    for msg in &params.messages {
+        let MessageContent::Blocks { content } = &msg.content else {
+            continue;
+        };
+        for block in content {
+            match block {
+                ContentBlock::ToolUse { id, .. } if msg.role == Role::Assistant => {
+                    tool_use_ids.insert(id.as_str());
+                }
+                ContentBlock::ServerToolUse { data } => {
+                    if let Some(id) = data.get("id").and_then(Value::as_str) {
+                        tool_use_ids.insert(id);
+                    }
+                }
+                ContentBlock::ToolResult { tool_use_id, .. }
+                    if !tool_use_ids.contains(tool_use_id.as_str()) =>
+                {
+                    return Err(ClewdrError::BadRequest {
+                        msg: "tool_result without a matching tool_use",
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates that an enabled thinking budget fits below `max_tokens`
+///
+/// Only the API backends honor `max_tokens`, so this check is skipped for
+/// the web backend.
+///
+/// # Arguments
+/// * `params` - The request to validate
+///
+/// # Returns
+/// * `Ok(())` if the budget fits or thinking is disabled
+/// * `Err(ClewdrError::BadRequest)` otherwise
+pub fn validate_max_tokens(params: &CreateMessageParams) -> Result<(), ClewdrError> {
+    if let Some(thinking) = params.thinking.as_ref().filter(|t| t.is_enabled())
+        && thinking.budget_tokens >= u64::from(params.max_tokens)
+    {
+        return Err(ClewdrError::BadRequest {
+            msg: "Thinking budget must be lower than max_tokens",
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn params(body: Value) -> CreateMessageParams {
+        serde_json::from_value(body).unwrap()
+    }
+
+    fn assert_bad_request(params: &CreateMessageParams, expected: &str) {
+        match validate_request(params) {
+            Err(ClewdrError::BadRequest { msg }) => assert_eq!(msg, expected),
+            other => panic!("Expected BadRequest({expected}), got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_valid_request() {
+        let p = params(json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 8192,
+            "system": [{"type": "text", "text": "Be brief."}],
+            "thinking": {"type": "enabled", "budget_tokens": 4096},
+            "messages": [
+                {"role": "user", "content": "list files"},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "toolu_01", "name": "LS", "input": {}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_01", "content": "a.txt"}
+                ]}
+            ]
+        }));
+        assert!(validate_request(&p).is_ok());
+    }
+
+    #[test]
+    fn test_empty_messages() {
+        let p = params(json!({"model": "claude-sonnet-4-5", "messages": []}));
+        assert_bad_request(&p, "Messages must not be empty");
+    }
+
+    #[test]
+    fn test_missing_or_null_messages() {
+        let p = params(json!({"model": "claude-sonnet-4-5"}));
+        assert!(p.messages.is_empty());
+        assert_bad_request(&p, "Messages must not be empty");
+
+        let p = params(json!({"model": "claude-sonnet-4-5", "messages": null}));
+        assert_bad_request(&p, "Messages must not be empty");
+    }
+
+    #[test]
+    fn test_empty_system() {
+        let p = params(json!({
+            "model": "claude-sonnet-4-5",
+            "system": [],
+            "messages": [{"role": "user", "content": "hi"}]
+        }));
+        assert_bad_request(&p, "Empty system prompt");
+    }
+
+    #[test]
+    fn test_invalid_system_type() {
+        let p = params(json!({
+            "model": "claude-sonnet-4-5",
+            "system": 42,
+            "messages": [{"role": "user", "content": "hi"}]
+        }));
+        assert_bad_request(&p, "System prompt must be a string or an array");
+    }
+
+    #[test]
+    fn test_thinking_budget_too_small() {
+        let p = params(json!({
+            "model": "claude-sonnet-4-5",
+            "thinking": {"type": "enabled", "budget_tokens": 256},
+            "messages": [{"role": "user", "content": "hi"}]
+        }));
+        assert_bad_request(&p, "Thinking budget must be at least 1024 tokens");
+    }
+
+    #[test]
+    fn test_thinking_budget_exceeds_max_tokens() {
+        let p = params(json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 4096,
+            "thinking": {"type": "enabled", "budget_tokens": 4096},
+            "messages": [{"role": "user", "content": "hi"}]
+        }));
+        assert!(validate_request(&p).is_ok());
+        assert!(matches!(
+            validate_max_tokens(&p),
+            Err(ClewdrError::BadRequest { msg: "Thinking budget must be lower than max_tokens" })
+        ));
+    }
+
+    #[test]
+    fn test_orphaned_tool_result() {
+        let p = params(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_missing", "content": "a.txt"}
+                ]}
+            ]
+        }));
+        assert_bad_request(&p, "tool_result without a matching tool_use");
+    }
+}
@@ -12,7 +12,7 @@ use crate::{
     middleware::claude::{ClaudeApiFormat, ClaudeContext},
     services::cookie_actor::CookieActorHandle,
     types::claude::CreateMessageParams,
-    utils::{enabled, print_out_json},
+    utils::{enabled, print_out_request},
 };
 
 #[derive(Clone, Copy)]
@@ -108,6 +108,7 @@ impl LLMProvider for ClaudeWebProvider {
         state.api_format = request.context.api_format();
         state.stream = stream;
         state.usage = request.context.usage().to_owned();
+        state.use_real_roles = request.context.use_real_roles();
         let ClaudeInvocation {
             params,
             context,
@@ -130,7 +131,7 @@ impl LLMProvider for ClaudeWebProvider {
             enabled(params.thinking.is_some()),
             format_display
         );
-        print_out_json(&params, "claude_web_client_req.json");
+        print_out_request(&params, "claude_web_client_req.json");
         let stopwatch = Instant::now();
         let response = state.try_chat(params).await?;
         let elapsed = stopwatch.elapsed();
@@ -182,7 +183,7 @@ impl LLMProvider for ClaudeCodeProvider {
                     params.model.green(),
                     format_display
                 );
-                print_out_json(&params, "claude_code_client_req.json");
+                print_out_request(&params, "claude_code_client_req.json");
                 let stopwatch = Instant::now();
                 let response = state.try_chat(params).await?;
                 let elapsed = stopwatch.elapsed();
@@ -11,7 +11,10 @@ use crate::{
     api::*,
     middleware::{
         RequireAdminAuth, RequireBearerAuth, RequireXApiKeyAuth,
-        claude::{add_usage_info, apply_stop_sequences, check_overloaded, to_oai},
+        claude::{
+            add_conversion_headers, add_usage_info, apply_stop_sequences, check_overloaded,
+            strip_output_control_chars, to_oai,
+        },
     },
     providers::claude::ClaudeProviders,
     services::cookie_actor::CookieActorHandle,
@@ -63,7 +66,9 @@ impl RouterBuilder {
                 ServiceBuilder::new()
                     .layer(from_extractor::<RequireXApiKeyAuth>())
                     .layer(CompressionLayer::new())
+                    .layer(map_response(add_conversion_headers))
                     .layer(map_response(add_usage_info))
+                    .layer(map_response(strip_output_control_chars))
                     .layer(map_response(apply_stop_sequences))
                     .layer(map_response(check_overloaded)),
             )
@@ -83,7 +88,8 @@ impl RouterBuilder {
             .layer(
                 ServiceBuilder::new()
                     .layer(from_extractor::<RequireXApiKeyAuth>())
-                    .layer(CompressionLayer::new()),
+                    .layer(CompressionLayer::new())
+                    .layer(map_response(add_conversion_headers)),
             )
             .with_state(self.claude_providers.code());
         self.inner = self.inner.merge(router);
@@ -121,7 +127,9 @@ impl RouterBuilder {
                 ServiceBuilder::new()
                     .layer(from_extractor::<RequireBearerAuth>())
                     .layer(CompressionLayer::new())
+                    .layer(map_response(add_conversion_headers))
                     .layer(map_response(to_oai))
+                    .layer(map_response(strip_output_control_chars))
                     .layer(map_response(apply_stop_sequences))
                     .layer(map_response(check_overloaded)),
             )
@@ -139,6 +147,7 @@ impl RouterBuilder {
                 ServiceBuilder::new()
                     .layer(from_extractor::<RequireBearerAuth>())
                     .layer(CompressionLayer::new())
+                    .layer(map_response(add_conversion_headers))
                     .layer(map_response(to_oai)),
             )
             .with_state(self.claude_providers.code());
@@ -1,7 +1,9 @@
+use std::{borrow::Cow, collections::HashSet, sync::LazyLock};
+
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use serde_with::{DefaultOnError, serde_as};
-use tiktoken_rs::o200k_base;
+use serde_with::{DefaultOnError, DefaultOnNull, DisplayFromStr, PickFirst, serde_as};
+use tiktoken_rs::{CoreBPE, o200k_base};
 
 #[derive(Debug)]
 pub struct RequiredMessageParams {
@@ -13,14 +15,40 @@ pub struct RequiredMessageParams {
 pub(super) fn default_max_tokens() -> u32 {
     8192
 }
+
+/// Shared o200k encoder, `None` if it failed to initialize
+static ENCODER: LazyLock<Option<CoreBPE>> = LazyLock::new(|| {
+    o200k_base()
+        .inspect_err(|e| tracing::warn!("Failed to get encoding, estimating tokens: {}", e))
+        .ok()
+});
+
+/// Counts the tokens in `text`
+///
+/// Falls back to `estimate_tokens` if the encoder is unavailable.
+pub fn count_text_tokens(text: &str) -> u32 {
+    match ENCODER.as_ref() {
+        Some(bpe) => bpe.encode_with_special_tokens(text).len() as u32,
+        None => estimate_tokens(text),
+    }
+}
+
+/// Rough token estimate of one token per four characters
+pub fn estimate_tokens(text: &str) -> u32 {
+    text.chars().count().div_ceil(4) as u32
+}
+
 /// Parameters for creating a message
 #[serde_as]
 #[derive(Debug, Deserialize, Serialize, Default, Clone)]
 pub struct CreateMessageParams {
     /// Maximum number of tokens to generate
     #[serde(default = "default_max_tokens")]
+    #[serde_as(deserialize_as = "PickFirst<(_, DisplayFromStr)>")]
     pub max_tokens: u32,
-    /// Input messages for the conversation
+    /// Input messages for the conversation, empty when missing or null
+    #[serde(default)]
+    #[serde_as(deserialize_as = "DefaultOnNull")]
     pub messages: Vec<Message>,
     /// Model to use
     pub model: String,
@@ -43,6 +71,8 @@ pub struct CreateMessageParams {
     pub thinking: Option<Thinking>,
     /// Top-k sampling
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(_, DisplayFromStr)>>")]
     pub top_k: Option<u32>,
     /// Top-p sampling
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -58,12 +88,16 @@ pub struct CreateMessageParams {
     pub metadata: Option<Metadata>,
     /// Number of completions to generate
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(_, DisplayFromStr)>>")]
     pub n: Option<u32>,
+    /// Code execution container to reuse across requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
 }
 
 impl CreateMessageParams {
     pub fn count_tokens(&self) -> u32 {
-        let bpe = o200k_base().expect("Failed to get encoding");
         let systems = match self.system {
             Some(Value::String(ref s)) => s.to_string(),
             Some(Value::Array(ref arr)) => arr.iter().filter_map(|v| v["text"].as_str()).collect(),
@@ -76,16 +110,12 @@ impl CreateMessageParams {
                 MessageContent::Text { ref content } => content.to_string(),
                 MessageContent::Blocks { ref content } => content
                     .iter()
-                    .map(|block| match block {
-                        ContentBlock::Text { text, .. } => text,
-                        _ => "",
-                    })
+                    .map(ContentBlock::token_text)
                     .collect::<String>(),
             })
             .collect::<Vec<_>>()
             .join("\n");
-        bpe.encode_with_special_tokens(&systems).len() as u32
-            + bpe.encode_with_special_tokens(&messages).len() as u32
+        count_text_tokens(&systems) + count_text_tokens(&messages)
     }
 }
 
@@ -114,6 +144,11 @@ impl Thinking {
             r#type: String::from("enabled"),
         }
     }
+
+    /// Whether this configuration turns extended thinking on
+    pub fn is_enabled(&self) -> bool {
+        self.r#type == "enabled"
+    }
 }
 
 impl From<RequiredMessageParams> for CreateMessageParams {
@@ -182,6 +217,7 @@ impl CreateMessageParams {
 
 /// Message in a conversation
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(try_from = "RawMessage")]
 pub struct Message {
     /// Role of the message sender
     pub role: Role,
@@ -190,6 +226,87 @@ pub struct Message {
     pub content: MessageContent,
 }
 
+/// Wire shape of a message, tolerating the OpenAI "tool" role
+///
+/// Some clients mix formats and send `{"role": "tool", "tool_call_id": ...}`
+/// to the Claude endpoint. Such messages are coerced into a user message
+/// carrying a tool_result block instead of being rejected, as long as they
+/// name the tool call they answer.
+#[derive(Deserialize)]
+struct RawMessage {
+    role: RawRole,
+    #[serde(flatten)]
+    content: MessageContent,
+    #[serde(default)]
+    tool_call_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RawRole {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+impl TryFrom<RawMessage> for Message {
+    type Error = &'static str;
+
+    fn try_from(raw: RawMessage) -> Result<Self, Self::Error> {
+        let role = match raw.role {
+            RawRole::System => Role::System,
+            RawRole::User => Role::User,
+            RawRole::Assistant => Role::Assistant,
+            RawRole::Tool => {
+                return Ok(Message {
+                    role: Role::User,
+                    content: tool_message_content(raw.content, raw.tool_call_id)?,
+                });
+            }
+        };
+        Ok(Message {
+            role,
+            content: raw.content,
+        })
+    }
+}
+
+/// Wraps the content of a tool-role message into a tool_result block
+///
+/// # Errors
+/// If the content has to be wrapped but `tool_call_id` is missing or empty
+fn tool_message_content(
+    content: MessageContent,
+    tool_call_id: Option<String>,
+) -> Result<MessageContent, &'static str> {
+    let result = match content {
+        // Already Claude-shaped, keep the blocks untouched
+        MessageContent::Blocks { content }
+            if content
+                .iter()
+                .any(|b| matches!(b, ContentBlock::ToolResult { .. })) =>
+        {
+            return Ok(MessageContent::Blocks { content });
+        }
+        MessageContent::Text { content } => serde_json::Value::String(content),
+        MessageContent::Blocks { content } => {
+            serde_json::to_value(content).unwrap_or(serde_json::Value::Null)
+        }
+    };
+    let tool_use_id = tool_call_id
+        .filter(|id| !id.is_empty())
+        .ok_or("tool message is missing tool_call_id")?;
+    Ok(MessageContent::Blocks {
+        content: vec![ContentBlock::ToolResult {
+            tool_use_id,
+            content: result,
+            is_error: None,
+            cache_control: None,
+        }],
+    })
+}
+
 /// Role of a message sender
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Hash)]
 #[serde(rename_all = "lowercase")]
@@ -221,6 +338,9 @@ pub enum ContentBlock {
         text: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         cache_control: Option<CacheControlEphemeral>,
+        /// Citations supporting this text, in Anthropic's location formats
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        citations: Option<Vec<Value>>,
     },
     /// Image content (Claude native format)
     #[serde(rename = "image")]
@@ -291,6 +411,18 @@ pub enum ContentBlock {
         #[serde(flatten)]
         data: serde_json::Value,
     },
+    /// Code execution tool result
+    #[serde(rename = "code_execution_tool_result")]
+    CodeExecutionToolResult {
+        #[serde(flatten)]
+        data: serde_json::Value,
+    },
+    /// Bash code execution tool result
+    #[serde(rename = "bash_code_execution_tool_result")]
+    BashCodeExecutionToolResult {
+        #[serde(flatten)]
+        data: serde_json::Value,
+    },
 }
 
 impl ContentBlock {
@@ -306,6 +438,50 @@ impl ContentBlock {
             _ => {}
         }
     }
+
+    /// Set cache_control on this block
+    ///
+    /// Returns false if the block type cannot carry cache_control.
+    pub fn set_cache_control(&mut self, value: CacheControlEphemeral) -> bool {
+        match self {
+            ContentBlock::Text { cache_control, .. }
+            | ContentBlock::Image { cache_control, .. }
+            | ContentBlock::Document { cache_control, .. }
+            | ContentBlock::ToolUse { cache_control, .. }
+            | ContentBlock::ToolResult { cache_control, .. }
+            | ContentBlock::Thinking { cache_control, .. } => {
+                *cache_control = Some(value);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Flattens tool_result content into text, skipping non-text parts
+fn tool_result_text(content: &Value) -> Cow<'_, str> {
+    match content {
+        Value::String(text) => Cow::Borrowed(text),
+        Value::Array(parts) => Cow::Owned(
+            parts
+                .iter()
+                .filter_map(|part| part["text"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        _ => Cow::Borrowed(""),
+    }
+}
+
+impl ContentBlock {
+    /// Text of this block that counts towards input tokens
+    pub fn token_text(&self) -> Cow<'_, str> {
+        match self {
+            ContentBlock::Text { text, .. } => Cow::Borrowed(text),
+            ContentBlock::ToolResult { content, .. } => tool_result_text(content),
+            _ => Cow::Borrowed(""),
+        }
+    }
 }
 
 impl Message {
@@ -331,6 +507,106 @@ pub fn clean_cache_control_from_messages(messages: &mut [Message]) {
     }
 }
 
+/// Maximum number of cache_control breakpoints accepted per request
+pub const MAX_CACHE_BREAKPOINTS: usize = 4;
+
+/// Count the cache_control breakpoints set on the system prompt and tools
+pub fn count_prefix_cache_breakpoints(
+    system: Option<&serde_json::Value>,
+    tools: Option<&[Tool]>,
+) -> usize {
+    let system = system.and_then(|s| s.as_array()).map_or(0, |blocks| {
+        blocks
+            .iter()
+            .filter(|b| b.get("cache_control").is_some_and(|c| !c.is_null()))
+            .count()
+    });
+    let tools = tools.map_or(0, |tools| {
+        tools.iter().filter(|t| t.has_cache_control()).count()
+    });
+    system + tools
+}
+
+/// Place cache_control breakpoints on the last `count` eligible blocks
+///
+/// This is the inverse of [`clean_cache_control_from_messages`]. Thinking
+/// blocks and empty text are skipped. Breakpoints already present in the
+/// messages, plus the `reserved` ones on the system prompt and tools, count
+/// against [`MAX_CACHE_BREAKPOINTS`].
+///
+/// # Returns
+/// The number of breakpoints that were added
+pub fn apply_cache_breakpoints(messages: &mut [Message], count: usize, reserved: usize) -> usize {
+    let existing = reserved
+        + messages
+            .iter()
+            .filter_map(|m| match &m.content {
+                MessageContent::Blocks { content } => Some(content),
+                MessageContent::Text { .. } => None,
+            })
+            .flatten()
+            .filter(|b| match b {
+                ContentBlock::Text { cache_control, .. }
+                | ContentBlock::Image { cache_control, .. }
+                | ContentBlock::Document { cache_control, .. }
+                | ContentBlock::ToolUse { cache_control, .. }
+                | ContentBlock::ToolResult { cache_control, .. }
+                | ContentBlock::Thinking { cache_control, .. } => cache_control.is_some(),
+                _ => false,
+            })
+            .count();
+    let mut remaining = count.min(MAX_CACHE_BREAKPOINTS.saturating_sub(existing));
+    let mut added = 0;
+    for msg in messages.iter_mut().rev() {
+        if remaining == 0 {
+            break;
+        }
+        // Plain text content has no block to carry the breakpoint
+        if let MessageContent::Text { content } = &msg.content {
+            if content.is_empty() {
+                continue;
+            }
+            msg.content = MessageContent::Blocks {
+                content: vec![ContentBlock::Text {
+                    text: content.to_owned(),
+                    cache_control: None,
+                    citations: None,
+                }],
+            };
+        }
+        let MessageContent::Blocks { content } = &mut msg.content else {
+            continue;
+        };
+        for block in content.iter_mut().rev() {
+            if remaining == 0 {
+                break;
+            }
+            let eligible = match block {
+                ContentBlock::Text {
+                    text,
+                    cache_control,
+                    ..
+                } => !text.is_empty() && cache_control.is_none(),
+                ContentBlock::Image { cache_control, .. }
+                | ContentBlock::Document { cache_control, .. }
+                | ContentBlock::ToolUse { cache_control, .. }
+                | ContentBlock::ToolResult { cache_control, .. } => cache_control.is_none(),
+                _ => false,
+            };
+            if eligible
+                && block.set_cache_control(CacheControlEphemeral {
+                    type_: CacheControlType::Ephemeral,
+                    ttl: None,
+                })
+            {
+                remaining -= 1;
+                added += 1;
+            }
+        }
+    }
+    added
+}
+
 /// Document source for document content blocks
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct DocumentSource {
@@ -364,6 +640,9 @@ pub struct ImageSource {
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct ImageUrl {
     pub url: String,
+    /// OpenAI image fidelity hint ("low", "high" or "auto")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
 }
 
 /// Cache control breakpoint configuration.
@@ -381,6 +660,75 @@ pub enum CacheControlType {
     Ephemeral,
 }
 
+/// Normalize a cache_control TTL to a value accepted by the Claude API
+///
+/// Only "5m" and "1h" are accepted upstream. Equivalent spellings such as
+/// "300s", "300", "5min" or "60m" are mapped onto them, and any other value
+/// is dropped so the default TTL applies instead of the request failing.
+pub fn normalize_ttl(cache_control: &mut CacheControlEphemeral) {
+    let Some(ttl) = cache_control.ttl.take() else {
+        return;
+    };
+    let ttl = ttl.trim().to_ascii_lowercase();
+    let split = ttl.find(|c: char| !c.is_ascii_digit()).unwrap_or(ttl.len());
+    let (number, unit) = ttl.split_at(split);
+    let seconds = number.parse::<u64>().ok().and_then(|n| {
+        let scale = match unit.trim() {
+            "" | "s" | "sec" | "secs" | "second" | "seconds" => 1,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600,
+            _ => return None,
+        };
+        n.checked_mul(scale)
+    });
+    cache_control.ttl = match seconds {
+        Some(300) => Some("5m".to_string()),
+        Some(3600) => Some("1h".to_string()),
+        _ => {
+            tracing::warn!("[Format] Dropping unsupported cache_control ttl: {}", ttl);
+            None
+        }
+    };
+}
+
+/// Normalize cache_control TTLs on every block of a system prompt array
+pub fn normalize_system_cache_ttl(system: &mut Value) {
+    let Some(blocks) = system.as_array_mut() else {
+        return;
+    };
+    for block in blocks {
+        let Some(raw) = block.get_mut("cache_control") else {
+            continue;
+        };
+        if let Ok(mut cache_control) = serde_json::from_value::<CacheControlEphemeral>(raw.clone()) {
+            normalize_ttl(&mut cache_control);
+            if let Ok(normalized) = serde_json::to_value(cache_control) {
+                *raw = normalized;
+            }
+        }
+    }
+}
+
+/// Remove exact-duplicate text blocks from a system prompt array
+///
+/// Order is preserved and the first occurrence of each text is kept along with
+/// its cache_control. Non-text blocks are never removed.
+pub fn dedup_system_blocks(system: &mut Value) {
+    let Some(blocks) = system.as_array_mut() else {
+        return;
+    };
+    let mut seen = HashSet::new();
+    blocks.retain(|block| {
+        if block["type"] != "text" {
+            return true;
+        }
+        match block["text"].as_str() {
+            Some(text) => seen.insert(text.to_owned()),
+            None => true,
+        }
+    });
+}
+
 /// Tool definition
 ///
 /// Claude `tools` is a union type: it can include custom tools (which have an
@@ -396,6 +744,23 @@ pub enum Tool {
     Raw(serde_json::Value),
 }
 
+impl Tool {
+    /// Check whether this tool definition carries a cache_control breakpoint
+    pub fn has_cache_control(&self) -> bool {
+        match self {
+            Tool::Custom(tool) => tool.cache_control.is_some(),
+            Tool::Known(
+                KnownTool::Bash20250124 { cache_control, .. }
+                | KnownTool::TextEditor20250124 { cache_control, .. }
+                | KnownTool::TextEditor20250429 { cache_control, .. }
+                | KnownTool::TextEditor20250728 { cache_control, .. }
+                | KnownTool::WebSearch20250305 { cache_control, .. },
+            ) => cache_control.is_some(),
+            Tool::Raw(value) => value.get("cache_control").is_some_and(|c| !c.is_null()),
+        }
+    }
+}
+
 /// Custom tool definition (requires `input_schema`)
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CustomTool {
@@ -587,6 +952,22 @@ pub enum ToolChoiceObject {
     /// Model will not be allowed to use tools
     #[serde(rename = "none")]
     None,
+    /// A tool choice mode not known to this version, passed through as-is
+    #[serde(untagged)]
+    Other(#[serde(deserialize_with = "deserialize_object")] Value),
+}
+
+/// Deserializes a JSON object, rejecting every other kind of value
+fn deserialize_object<'de, D>(deserializer: D) -> Result<Value, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        value @ Value::Object(_) => Ok(value),
+        other => Err(serde::de::Error::custom(format!(
+            "expected a tool_choice object, got {other}"
+        ))),
+    }
 }
 
 /// Message metadata
@@ -621,7 +1002,6 @@ pub struct CreateMessageResponse {
 
 impl CreateMessageResponse {
     pub fn count_tokens(&self) -> u32 {
-        let bpe = o200k_base().expect("Failed to get encoding");
         let content = self
             .content
             .iter()
@@ -633,7 +1013,7 @@ impl CreateMessageResponse {
             })
             .collect::<Vec<_>>()
             .join("\n");
-        bpe.encode_with_special_tokens(&content).len() as u32
+        count_text_tokens(&content)
     }
 }
 
@@ -654,7 +1034,7 @@ impl CreateMessageResponse {
 }
 
 /// Reason for stopping message generation
-#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum StopReason {
     EndTurn,
@@ -671,6 +1051,12 @@ pub struct Usage {
     pub input_tokens: u32,
     /// Output tokens used
     pub output_tokens: u32,
+    /// Input tokens written to the prompt cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u32>,
+    /// Input tokens read from the prompt cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
@@ -709,6 +1095,7 @@ impl ContentBlock {
         Self::Text {
             text: text.into(),
             cache_control: None,
+            citations: None,
         }
     }
 
@@ -839,6 +1226,300 @@ mod tests {
         assert_eq!(reserialized["tools"][1]["type"], "text_editor_20250124");
     }
 
+    #[test]
+    fn deserializes_stringified_numbers() {
+        let params: CreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "max_tokens": "4096",
+            "top_k": "40",
+            "n": 1
+        }))
+        .unwrap();
+        assert_eq!(params.max_tokens, 4096);
+        assert_eq!(params.top_k, Some(40));
+        assert_eq!(params.n, Some(1));
+
+        // Numbers still serialize as numbers
+        let reserialized = serde_json::to_value(&params).unwrap();
+        assert_eq!(reserialized["max_tokens"], 4096);
+    }
+
+    #[test]
+    fn estimates_tokens_without_encoder() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abc"), 1);
+        assert_eq!(estimate_tokens("Hello, world!"), 4);
+        assert!(count_text_tokens("Hello, world!") > 0);
+    }
+
+    #[test]
+    fn deserializes_code_execution_tool_result() {
+        let block: ContentBlock = serde_json::from_value(json!({
+            "type": "code_execution_tool_result",
+            "tool_use_id": "srvtoolu_01",
+            "content": {
+                "type": "code_execution_result",
+                "stdout": "4\n",
+                "stderr": "",
+                "return_code": 0
+            }
+        }))
+        .unwrap();
+
+        match &block {
+            ContentBlock::CodeExecutionToolResult { data } => {
+                assert_eq!(data["tool_use_id"], "srvtoolu_01");
+                assert_eq!(data["content"]["stdout"], "4\n");
+            }
+            other => panic!("Expected code_execution_tool_result, got {:?}", other),
+        }
+        let reserialized = serde_json::to_value(&block).unwrap();
+        assert_eq!(reserialized["type"], "code_execution_tool_result");
+    }
+
+    #[test]
+    fn round_trips_bash_code_execution_tool_result() {
+        let raw = json!({
+            "type": "bash_code_execution_tool_result",
+            "tool_use_id": "srvtoolu_02",
+            "content": {
+                "type": "bash_code_execution_result",
+                "stdout": "ok\n",
+                "stderr": "",
+                "return_code": 0
+            }
+        });
+        let block: ContentBlock = serde_json::from_value(raw.clone()).unwrap();
+
+        assert!(matches!(
+            block,
+            ContentBlock::BashCodeExecutionToolResult { .. }
+        ));
+        assert_eq!(serde_json::to_value(&block).unwrap(), raw);
+    }
+
+    #[test]
+    fn deserializes_tool_role_message_as_tool_result() {
+        let body = json!({
+            "model": "claude-sonnet-4-5-20250929",
+            "messages": [
+                { "role": "user", "content": "list files" },
+                { "role": "tool", "tool_call_id": "toolu_01", "content": "a.txt b.txt" }
+            ]
+        });
+
+        let params: CreateMessageParams = serde_json::from_value(body).unwrap();
+        let msg = &params.messages[1];
+        assert_eq!(msg.role, Role::User);
+        match &msg.content {
+            MessageContent::Blocks { content } => {
+                assert_eq!(content.len(), 1);
+                match &content[0] {
+                    ContentBlock::ToolResult {
+                        tool_use_id,
+                        content,
+                        ..
+                    } => {
+                        assert_eq!(tool_use_id, "toolu_01");
+                        assert_eq!(content, &json!("a.txt b.txt"));
+                    }
+                    other => panic!("Expected tool_result block, got {:?}", other),
+                }
+            }
+            other => panic!("Expected blocks content, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_tool_role_message_without_tool_call_id() {
+        let body = json!({
+            "model": "claude-sonnet-4-5-20250929",
+            "messages": [
+                { "role": "user", "content": "list files" },
+                { "role": "tool", "content": "a.txt b.txt" }
+            ]
+        });
+
+        let err = serde_json::from_value::<CreateMessageParams>(body).unwrap_err();
+        assert!(err.to_string().contains("missing tool_call_id"));
+    }
+
+    #[test]
+    fn image_url_detail_round_trips() {
+        let block: ContentBlock = serde_json::from_value(json!({
+            "type": "image_url",
+            "image_url": { "url": "https://example.com/cat.png", "detail": "low" }
+        }))
+        .unwrap();
+
+        match &block {
+            ContentBlock::ImageUrl { image_url } => {
+                assert_eq!(image_url.detail.as_deref(), Some("low"));
+            }
+            other => panic!("Expected image_url block, got {:?}", other),
+        }
+        let reserialized = serde_json::to_value(&block).unwrap();
+        assert_eq!(reserialized["image_url"]["detail"], "low");
+    }
+
+    #[test]
+    fn normalizes_cache_control_ttl() {
+        let mut cc = CacheControlEphemeral {
+            type_: CacheControlType::Ephemeral,
+            ttl: Some("300s".to_string()),
+        };
+        normalize_ttl(&mut cc);
+        assert_eq!(cc.ttl.as_deref(), Some("5m"));
+
+        cc.ttl = Some("60m".to_string());
+        normalize_ttl(&mut cc);
+        assert_eq!(cc.ttl.as_deref(), Some("1h"));
+
+        cc.ttl = Some("1h".to_string());
+        normalize_ttl(&mut cc);
+        assert_eq!(cc.ttl.as_deref(), Some("1h"));
+
+        cc.ttl = Some("forever".to_string());
+        normalize_ttl(&mut cc);
+        assert_eq!(cc.ttl, None);
+
+        cc.ttl = Some("10m".to_string());
+        normalize_ttl(&mut cc);
+        assert_eq!(cc.ttl, None);
+    }
+
+    fn breakpoints(messages: &[Message]) -> Vec<(usize, usize)> {
+        messages
+            .iter()
+            .enumerate()
+            .filter_map(|(i, m)| match &m.content {
+                MessageContent::Blocks { content } => Some((i, content)),
+                MessageContent::Text { .. } => None,
+            })
+            .flat_map(|(i, content)| {
+                content.iter().enumerate().filter_map(move |(j, b)| match b {
+                    ContentBlock::Text {
+                        cache_control: Some(_),
+                        ..
+                    } => Some((i, j)),
+                    _ => None,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn applies_cache_breakpoints_to_last_blocks() {
+        let mut messages = vec![
+            Message::new_text(Role::User, "first"),
+            Message::new_text(Role::Assistant, "second"),
+            Message::new_blocks(
+                Role::User,
+                vec![
+                    ContentBlock::Text {
+                        text: "third".to_string(),
+                        cache_control: None,
+                        citations: None,
+                    },
+                    ContentBlock::Text {
+                        text: String::new(),
+                        cache_control: None,
+                        citations: None,
+                    },
+                ],
+            ),
+        ];
+
+        assert_eq!(apply_cache_breakpoints(&mut messages, 2, 0), 2);
+        // The empty trailing block is skipped
+        assert_eq!(breakpoints(&messages), vec![(1, 0), (2, 0)]);
+        assert!(matches!(messages[0].content, MessageContent::Text { .. }));
+    }
+
+    #[test]
+    fn caps_cache_breakpoints_at_four() {
+        let mut messages: Vec<Message> = (0..6)
+            .map(|i| Message::new_text(Role::User, format!("turn {i}")))
+            .collect();
+        assert_eq!(apply_cache_breakpoints(&mut messages, 10, 0), MAX_CACHE_BREAKPOINTS);
+        assert_eq!(breakpoints(&messages), vec![(2, 0), (3, 0), (4, 0), (5, 0)]);
+
+        // Existing breakpoints use up the budget
+        assert_eq!(apply_cache_breakpoints(&mut messages, 2, 0), 0);
+    }
+
+    #[test]
+    fn reserves_cache_breakpoints_for_system_and_tools() {
+        let system = json!([
+            {"type": "text", "text": "You are helpful.", "cache_control": {"type": "ephemeral"}},
+            {"type": "text", "text": "Be brief."}
+        ]);
+        let tools: Vec<Tool> = serde_json::from_value(json!([
+            {
+                "name": "lookup",
+                "input_schema": {"type": "object"},
+                "cache_control": {"type": "ephemeral"}
+            },
+            {"name": "search", "input_schema": {"type": "object"}}
+        ]))
+        .unwrap();
+        let reserved = count_prefix_cache_breakpoints(Some(&system), Some(&tools));
+        assert_eq!(reserved, 2);
+
+        let mut messages: Vec<Message> = (0..4)
+            .map(|i| Message::new_text(Role::User, format!("turn {i}")))
+            .collect();
+        assert_eq!(apply_cache_breakpoints(&mut messages, 4, reserved), 2);
+        assert_eq!(breakpoints(&messages), vec![(2, 0), (3, 0)]);
+    }
+
+    #[test]
+    fn counts_tool_result_array_content() {
+        let tool_result = |content: Value| ContentBlock::ToolResult {
+            tool_use_id: "toolu_01".to_string(),
+            content,
+            is_error: None,
+            cache_control: None,
+        };
+        let params = |content: Value| CreateMessageParams {
+            messages: vec![Message::new_blocks(Role::User, vec![tool_result(content)])],
+            ..Default::default()
+        };
+
+        let text = "The build finished with three warnings about unused imports.";
+        let array = json!([
+            {"type": "text", "text": text},
+            {"type": "image", "source": {"type": "url", "url": "https://example.com/a.png"}}
+        ]);
+        assert_eq!(tool_result_text(&array), text);
+        assert_eq!(params(array).count_tokens(), count_text_tokens(text));
+        assert!(params(json!([])).count_tokens() < count_text_tokens(text));
+    }
+
+    #[test]
+    fn dedups_system_blocks() {
+        let mut system = json!([
+            {"type": "text", "text": "You are helpful.", "cache_control": {"type": "ephemeral"}},
+            {"type": "text", "text": "Be brief."},
+            {"type": "text", "text": "You are helpful."},
+            {"type": "text", "text": "Be brief.", "cache_control": {"type": "ephemeral"}}
+        ]);
+        dedup_system_blocks(&mut system);
+        assert_eq!(
+            system,
+            json!([
+                {"type": "text", "text": "You are helpful.", "cache_control": {"type": "ephemeral"}},
+                {"type": "text", "text": "Be brief."}
+            ])
+        );
+
+        // String system prompts are left alone
+        let mut system = json!("You are helpful.");
+        dedup_system_blocks(&mut system);
+        assert_eq!(system, json!("You are helpful."));
+    }
+
     #[test]
     fn deserializes_tool_choice_string_format() {
         // Test string format "auto"
@@ -911,6 +1592,45 @@ mod tests {
         }
     }
 
+    #[test]
+    fn round_trips_container() {
+        let body = json!({
+            "max_tokens": 1024,
+            "messages": [{ "role": "user", "content": "run it again" }],
+            "model": "claude-sonnet-4-5-20250929",
+            "container": "container_011CPR5CNjB747bTd36fQLFk"
+        });
+        let params: CreateMessageParams = serde_json::from_value(body).unwrap();
+        assert_eq!(params.container.as_deref(), Some("container_011CPR5CNjB747bTd36fQLFk"));
+        let value = serde_json::to_value(&params).unwrap();
+        assert_eq!(value["container"], "container_011CPR5CNjB747bTd36fQLFk");
+
+        let params = CreateMessageParams::default();
+        let value = serde_json::to_value(&params).unwrap();
+        assert!(value.get("container").is_none());
+    }
+
+    #[test]
+    fn passes_through_unknown_tool_choice_type() {
+        let body = json!({
+            "max_tokens": 1024,
+            "messages": [{ "role": "user", "content": "hi" }],
+            "model": "claude-sonnet-4-5-20250929",
+            "tool_choice": { "type": "tool_group", "names": ["a", "b"] }
+        });
+        let params: CreateMessageParams = serde_json::from_value(body).unwrap();
+        match &params.tool_choice {
+            Some(ToolChoice::Object(ToolChoiceObject::Other(value))) => {
+                assert_eq!(value["type"], "tool_group")
+            }
+            other => panic!("Expected ToolChoiceObject::Other, got {:?}", other),
+        }
+        assert_eq!(
+            serde_json::to_value(&params.tool_choice).unwrap(),
+            json!({ "type": "tool_group", "names": ["a", "b"] })
+        );
+    }
+
     #[test]
     fn deserializes_image_url_content_block() {
         let body = json!({
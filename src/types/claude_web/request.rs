@@ -50,6 +50,10 @@ pub struct Tool {
     pub name: String,
     #[serde(rename = "type")]
     pub type_: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_domains: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked_domains: Option<Vec<String>>,
 }
 
 impl Tool {
@@ -57,6 +61,20 @@ impl Tool {
         Tool {
             name: "web_search".to_string(),
             type_: "web_search_v0".to_string(),
+            allowed_domains: None,
+            blocked_domains: None,
         }
     }
+
+    /// Restricts results to the given domains, ignored if empty
+    pub fn allowed_domains(mut self, domains: Vec<String>) -> Self {
+        self.allowed_domains = (!domains.is_empty()).then_some(domains);
+        self
+    }
+
+    /// Excludes results from the given domains, ignored if empty
+    pub fn blocked_domains(mut self, domains: Vec<String>) -> Self {
+        self.blocked_domains = (!domains.is_empty()).then_some(domains);
+        self
+    }
 }
@@ -60,7 +60,7 @@ where
     fn from(str: S) -> Self {
         Message::new_blocks(
             Role::Assistant,
-            vec![ContentBlock::Text { text: str.into(), cache_control: None }],
+            vec![ContentBlock::Text { text: str.into(), cache_control: None, citations: None }],
         )
     }
 }
@@ -128,7 +128,7 @@ impl ClaudeWebState {
                         ).await.map(|v| v as u64);
                     }
                     let out = out.unwrap_or_else(|| {
-                        let usage = crate::types::claude::Usage { input_tokens: input_tokens as u32, output_tokens: 0 };
+                        let usage = crate::types::claude::Usage { input_tokens: input_tokens as u32, output_tokens: 0, ..Default::default() };
                         let resp = crate::types::claude::CreateMessageResponse::text(acc.clone(), Default::default(), usage);
                         resp.count_tokens() as u64
                     });
@@ -1,13 +1,19 @@
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use tiktoken_rs::o200k_base;
+use serde_with::{DefaultOnNull, DisplayFromStr, PickFirst, serde_as};
 
 use super::claude::{CreateMessageParams as ClaudeCreateMessageParams, *};
 use crate::format::{
-    annotations_to_web_search_content, clean_json_schema, ensure_valid_schema,
-    move_constraints_to_description, oai_image_url_to_claude, remap_oai_to_claude_args,
-    remap_tool_result_args,
+    ConversionReport, ToolIdMap, annotations_to_web_search_content, clean_json_schema,
+    clean_json_schema_strict, count_image_urls, decode_json_tool_results, ensure_valid_schema,
+    move_constraints_to_description, normalize_tool_ids, oai_image_url_to_claude,
+    remap_oai_to_claude_args, require_all_properties, sanitize_tool_id,
 };
+use crate::config::{
+    ClewdrConfig, default_effort_high_budget, default_effort_low_budget,
+    default_effort_medium_budget, default_effort_minimal_budget, default_image_media_type,
+};
+use crate::error::ClewdrError;
 use crate::types::claude::Message;
 
 /// OpenAI-specific role that includes "tool" for tool results
@@ -32,13 +38,64 @@ impl From<OaiRole> for Role {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum Effort {
-    Low = 256,
+    /// Disables thinking entirely
+    None,
+    Minimal,
+    Low,
     #[default]
-    Medium = 256 * 8,
-    High = 256 * 8 * 8,
+    Medium,
+    High,
+}
+
+impl Effort {
+    /// Thinking budget for this effort level, `None` if thinking is disabled
+    pub fn budget(&self, budgets: &EffortBudgets) -> Option<u64> {
+        match self {
+            Effort::None => None,
+            Effort::Minimal => Some(budgets.minimal),
+            Effort::Low => Some(budgets.low),
+            Effort::Medium => Some(budgets.medium),
+            Effort::High => Some(budgets.high),
+        }
+    }
+}
+
+/// Resolve the thinking configuration from `thinking` and `reasoning_effort`
+///
+/// An explicit `thinking` wins over the effort level, except for
+/// `reasoning_effort: "none"` which always disables thinking.
+fn resolve_thinking(
+    thinking: Option<Thinking>,
+    effort: Option<Effort>,
+    budgets: &EffortBudgets,
+) -> Option<Thinking> {
+    if effort == Some(Effort::None) {
+        return None;
+    }
+    thinking.or_else(|| effort?.budget(budgets).map(Thinking::new))
+}
+
+/// Thinking budgets used for each `reasoning_effort` level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EffortBudgets {
+    pub minimal: u64,
+    pub low: u64,
+    pub medium: u64,
+    pub high: u64,
+}
+
+impl Default for EffortBudgets {
+    fn default() -> Self {
+        Self {
+            minimal: default_effort_minimal_budget(),
+            low: default_effort_low_budget(),
+            medium: default_effort_medium_budget(),
+            high: default_effort_high_budget(),
+        }
+    }
 }
 
 /// OpenAI format message content
@@ -52,6 +109,8 @@ pub enum OaiMessageContent {
     Blocks(Vec<ContentBlock>),
     /// Null content (for assistant messages with only tool_calls)
     Null,
+    /// JSON object content, sent by some SDKs for tool results
+    Object(serde_json::Map<String, Value>),
 }
 
 impl Default for OaiMessageContent {
@@ -67,6 +126,9 @@ impl OaiMessageContent {
             OaiMessageContent::Text(text) => MessageContent::Text { content: text },
             OaiMessageContent::Blocks(blocks) => MessageContent::Blocks { content: blocks },
             OaiMessageContent::Null => MessageContent::Text { content: String::new() },
+            OaiMessageContent::Object(obj) => MessageContent::Text {
+                content: Value::Object(obj).to_string(),
+            },
         }
     }
 }
@@ -87,6 +149,18 @@ pub struct OaiMessage {
     /// Annotations (web search citations) for content
     #[serde(skip_serializing_if = "Option::is_none")]
     pub annotations: Option<Vec<Value>>,
+    /// Message-level prompt caching breakpoint, applied to the last content block
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<CacheControlEphemeral>,
+    /// Reasoning text replayed from a previous assistant turn
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+    /// Thinking signature matching `reasoning_content`
+    #[serde(alias = "signature", skip_serializing_if = "Option::is_none")]
+    pub reasoning_signature: Option<String>,
+    /// Refusal text of a previous assistant turn
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
 }
 
 /// OpenAI tool call format
@@ -115,6 +189,12 @@ pub enum OaiTool {
     /// Claude custom tool format (passthrough)
     #[serde(rename = "custom")]
     Custom(CustomTool),
+    /// OpenAI built-in web search tool
+    #[serde(rename = "web_search_preview", alias = "web_search")]
+    WebSearch {
+        #[serde(flatten)]
+        params: Value,
+    },
     /// Other tool types (passthrough as raw)
     #[serde(other)]
     Other,
@@ -128,6 +208,47 @@ pub struct OaiToolFunction {
     pub description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub parameters: Option<Value>,
+    /// Whether the arguments must follow the schema exactly
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+/// Read a web search setting from OAI function parameters
+///
+/// Values that are missing or have the wrong shape are ignored, so a JSON
+/// schema passed as `parameters` does not break the conversion.
+fn web_search_param<T: serde::de::DeserializeOwned>(
+    params: Option<&Value>,
+    key: &str,
+) -> Option<T> {
+    params
+        .and_then(|p| p.get(key))
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+}
+
+/// Merge the OAI `user` field into Claude metadata as `user_id`
+///
+/// A `user_id` already present in the metadata takes precedence.
+fn metadata_with_user(metadata: Option<Metadata>, user: Option<String>) -> Option<Metadata> {
+    let Some(user) = user.filter(|u| !u.is_empty()) else {
+        return metadata;
+    };
+    let mut metadata = metadata.unwrap_or_default();
+    metadata.fields.entry("user_id".to_string()).or_insert(user);
+    Some(metadata)
+}
+
+/// Build Claude's built-in web search tool from OAI web search settings
+fn web_search_tool(params: Option<&Value>) -> Tool {
+    Tool::Known(KnownTool::WebSearch20250305 {
+        name: ToolNameWebSearch::WebSearch,
+        allowed_domains: web_search_param(params, "allowed_domains"),
+        blocked_domains: web_search_param(params, "blocked_domains"),
+        cache_control: None,
+        max_uses: web_search_param(params, "max_uses"),
+        user_location: web_search_param(params, "user_location"),
+        extra: std::collections::HashMap::new(),
+    })
 }
 
 impl From<OaiTool> for Tool {
@@ -137,16 +258,9 @@ impl From<OaiTool> for Tool {
                 // Check for special built-in tools
                 match function.name.as_str() {
                     "web_search" => {
-                        // Convert to Claude's built-in web_search tool
-                        Tool::Known(KnownTool::WebSearch20250305 {
-                            name: ToolNameWebSearch::WebSearch,
-                            allowed_domains: None,
-                            blocked_domains: None,
-                            cache_control: None,
-                            max_uses: None,
-                            user_location: None,
-                            extra: std::collections::HashMap::new(),
-                        })
+                        // Convert to Claude's built-in web_search tool,
+                        // keeping any settings the client passed in `parameters`
+                        web_search_tool(function.parameters.as_ref())
                     }
                     "bash" => {
                         // Convert to Claude's built-in bash tool
@@ -189,6 +303,7 @@ impl From<OaiTool> for Tool {
                 type_: Some(CustomToolType::Custom),
                 ..custom
             }),
+            OaiTool::WebSearch { params } => web_search_tool(Some(&params)),
             OaiTool::Other => Tool::Raw(json!({})),
         }
     }
@@ -236,25 +351,391 @@ fn annotations_to_server_tool_blocks(annotations: &[Value]) -> Vec<ContentBlock>
     ]
 }
 
-/// Sanitize a tool ID to match Claude API's required pattern: ^[a-zA-Z0-9_-]+$
-/// Replaces any invalid characters with underscores.
-fn sanitize_tool_id(id: &str) -> String {
-    if id.is_empty() {
-        return id.to_string();
+/// Options controlling the OpenAI → Claude request conversion
+#[derive(Debug, Clone)]
+pub struct OaiRequestOptions {
+    /// Maximum number of tools forwarded to Claude, unlimited if None
+    pub max_tools: Option<usize>,
+    /// Rewrite tool call IDs that do not follow Claude's `toolu_` convention
+    pub normalize_tool_ids: bool,
+    /// Thinking budgets for `reasoning_effort`
+    pub effort_budgets: EffortBudgets,
+    /// Coerce string tool_choice to object form, required by Claude Code
+    pub object_tool_choice: bool,
+    /// Unwrap tool results whose text is JSON-encoded
+    pub decode_json_tool_results: bool,
+    /// Prefix marking a leading user message as the system prompt, off if None
+    pub system_prompt_prefix: Option<String>,
+    /// Media type of bare base64 images whose format can't be detected
+    pub default_media_type: String,
+}
+
+impl Default for OaiRequestOptions {
+    fn default() -> Self {
+        Self {
+            max_tools: None,
+            normalize_tool_ids: false,
+            effort_budgets: EffortBudgets::default(),
+            object_tool_choice: true,
+            decode_json_tool_results: false,
+            system_prompt_prefix: None,
+            default_media_type: default_image_media_type(),
+        }
     }
-    id.chars()
-        .map(|c| {
-            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
-                c
-            } else {
-                '_'
+}
+
+impl OaiRequestOptions {
+    /// Builds request options from the current configuration
+    ///
+    /// The web backend keeps the string tool_choice form of the public API.
+    pub fn from_config(config: &ClewdrConfig, web_backend: bool) -> Self {
+        Self {
+            max_tools: config.max_tools,
+            normalize_tool_ids: config.normalize_tool_ids,
+            effort_budgets: EffortBudgets {
+                minimal: config.effort_minimal_budget,
+                low: config.effort_low_budget,
+                medium: config.effort_medium_budget,
+                high: config.effort_high_budget,
+            },
+            object_tool_choice: !web_backend,
+            decode_json_tool_results: config.decode_json_tool_results,
+            system_prompt_prefix: config.system_prompt_prefix.to_owned(),
+            default_media_type: config.default_image_media_type.to_owned(),
+        }
+    }
+}
+
+/// Convert OAI tools to Claude tools, cleaning schemas and enforcing the tool limit
+fn convert_tools(
+    tools: Vec<OaiTool>,
+    options: &OaiRequestOptions,
+    tool_choice: Option<&ToolChoice>,
+    report: &mut ConversionReport,
+) -> Vec<Tool> {
+    let tools = tools
+        .into_iter()
+        .filter_map(|oai_tool| {
+            let strict = matches!(
+                &oai_tool,
+                OaiTool::Function { function } if function.strict == Some(true)
+            );
+            let tool: Tool = oai_tool.into();
+            match tool {
+                Tool::Custom(mut custom) => {
+                    let original_schema = custom.input_schema.clone();
+                    // Full schema cleaning pipeline:
+                    // 0. Strict functions require every property
+                    if strict {
+                        require_all_properties(&mut custom.input_schema);
+                    }
+                    // 1. Move constraints to description (before removing them)
+                    move_constraints_to_description(&mut custom.input_schema);
+                    // 2. Clean unsupported keywords
+                    if strict {
+                        clean_json_schema_strict(&mut custom.input_schema);
+                    } else {
+                        clean_json_schema(&mut custom.input_schema);
+                    }
+                    // 3. Ensure schema is valid
+                    ensure_valid_schema(&mut custom.input_schema);
+                    if custom.input_schema != original_schema {
+                        report.schemas_cleaned += 1;
+                    }
+                    // Ensure type is set to custom for Claude Code API
+                    custom.type_ = Some(CustomToolType::Custom);
+                    Some(Tool::Custom(custom))
+                }
+                Tool::Raw(v) if v.as_object().map(|o| o.is_empty()).unwrap_or(true) => {
+                    // Filter out empty tools from OaiTool::Other
+                    None
+                }
+                other => Some(other),
             }
         })
+        .collect::<Vec<_>>();
+    let Some(max_tools) = options.max_tools else {
+        return tools;
+    };
+    let (tools, dropped) = limit_tools(tools, max_tools, tool_choice);
+    if !dropped.is_empty() {
+        tracing::warn!(
+            "[OAI→Claude] Dropping {} tools over the limit of {}: {}",
+            dropped.len(),
+            max_tools,
+            dropped.join(", ")
+        );
+        report.tools_dropped += dropped.len();
+    }
+    tools
+}
+
+/// Name of a tool regardless of its variant
+fn tool_name(tool: &Tool) -> Option<String> {
+    match tool {
+        Tool::Custom(custom) => Some(custom.name.to_owned()),
+        other => serde_json::to_value(other)
+            .ok()?
+            .get("name")?
+            .as_str()
+            .map(str::to_string),
+    }
+}
+
+/// Keep at most `max_tools` tools, preferring the one named by `tool_choice`
+///
+/// Tools keep their original order. The tool forced by a `{"type": "tool"}`
+/// choice is always retained, and the remaining slots go to the first tools.
+///
+/// # Returns
+/// The retained tools and the names of the dropped ones
+fn limit_tools(
+    tools: Vec<Tool>,
+    max_tools: usize,
+    tool_choice: Option<&ToolChoice>,
+) -> (Vec<Tool>, Vec<String>) {
+    if tools.len() <= max_tools {
+        return (tools, vec![]);
+    }
+    let preferred = match tool_choice {
+        Some(ToolChoice::Object(ToolChoiceObject::Tool { name, .. })) => Some(name.as_str()),
+        _ => None,
+    };
+    let preferred_idx = preferred
+        .and_then(|name| tools.iter().position(|t| tool_name(t).as_deref() == Some(name)))
+        .filter(|_| max_tools > 0);
+    let mut remaining = max_tools - usize::from(preferred_idx.is_some());
+    let mut kept = Vec::with_capacity(max_tools);
+    let mut dropped = Vec::new();
+    for (i, tool) in tools.into_iter().enumerate() {
+        if Some(i) == preferred_idx {
+            kept.push(tool);
+        } else if remaining > 0 {
+            remaining -= 1;
+            kept.push(tool);
+        } else {
+            dropped.push(tool_name(&tool).unwrap_or_default());
+        }
+    }
+    (kept, dropped)
+}
+
+/// Flatten system messages into a Claude system block array
+///
+/// Only text blocks are kept. Block-level cache_control set by the client is
+/// preserved so the final system block can still act as a caching breakpoint.
+fn flatten_system_messages(systems: Vec<Message>) -> Option<Value> {
+    let systems = systems
+        .into_iter()
+        .map(|m| m.content)
+        .flat_map(|c| match c {
+            MessageContent::Text { content } => vec![ContentBlock::Text {
+                text: content,
+                cache_control: None,
+                citations: None,
+            }],
+            MessageContent::Blocks { content } => content,
+        })
+        .filter(|b| matches!(b, ContentBlock::Text { .. }))
+        .map(|b| json!(b))
+        .collect::<Vec<_>>();
+    (!systems.is_empty()).then(|| json!(systems))
+}
+
+/// Promote a leading user message starting with `prefix` to a system message
+///
+/// Some clients send the system prompt as the first user message, marked
+/// with a prefix such as "System:". The prefix is stripped, and the message
+/// is only promoted if it holds nothing but text and more messages follow.
+///
+/// # Arguments
+/// * `messages` - The converted messages, system messages included
+/// * `prefix` - The prefix marking the system prompt
+///
+/// # Returns
+/// Whether a message was promoted
+fn promote_prefixed_system_prompt(messages: &mut [Message], prefix: &str) -> bool {
+    if prefix.is_empty() {
+        return false;
+    }
+    let Some(pos) = messages.iter().position(|m| m.role != Role::System) else {
+        return false;
+    };
+    if pos + 1 == messages.len() || messages[pos].role != Role::User {
+        return false;
+    }
+    let msg = &mut messages[pos];
+    let text = match &mut msg.content {
+        MessageContent::Text { content } => content,
+        MessageContent::Blocks { content } => match content.as_mut_slice() {
+            [ContentBlock::Text { text, .. }] => text,
+            _ => return false,
+        },
+    };
+    let Some(prompt) = text.trim_start().strip_prefix(prefix) else {
+        return false;
+    };
+    *text = prompt.trim_start().to_string();
+    msg.role = Role::System;
+    true
+}
+
+/// Label of a mid-conversation system message kept in place
+const SYSTEM_NOTE_LABEL: &str = "[System note]";
+
+/// Separate the leading system messages from the conversation
+///
+/// Only system messages before the first other message become the system
+/// prompt. Later ones are kept in place as labeled user notes, since hoisting
+/// them to the front would change what they apply to.
+///
+/// # Arguments
+/// * `messages` - The converted messages
+///
+/// # Returns
+/// The leading system messages and the remaining conversation
+fn split_system_messages(mut messages: Vec<Message>) -> (Vec<Message>, Vec<Message>) {
+    let leading = messages.iter().take_while(|m| m.role == Role::System).count();
+    let rest = messages
+        .split_off(leading)
+        .into_iter()
+        .filter_map(|m| {
+            if m.role != Role::System {
+                return Some(m);
+            }
+            let text = match m.content {
+                MessageContent::Text { content } => content,
+                MessageContent::Blocks { content } => content
+                    .into_iter()
+                    .filter_map(|b| match b {
+                        ContentBlock::Text { text, .. } => Some(text),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            };
+            if text.trim().is_empty() {
+                return None;
+            }
+            tracing::debug!("[Format] Keeping mid-conversation system message as a user note");
+            Some(Message::new_text(Role::User, format!("{SYSTEM_NOTE_LABEL} {text}")))
+        })
+        .collect();
+    (messages, rest)
+}
+
+/// Check if a message holds nothing but tool_use blocks
+fn is_tool_calls_only(msg: &Message) -> bool {
+    match &msg.content {
+        MessageContent::Blocks { content } => {
+            !content.is_empty() && content.iter().all(|b| matches!(b, ContentBlock::ToolUse { .. }))
+        }
+        MessageContent::Text { .. } => false,
+    }
+}
+
+/// Turn message content into blocks, dropping empty text
+fn into_blocks(content: MessageContent) -> Vec<ContentBlock> {
+    match content {
+        MessageContent::Text { content } if content.is_empty() => vec![],
+        MessageContent::Text { content } => vec![ContentBlock::Text {
+            text: content,
+            cache_control: None,
+            citations: None,
+        }],
+        MessageContent::Blocks { content } => content,
+    }
+}
+
+/// Merge tool_calls sent as a separate assistant message into the turn before it
+///
+/// Some clients send the assistant text and its tool_calls as two consecutive
+/// assistant messages, while Claude expects them in a single turn.
+fn merge_split_tool_calls(messages: Vec<Message>) -> Vec<Message> {
+    let mut merged: Vec<Message> = Vec::with_capacity(messages.len());
+    for msg in messages {
+        let mergeable = msg.role == Role::Assistant && is_tool_calls_only(&msg);
+        let Some(prev) = merged.pop_if(|prev| mergeable && prev.role == Role::Assistant) else {
+            merged.push(msg);
+            continue;
+        };
+        let mut content = into_blocks(prev.content);
+        content.extend(into_blocks(msg.content));
+        merged.push(Message::new_blocks(Role::Assistant, content));
+    }
+    merged
+}
+
+/// Convert the blocks of an OAI content array, keeping the client's order
+///
+/// `image_url` blocks are replaced in place by native image blocks when they
+/// can be converted, so text and images stay interleaved as sent.
+fn convert_content_blocks(
+    content: Vec<ContentBlock>,
+    default_media_type: &str,
+) -> Vec<ContentBlock> {
+    content
+        .into_iter()
+        .map(|block| match block {
+            ContentBlock::ImageUrl { ref image_url } => {
+                oai_image_url_to_claude(image_url, default_media_type).unwrap_or(block)
+            }
+            block => block,
+        })
         .collect()
 }
 
+/// Rewrite the tool call IDs of OAI messages to unique Claude-style IDs
+///
+/// # Returns
+/// The number of tool calls whose ID was rewritten
+fn normalize_oai_tool_ids(messages: &mut [OaiMessage]) -> usize {
+    let tool_call_ids = messages
+        .iter()
+        .flat_map(|msg| msg.tool_calls.iter().flatten())
+        .map(|tc| tc.id.as_str());
+    let map = ToolIdMap::new(tool_call_ids);
+    for msg in messages.iter_mut() {
+        for tc in msg.tool_calls.iter_mut().flatten() {
+            if let Some(rewritten) = map.get(&tc.id) {
+                tc.id = rewritten.to_string();
+            }
+        }
+        if let Some(id) = msg.tool_call_id.as_mut()
+            && let Some(rewritten) = map.get(id)
+        {
+            *id = rewritten.to_string();
+        }
+    }
+    map.len()
+}
+
 /// Convert OAI message to Claude message
-fn convert_oai_message(msg: OaiMessage) -> Message {
+///
+/// Content blocks keep their order. Tool calls follow the content, and blocks
+/// reconstructed from annotations come last.
+fn convert_oai_message(mut msg: OaiMessage, default_media_type: &str) -> Message {
+    // Replayed reasoning becomes a leading thinking block on assistant turns
+    let thinking_block = msg
+        .reasoning_content
+        .take()
+        .filter(|thinking| msg.role == OaiRole::Assistant && !thinking.is_empty())
+        .map(|thinking| ContentBlock::Thinking {
+            thinking,
+            signature: msg.reasoning_signature.take(),
+            cache_control: None,
+        });
+    // A replayed refusal is kept as plain text so the history stays complete
+    let refusal_block = msg
+        .refusal
+        .take()
+        .filter(|refusal| msg.role == OaiRole::Assistant && !refusal.is_empty())
+        .map(|text| ContentBlock::Text {
+            text,
+            cache_control: None,
+            citations: None,
+        });
+
     match msg.role {
         OaiRole::Tool => {
             // Convert tool role to user message with tool_result block
@@ -268,30 +749,26 @@ fn convert_oai_message(msg: OaiMessage) -> Message {
                 }
                 OaiMessageContent::Blocks(blocks) => json!(blocks),
                 OaiMessageContent::Null => json!(""),
+                // Objects are not accepted by Claude, so they are sent as JSON text
+                OaiMessageContent::Object(obj) => json!(Value::Object(obj).to_string()),
             };
-            
-            // Apply reverse parameter remapping (only if content is an object)
-            let mut remapped_content = content_value.clone();
-            if remapped_content.is_object() {
-                remap_tool_result_args(&tool_use_id, &mut remapped_content);
-            }
-            
+
             Message {
                 role: Role::User,
                 content: MessageContent::Blocks {
                     content: vec![ContentBlock::ToolResult {
                         tool_use_id,
-                        content: remapped_content,
+                        content: content_value,
                         is_error: None,
                         cache_control: None,
                     }],
                 },
             }
         }
-        OaiRole::Assistant if msg.tool_calls.is_some() => {
+        OaiRole::Assistant if msg.tool_calls.as_ref().is_some_and(|calls| !calls.is_empty()) => {
             // Convert assistant message with tool_calls to Claude format
             let tool_calls = msg.tool_calls.unwrap();
-            let mut blocks: Vec<ContentBlock> = Vec::new();
+            let mut blocks: Vec<ContentBlock> = thinking_block.into_iter().collect();
             
             // Add text content if present
             match msg.content {
@@ -299,13 +776,15 @@ fn convert_oai_message(msg: OaiMessage) -> Message {
                     blocks.push(ContentBlock::Text {
                         text,
                         cache_control: None,
+                        citations: None,
                     });
                 }
                 OaiMessageContent::Blocks(content) => {
-                    blocks.extend(content);
+                    blocks.extend(convert_content_blocks(content, default_media_type));
                 }
                 _ => {}
             }
+            blocks.extend(refusal_block);
             
             // Add tool_use blocks
             for tc in tool_calls {
@@ -337,34 +816,33 @@ fn convert_oai_message(msg: OaiMessage) -> Message {
         }
         _ => {
             // Standard message conversion - with image format conversion
-            let mut blocks: Vec<ContentBlock> = Vec::new();
+            let mut blocks: Vec<ContentBlock> = thinking_block.into_iter().collect();
             
             // First, convert message content
             match msg.content {
                 OaiMessageContent::Blocks(content) => {
                     // Convert ImageUrl blocks to native Image format
-                    let converted: Vec<ContentBlock> = content
-                        .into_iter()
-                        .map(|block| {
-                            if let ContentBlock::ImageUrl { ref image_url } = block {
-                                oai_image_url_to_claude(image_url).unwrap_or(block)
-                            } else {
-                                block
-                            }
-                        })
-                        .collect();
-                    blocks.extend(converted);
+                    blocks.extend(convert_content_blocks(content, default_media_type));
                 }
                 OaiMessageContent::Text(text) => {
                     if !text.is_empty() {
                         blocks.push(ContentBlock::Text {
                             text,
                             cache_control: None,
+                            citations: None,
                         });
                     }
                 }
                 OaiMessageContent::Null => {}
+                OaiMessageContent::Object(obj) => {
+                    blocks.push(ContentBlock::Text {
+                        text: Value::Object(obj).to_string(),
+                        cache_control: None,
+                        citations: None,
+                    });
+                }
             };
+            blocks.extend(refusal_block);
             
             // Handle annotations (web search citations)
             if let Some(ref annotations) = msg.annotations {
@@ -387,11 +865,19 @@ fn convert_oai_message(msg: OaiMessage) -> Message {
                         blocks.push(ContentBlock::Text {
                             text: citation_text,
                             cache_control: None,
+                            citations: None,
                         });
                     }
                 }
             }
             
+            // Apply message-level cache_control to the last block that supports it
+            if let Some(cache_control) = msg.cache_control {
+                if let Some(block) = blocks.last_mut() {
+                    block.set_cache_control(cache_control);
+                }
+            }
+
             let content = if blocks.is_empty() {
                 MessageContent::Text { content: String::new() }
             } else {
@@ -408,85 +894,85 @@ fn convert_oai_message(msg: OaiMessage) -> Message {
 
 impl From<CreateMessageParams> for ClaudeCreateMessageParams {
     fn from(params: CreateMessageParams) -> Self {
-        let (systems, messages): (Vec<Message>, Vec<Message>) = params
-            .messages
-            .into_iter()
-            .partition(|m| m.role == Role::System);
-        let systems = systems
-            .into_iter()
-            .map(|m| m.content)
-            .flat_map(|c| match c {
-                MessageContent::Text { content } => vec![ContentBlock::Text {
-                    text: content,
-                    cache_control: None,
-                }],
-                MessageContent::Blocks { content } => content,
-            })
-            .filter(|b| matches!(b, ContentBlock::Text { .. }))
-            .map(|b| json!(b))
-            .collect::<Vec<_>>();
-        let system = (!systems.is_empty()).then(|| json!(systems));
-        
-        // Convert OAI tools to Claude tools and clean schemas
-        let tools = params.tools.map(|tools| {
-            tools.into_iter().filter_map(|oai_tool| {
-                let tool: Tool = oai_tool.into();
-                match tool {
-                    Tool::Custom(mut custom) => {
-                        // Full schema cleaning pipeline:
-                        // 1. Move constraints to description (before removing them)
-                        move_constraints_to_description(&mut custom.input_schema);
-                        // 2. Clean unsupported keywords
-                        clean_json_schema(&mut custom.input_schema);
-                        // 3. Ensure schema is valid
-                        ensure_valid_schema(&mut custom.input_schema);
-                        // Ensure type is set to custom for Claude Code API
-                        custom.type_ = Some(CustomToolType::Custom);
-                        Some(Tool::Custom(custom))
-                    }
-                    Tool::Raw(v) if v.as_object().map(|o| o.is_empty()).unwrap_or(true) => {
-                        // Filter out empty tools from OaiTool::Other
-                        None
-                    }
-                    other => Some(other),
-                }
-            }).collect()
-        });
+        params.convert_with_report(&OaiRequestOptions::default()).0
+    }
+}
+
+impl CreateMessageParams {
+    /// Convert into Claude params, recording what the conversion discarded
+    pub fn convert_with_report(
+        self,
+        options: &OaiRequestOptions,
+    ) -> (ClaudeCreateMessageParams, ConversionReport) {
+        let mut report = ConversionReport {
+            frequency_penalty: self.frequency_penalty,
+            presence_penalty: self.presence_penalty,
+            store: self.store,
+            logprobs_ignored: self.logprobs == Some(true) || self.top_logprobs.is_some(),
+            ..Default::default()
+        };
+        let (systems, messages) = split_system_messages(self.messages);
+        let system = flatten_system_messages(systems);
+        let mut messages = merge_split_tool_calls(messages);
+        if options.normalize_tool_ids {
+            report.tool_ids_rewritten = normalize_tool_ids(&mut messages);
+        }
         
         // Convert tool_choice from Simple to Object format for Claude Code API compatibility
         // Claude Code API requires object format: {"type": "auto"} instead of "auto"
-        let tool_choice = params.tool_choice.map(|tc| tc.to_object_format());
+        let tool_choice = self.tool_choice.map(|tc| {
+            if options.object_tool_choice {
+                tc.to_object_format()
+            } else {
+                tc
+            }
+        });
+
+        let tools = self
+            .tools
+            .map(|tools| convert_tools(tools, options, tool_choice.as_ref(), &mut report));
         
-        Self {
-            max_tokens: (params.max_tokens.or(params.max_completion_tokens))
+        let converted = ClaudeCreateMessageParams {
+            max_tokens: (self.max_tokens.or(self.max_completion_tokens))
                 .unwrap_or_else(default_max_tokens),
             system,
             messages,
-            model: params.model,
-            stop_sequences: params.stop,
-            thinking: params
-                .thinking
-                .or_else(|| params.reasoning_effort.map(|e| Thinking::new(e as u64))),
-            temperature: params.temperature,
-            stream: params.stream,
-            top_k: params.top_k,
-            top_p: params.top_p,
+            model: self.model,
+            stop_sequences: self.stop,
+            thinking: resolve_thinking(
+                self.thinking,
+                self.reasoning_effort,
+                &options.effort_budgets,
+            ),
+            temperature: self.temperature,
+            stream: self.stream,
+            top_k: self.top_k,
+            top_p: self.top_p,
             tools,
             tool_choice,
-            metadata: params.metadata,
-            n: params.n,
-        }
+            metadata: metadata_with_user(self.metadata, self.user),
+            n: self.n,
+            container: self.container,
+        };
+        (converted, report)
     }
 }
 
+#[serde_as]
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct CreateMessageParams {
     /// Maximum number of tokens to generate
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(_, DisplayFromStr)>>")]
     pub max_completion_tokens: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(_, DisplayFromStr)>>")]
     pub max_tokens: Option<u32>,
-    /// Input messages for the conversation
+    /// Input messages for the conversation, empty when missing or null
+    #[serde(default)]
+    #[serde_as(deserialize_as = "DefaultOnNull")]
     pub messages: Vec<Message>,
     /// Model to use
     pub model: String,
@@ -496,6 +982,9 @@ pub struct CreateMessageParams {
     /// Frequency penalty for response generation
     #[serde(skip_serializing_if = "Option::is_none")]
     pub frequency_penalty: Option<f32>,
+    /// Presence penalty for response generation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
     /// Temperature for response generation
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
@@ -510,6 +999,8 @@ pub struct CreateMessageParams {
     pub thinking: Option<Thinking>,
     /// Top-k sampling
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(_, DisplayFromStr)>>")]
     pub top_k: Option<u32>,
     /// Top-p sampling
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -517,6 +1008,14 @@ pub struct CreateMessageParams {
     /// Logit bias for token generation
     #[serde(skip_serializing_if = "Option::is_none")]
     pub logit_bias: Option<Value>,
+    /// Whether to return log probabilities, which Claude does not support
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    /// Number of most likely tokens to return log probabilities for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(_, DisplayFromStr)>>")]
+    pub top_logprobs: Option<u32>,
     /// Tools that the model may use (supports both OAI function and Claude custom formats)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<OaiTool>>,
@@ -526,14 +1025,24 @@ pub struct CreateMessageParams {
     /// Request metadata
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<Metadata>,
+    /// End-user identifier for abuse tracking
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// OpenAI output storage flag, recorded but not forwarded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
     /// Number of completions to generate
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(_, DisplayFromStr)>>")]
     pub n: Option<u32>,
+    /// Code execution container to reuse across requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
 }
 
 impl CreateMessageParams {
     pub fn count_tokens(&self) -> u32 {
-        let bpe = o200k_base().expect("Failed to get encoding");
         let messages = self
             .messages
             .iter()
@@ -550,25 +1059,38 @@ impl CreateMessageParams {
             })
             .collect::<Vec<_>>()
             .join("\n");
-        bpe.encode_with_special_tokens(&messages).len() as u32
+        count_text_tokens(&messages)
     }
 }
 
 /// OpenAI format request with extended tool support
+#[serde_as]
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct OaiCreateMessageParams {
     /// Maximum number of tokens to generate
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(_, DisplayFromStr)>>")]
     pub max_completion_tokens: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(_, DisplayFromStr)>>")]
     pub max_tokens: Option<u32>,
     /// Input messages for the conversation (OAI format with tool role)
+    #[serde(default)]
+    #[serde_as(deserialize_as = "DefaultOnNull")]
     pub messages: Vec<OaiMessage>,
     /// Model to use
     pub model: String,
     /// Reasoning effort for response generation
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning_effort: Option<Effort>,
+    /// Frequency penalty for response generation (not supported by Claude)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    /// Presence penalty for response generation (not supported by Claude)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
     /// Temperature for response generation
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
@@ -583,10 +1105,20 @@ pub struct OaiCreateMessageParams {
     pub thinking: Option<Thinking>,
     /// Top-k sampling
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(_, DisplayFromStr)>>")]
     pub top_k: Option<u32>,
     /// Top-p sampling
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f32>,
+    /// Whether to return log probabilities, which Claude does not support
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    /// Number of most likely tokens to return log probabilities for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(_, DisplayFromStr)>>")]
+    pub top_logprobs: Option<u32>,
     /// Tools that the model may use (OAI function format)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<OaiTool>>,
@@ -596,81 +1128,136 @@ pub struct OaiCreateMessageParams {
     /// Request metadata
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<Metadata>,
+    /// End-user identifier for abuse tracking
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// OpenAI output storage flag, recorded but not forwarded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
     /// Number of completions to generate
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(_, DisplayFromStr)>>")]
     pub n: Option<u32>,
+    /// Code execution container to reuse across requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// Requested output modalities, e.g. `["text", "audio"]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modalities: Option<Vec<String>>,
 }
 
 impl From<OaiCreateMessageParams> for ClaudeCreateMessageParams {
     fn from(params: OaiCreateMessageParams) -> Self {
+        params.convert_with_report(&OaiRequestOptions::default()).0
+    }
+}
+
+impl OaiCreateMessageParams {
+    /// Reject output modalities Claude cannot produce
+    pub fn check_modalities(&self) -> Result<(), ClewdrError> {
+        let audio = self
+            .modalities
+            .iter()
+            .flatten()
+            .any(|m| m.eq_ignore_ascii_case("audio"));
+        if audio {
+            return Err(ClewdrError::BadRequest {
+                msg: "Audio output is not supported, remove \"audio\" from modalities",
+            });
+        }
+        Ok(())
+    }
+
+    /// Convert into Claude params, recording what the conversion discarded
+    pub fn convert_with_report(
+        mut self,
+        options: &OaiRequestOptions,
+    ) -> (ClaudeCreateMessageParams, ConversionReport) {
+        let mut report = ConversionReport {
+            frequency_penalty: self.frequency_penalty,
+            presence_penalty: self.presence_penalty,
+            store: self.store,
+            logprobs_ignored: self.logprobs == Some(true) || self.top_logprobs.is_some(),
+            ..Default::default()
+        };
+        // Tool role content becomes tool_result JSON, so only count other roles
+        let image_urls: usize = self
+            .messages
+            .iter()
+            .filter(|m| m.role != OaiRole::Tool)
+            .map(|m| match &m.content {
+                OaiMessageContent::Blocks(blocks) => count_image_urls(blocks),
+                _ => 0,
+            })
+            .sum();
+        // IDs are mapped before the conversion sanitizes them, so IDs that only
+        // differ in invalid characters still get distinct Claude IDs
+        if options.normalize_tool_ids {
+            report.tool_ids_rewritten = normalize_oai_tool_ids(&mut self.messages);
+        }
         // Convert OAI messages to Claude format
-        let converted_messages: Vec<Message> = params.messages
+        let mut converted_messages: Vec<Message> = self.messages
             .into_iter()
-            .map(convert_oai_message)
+            .map(|msg| convert_oai_message(msg, &options.default_media_type))
             .collect();
+        let remaining: usize = converted_messages
+            .iter()
+            .map(|m| match &m.content {
+                MessageContent::Blocks { content } => count_image_urls(content),
+                MessageContent::Text { .. } => 0,
+            })
+            .sum();
+        report.images_converted = image_urls.saturating_sub(remaining);
+        if let Some(prefix) = options.system_prompt_prefix.as_deref()
+            && promote_prefixed_system_prompt(&mut converted_messages, prefix)
+        {
+            tracing::debug!("[Format] Promoted prefixed user message to system prompt");
+        }
         
         // Separate system messages
-        let (systems, messages): (Vec<Message>, Vec<Message>) = converted_messages
-            .into_iter()
-            .partition(|m| m.role == Role::System);
+        let (systems, messages) = split_system_messages(converted_messages);
         
-        let systems = systems
-            .into_iter()
-            .map(|m| m.content)
-            .flat_map(|c| match c {
-                MessageContent::Text { content } => vec![ContentBlock::Text {
-                    text: content,
-                    cache_control: None,
-                }],
-                MessageContent::Blocks { content } => content,
-            })
-            .filter(|b| matches!(b, ContentBlock::Text { .. }))
-            .map(|b| json!(b))
-            .collect::<Vec<_>>();
-        let system = (!systems.is_empty()).then(|| json!(systems));
+        let system = flatten_system_messages(systems);
+        let mut messages = merge_split_tool_calls(messages);
+        if options.decode_json_tool_results {
+            decode_json_tool_results(&mut messages);
+        }
         
-        // Convert OAI tools to Claude tools and clean schemas
-        let tools = params.tools.map(|tools| {
-            tools.into_iter().filter_map(|oai_tool| {
-                let tool: Tool = oai_tool.into();
-                match tool {
-                    Tool::Custom(mut custom) => {
-                        // Apply full schema cleaning pipeline
-                        move_constraints_to_description(&mut custom.input_schema);
-                        clean_json_schema(&mut custom.input_schema);
-                        ensure_valid_schema(&mut custom.input_schema);
-                        // Ensure type is set to custom for Claude Code API
-                        custom.type_ = Some(CustomToolType::Custom);
-                        Some(Tool::Custom(custom))
-                    }
-                    Tool::Raw(v) if v.as_object().map(|o| o.is_empty()).unwrap_or(true) => {
-                        // Filter out empty tools from OaiTool::Other
-                        None
-                    }
-                    other => Some(other),
-                }
-            }).collect()
+        let tool_choice = self.tool_choice.map(|tc| {
+            if options.object_tool_choice {
+                tc.to_object_format()
+            } else {
+                tc
+            }
         });
+        let tools = self
+            .tools
+            .map(|tools| convert_tools(tools, options, tool_choice.as_ref(), &mut report));
         
-        Self {
-            max_tokens: (params.max_tokens.or(params.max_completion_tokens))
+        let converted = ClaudeCreateMessageParams {
+            max_tokens: (self.max_tokens.or(self.max_completion_tokens))
                 .unwrap_or_else(default_max_tokens),
             system,
             messages,
-            model: params.model,
-            stop_sequences: params.stop,
-            thinking: params
-                .thinking
-                .or_else(|| params.reasoning_effort.map(|e| Thinking::new(e as u64))),
-            temperature: params.temperature,
-            stream: params.stream,
-            top_k: params.top_k,
-            top_p: params.top_p,
+            model: self.model,
+            stop_sequences: self.stop,
+            thinking: resolve_thinking(
+                self.thinking,
+                self.reasoning_effort,
+                &options.effort_budgets,
+            ),
+            temperature: self.temperature,
+            stream: self.stream,
+            top_k: self.top_k,
+            top_p: self.top_p,
             tools,
-            tool_choice: params.tool_choice.map(|tc| tc.to_object_format()),
-            metadata: params.metadata,
-            n: params.n,
-        }
+            tool_choice,
+            metadata: metadata_with_user(self.metadata, self.user),
+            n: self.n,
+            container: self.container,
+        };
+        (converted, report)
     }
 }
 
@@ -679,6 +1266,47 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    #[test]
+    fn test_text_and_image_order_preserved() {
+        let content = json!([
+            {"type": "text", "text": "before"},
+            {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}},
+            {"type": "text", "text": "after"}
+        ]);
+        let user: OaiMessage =
+            serde_json::from_value(json!({"role": "user", "content": content})).unwrap();
+        let assistant: OaiMessage = serde_json::from_value(json!({
+            "role": "assistant",
+            "content": content,
+            "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": "describe", "arguments": "{}"}
+            }]
+        }))
+        .unwrap();
+
+        for (msg, expected_len) in [(user, 3), (assistant, 4)] {
+            let MessageContent::Blocks { content } = convert_oai_message(msg, "image/png").content
+            else {
+                panic!("Expected Blocks content");
+            };
+            assert_eq!(content.len(), expected_len);
+            match &content[..3] {
+                [
+                    ContentBlock::Text { text: before, .. },
+                    ContentBlock::Image { source, .. },
+                    ContentBlock::Text { text: after, .. },
+                ] => {
+                    assert_eq!(before, "before");
+                    assert_eq!(source.media_type, "image/png");
+                    assert_eq!(after, "after");
+                }
+                other => panic!("Unexpected blocks: {:?}", other),
+            }
+        }
+    }
+
     #[test]
     fn test_oai_tool_role_conversion() {
         let msg = OaiMessage {
@@ -687,9 +1315,13 @@ mod tests {
             tool_call_id: Some("call_123".to_string()),
             tool_calls: None,
             annotations: None,
+            cache_control: None,
+            reasoning_content: None,
+            reasoning_signature: None,
+            refusal: None,
         };
 
-        let converted = convert_oai_message(msg);
+        let converted = convert_oai_message(msg, "image/png");
         assert_eq!(converted.role, Role::User);
 
         if let MessageContent::Blocks { content } = converted.content {
@@ -719,9 +1351,13 @@ mod tests {
                 },
             }]),
             annotations: None,
+            cache_control: None,
+            reasoning_content: None,
+            reasoning_signature: None,
+            refusal: None,
         };
 
-        let converted = convert_oai_message(msg);
+        let converted = convert_oai_message(msg, "image/png");
         assert_eq!(converted.role, Role::Assistant);
 
         if let MessageContent::Blocks { content } = converted.content {
@@ -734,17 +1370,42 @@ mod tests {
     }
 
     #[test]
-    fn test_sanitize_tool_id() {
-        // Normal ID should pass through
-        assert_eq!(sanitize_tool_id("call_123"), "call_123");
-        // Dots should be replaced with underscores
-        assert_eq!(sanitize_tool_id("call_123.456"), "call_123_456");
-        // Mixed invalid chars
-        assert_eq!(sanitize_tool_id("call.123@foo"), "call_123_foo");
-        // Empty string
-        assert_eq!(sanitize_tool_id(""), "");
-        // Already valid with hyphens
-        assert_eq!(sanitize_tool_id("call-123_abc"), "call-123_abc");
+    fn test_empty_tool_calls_use_standard_conversion() {
+        let msg: OaiMessage = serde_json::from_value(json!({
+            "role": "assistant",
+            "content": "Nothing to call.",
+            "tool_calls": []
+        }))
+        .unwrap();
+        assert_eq!(msg.tool_calls.as_deref().map(<[_]>::len), Some(0));
+
+        let converted = convert_oai_message(msg, "image/png");
+        assert_eq!(converted.role, Role::Assistant);
+        let MessageContent::Blocks { content } = converted.content else {
+            panic!("Expected Blocks content");
+        };
+        assert_eq!(content.len(), 1);
+        assert!(matches!(&content[0], ContentBlock::Text { text, .. } if text == "Nothing to call."));
+    }
+
+    #[test]
+    fn test_refusal_kept_as_text() {
+        let msg: OaiMessage = serde_json::from_value(json!({
+            "role": "assistant",
+            "content": null,
+            "refusal": "I can't help with that."
+        }))
+        .unwrap();
+
+        let converted = convert_oai_message(msg, "image/png");
+        assert_eq!(converted.role, Role::Assistant);
+        let MessageContent::Blocks { content } = converted.content else {
+            panic!("Expected Blocks content");
+        };
+        assert!(matches!(
+            content.as_slice(),
+            [ContentBlock::Text { text, .. }] if text == "I can't help with that."
+        ));
     }
 
     #[test]
@@ -789,6 +1450,745 @@ mod tests {
         assert!(blocks.is_empty());
     }
 
+    #[test]
+    fn test_oai_web_search_tool_params_pass_through() {
+        let oai_tool: OaiTool = serde_json::from_value(json!({
+            "type": "function",
+            "function": {
+                "name": "web_search",
+                "parameters": {
+                    "allowed_domains": ["docs.rs", "rust-lang.org"],
+                    "max_uses": 3
+                }
+            }
+        }))
+        .unwrap();
+
+        match Tool::from(oai_tool) {
+            Tool::Known(KnownTool::WebSearch20250305 {
+                allowed_domains,
+                blocked_domains,
+                max_uses,
+                user_location,
+                ..
+            }) => {
+                assert_eq!(
+                    allowed_domains,
+                    Some(vec!["docs.rs".to_string(), "rust-lang.org".to_string()])
+                );
+                assert_eq!(blocked_domains, None);
+                assert_eq!(max_uses, Some(3));
+                assert!(user_location.is_none());
+            }
+            other => panic!("Expected web search tool, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_oai_web_search_preview_tool() {
+        let oai_tool: OaiTool = serde_json::from_value(json!({
+            "type": "web_search_preview",
+            "search_context_size": "medium",
+            "user_location": {"type": "approximate", "country": "US"}
+        }))
+        .unwrap();
+        assert!(matches!(oai_tool, OaiTool::WebSearch { .. }));
+
+        let params: OaiCreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "tools": [oai_tool]
+        }))
+        .unwrap();
+        let (claude, _) = params.convert_with_report(&OaiRequestOptions::default());
+        match claude.tools.as_deref() {
+            Some([Tool::Known(KnownTool::WebSearch20250305 { user_location, .. })]) => {
+                let location = user_location.as_ref().expect("user_location should be kept");
+                assert_eq!(location.country.as_deref(), Some("US"));
+            }
+            other => panic!("Expected web search tool, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_numeric_fields_accept_strings() {
+        let params: OaiCreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "max_completion_tokens": "2048",
+            "top_k": "40",
+            "n": 1
+        }))
+        .unwrap();
+        assert_eq!(params.max_completion_tokens, Some(2048));
+        assert_eq!(params.top_k, Some(40));
+        assert_eq!(params.n, Some(1));
+        assert_eq!(params.max_tokens, None);
+
+        let params: CreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "max_tokens": "4096",
+            "n": "1"
+        }))
+        .unwrap();
+        assert_eq!(params.max_tokens, Some(4096));
+        assert_eq!(params.n, Some(1));
+
+        let invalid = serde_json::from_value::<OaiCreateMessageParams>(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [],
+            "max_tokens": "many"
+        }));
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_user_populates_metadata_user_id() {
+        let params: OaiCreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "user": "user-123"
+        }))
+        .unwrap();
+        let claude = ClaudeCreateMessageParams::from(params);
+        let metadata = claude.metadata.expect("metadata should be set");
+        assert_eq!(metadata.fields.get("user_id").map(String::as_str), Some("user-123"));
+
+        let params: OaiCreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "metadata": {"user_id": "explicit"},
+            "user": "user-123"
+        }))
+        .unwrap();
+        let claude = ClaudeCreateMessageParams::from(params);
+        let metadata = claude.metadata.expect("metadata should be set");
+        assert_eq!(metadata.fields.get("user_id").map(String::as_str), Some("explicit"));
+    }
+
+    #[test]
+    fn test_store_and_metadata() {
+        let body = json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "store": true,
+            "metadata": {"session": "abc"},
+            "user": "user-123"
+        });
+        let params: OaiCreateMessageParams = serde_json::from_value(body).unwrap();
+        assert_eq!(params.store, Some(true));
+
+        // Both fields survive a round trip through the OAI params
+        let reserialized = serde_json::to_value(&params).unwrap();
+        assert_eq!(reserialized["store"], true);
+        assert_eq!(reserialized["metadata"], json!({"session": "abc"}));
+
+        let (claude, report) = params.convert_with_report(&OaiRequestOptions::default());
+        assert_eq!(report.store, Some(true));
+        let metadata = claude.metadata.expect("metadata should be set");
+        assert_eq!(metadata.fields.get("session").map(String::as_str), Some("abc"));
+        assert_eq!(metadata.fields.get("user_id").map(String::as_str), Some("user-123"));
+    }
+
+    #[test]
+    fn test_tool_choice_object_coercion() {
+        let params = || -> OaiCreateMessageParams {
+            serde_json::from_value(json!({
+                "model": "claude-sonnet-4-5",
+                "messages": [{"role": "user", "content": "hi"}],
+                "tools": function_tools(&["a"]),
+                "tool_choice": "auto"
+            }))
+            .unwrap()
+        };
+
+        // Claude Code needs the object form
+        let (claude, _) = params().convert_with_report(&OaiRequestOptions::default());
+        assert_eq!(
+            serde_json::to_value(claude.tool_choice).unwrap(),
+            json!({"type": "auto"})
+        );
+
+        // The public API accepts the string form as sent
+        let options = OaiRequestOptions {
+            object_tool_choice: false,
+            ..Default::default()
+        };
+        let (claude, _) = params().convert_with_report(&options);
+        assert!(matches!(
+            claude.tool_choice,
+            Some(ToolChoice::Simple(ToolChoiceSimple::Auto))
+        ));
+    }
+
+    #[test]
+    fn test_penalties_recorded_in_report() {
+        let params: OaiCreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "frequency_penalty": 0.5,
+            "presence_penalty": -0.25
+        }))
+        .unwrap();
+        assert_eq!(params.frequency_penalty, Some(0.5));
+        assert_eq!(params.presence_penalty, Some(-0.25));
+
+        let (_, report) = params.convert_with_report(&OaiRequestOptions::default());
+        assert_eq!(report.frequency_penalty, Some(0.5));
+        assert_eq!(report.presence_penalty, Some(-0.25));
+        assert!(report.penalties_ignored());
+
+        let params: CreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "frequency_penalty": 1.0,
+            "presence_penalty": 1.5
+        }))
+        .unwrap();
+        let (_, report) = params.convert_with_report(&OaiRequestOptions::default());
+        assert_eq!(report.frequency_penalty, Some(1.0));
+        assert_eq!(report.presence_penalty, Some(1.5));
+    }
+
+    #[test]
+    fn test_conversion_report_flags() {
+        let params: OaiCreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": [
+                {"type": "text", "text": "what is this?"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}}
+            ]}],
+            "logprobs": true,
+            "top_logprobs": 5,
+            "tools": [{"type": "function", "function": {
+                "name": "search",
+                "parameters": {
+                    "type": "object",
+                    "properties": {"q": {"type": "string", "minLength": 1}},
+                    "additionalProperties": false
+                }
+            }}]
+        }))
+        .unwrap();
+
+        let (_, report) = params.convert_with_report(&OaiRequestOptions::default());
+        assert!(report.logprobs_ignored);
+        assert_eq!(report.images_converted, 1);
+        assert_eq!(report.schemas_cleaned, 1);
+        assert!(!report.penalties_ignored());
+        assert!(!report.thinking_disabled);
+
+        let params: CreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "logprobs": false
+        }))
+        .unwrap();
+        let (_, report) = params.convert_with_report(&OaiRequestOptions::default());
+        assert_eq!(report, ConversionReport::default());
+    }
+
+    #[test]
+    fn test_effort_budgets() {
+        let params = || -> OaiCreateMessageParams {
+            serde_json::from_value(json!({
+                "model": "claude-sonnet-4-5",
+                "messages": [{"role": "user", "content": "hi"}],
+                "reasoning_effort": "high"
+            }))
+            .unwrap()
+        };
+        let budget = |claude: ClaudeCreateMessageParams| claude.thinking.unwrap().budget_tokens;
+
+        let (claude, _) = params().convert_with_report(&OaiRequestOptions::default());
+        assert_eq!(budget(claude), 16384);
+
+        let options = OaiRequestOptions {
+            effort_budgets: EffortBudgets {
+                minimal: 1024,
+                low: 2048,
+                medium: 4096,
+                high: 32000,
+            },
+            ..Default::default()
+        };
+        let (claude, _) = params().convert_with_report(&options);
+        assert_eq!(budget(claude), 32000);
+        assert_eq!(Effort::Low.budget(&options.effort_budgets), Some(2048));
+        assert_eq!(Effort::Medium.budget(&options.effort_budgets), Some(4096));
+    }
+
+    #[test]
+    fn test_minimal_and_none_effort() {
+        let effort = |value: &str| -> Option<Effort> {
+            serde_json::from_value(json!({
+                "model": "claude-sonnet-4-5",
+                "messages": [{"role": "user", "content": "hi"}],
+                "reasoning_effort": value
+            }))
+            .map(|p: OaiCreateMessageParams| p.reasoning_effort)
+            .unwrap()
+        };
+        assert_eq!(effort("minimal"), Some(Effort::Minimal));
+        assert_eq!(effort("none"), Some(Effort::None));
+
+        let params: OaiCreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "reasoning_effort": "none",
+            "thinking": {"type": "enabled", "budget_tokens": 2048}
+        }))
+        .unwrap();
+        let (claude, _) = params.convert_with_report(&OaiRequestOptions::default());
+        assert!(claude.thinking.is_none());
+
+        let params: CreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "reasoning_effort": "minimal"
+        }))
+        .unwrap();
+        let (claude, _) = params.convert_with_report(&OaiRequestOptions::default());
+        assert_eq!(claude.thinking.unwrap().budget_tokens, 1024);
+    }
+
+    #[test]
+    fn test_system_cache_control_preserved() {
+        let params: OaiCreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                {"role": "system", "content": "You are helpful."},
+                {
+                    "role": "system",
+                    "content": "Long reference material.",
+                    "cache_control": {"type": "ephemeral"}
+                },
+                {"role": "user", "content": "hi"}
+            ]
+        }))
+        .unwrap();
+
+        let claude: ClaudeCreateMessageParams = params.into();
+        let system = claude.system.expect("system should be present");
+        let blocks = system.as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[0].get("cache_control").is_none());
+        assert_eq!(blocks[1]["text"], "Long reference material.");
+        assert_eq!(blocks[1]["cache_control"]["type"], "ephemeral");
+    }
+
+    fn function_tools(names: &[&str]) -> Vec<Value> {
+        names
+            .iter()
+            .map(|name| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": name,
+                        "parameters": {"type": "object", "properties": {}}
+                    }
+                })
+            })
+            .collect()
+    }
+
+    fn claude_tool_names(params: &ClaudeCreateMessageParams) -> Vec<String> {
+        params
+            .tools
+            .as_ref()
+            .unwrap()
+            .iter()
+            .filter_map(tool_name)
+            .collect()
+    }
+
+    #[test]
+    fn test_max_tools_truncation() {
+        let params: OaiCreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "tools": function_tools(&["a", "b", "c", "d"])
+        }))
+        .unwrap();
+        let options = OaiRequestOptions {
+            max_tools: Some(2),
+            ..Default::default()
+        };
+
+        let (claude, report) = params.convert_with_report(&options);
+        assert_eq!(claude_tool_names(&claude), vec!["a", "b"]);
+        assert_eq!(report.tools_dropped, 2);
+    }
+
+    #[test]
+    fn test_max_tools_keeps_tool_choice() {
+        let params: CreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "tools": function_tools(&["a", "b", "c", "d"]),
+            "tool_choice": {"type": "tool", "name": "d"}
+        }))
+        .unwrap();
+        let options = OaiRequestOptions {
+            max_tools: Some(2),
+            ..Default::default()
+        };
+
+        let (claude, report) = params.convert_with_report(&options);
+        assert_eq!(claude_tool_names(&claude), vec!["a", "d"]);
+        assert_eq!(report.tools_dropped, 2);
+    }
+
+    #[test]
+    fn test_strict_function_keeps_strict_schema() {
+        let tool = |strict: bool| {
+            json!({
+                "type": "function",
+                "function": {
+                    "name": "get_weather",
+                    "strict": strict,
+                    "parameters": {
+                        "type": "object",
+                        "additionalProperties": false,
+                        "properties": {
+                            "city": {"type": "string"},
+                            "unit": {"type": "string"}
+                        },
+                        "required": ["city"]
+                    }
+                }
+            })
+        };
+        let schema = |strict: bool| {
+            let params: CreateMessageParams = serde_json::from_value(json!({
+                "model": "claude-sonnet-4-5",
+                "messages": [{"role": "user", "content": "hi"}],
+                "tools": [tool(strict)]
+            }))
+            .unwrap();
+            let (claude, _) = params.convert_with_report(&OaiRequestOptions::default());
+            match claude.tools.unwrap().remove(0) {
+                Tool::Custom(custom) => custom.input_schema,
+                other => panic!("Expected a custom tool, got {:?}", other),
+            }
+        };
+
+        let strict = schema(true);
+        assert_eq!(strict["additionalProperties"], false);
+        assert_eq!(strict["required"], json!(["city", "unit"]));
+
+        let lax = schema(false);
+        assert!(lax.get("additionalProperties").is_none());
+        assert_eq!(lax["required"], json!(["city"]));
+    }
+
+    #[test]
+    fn test_container_survives_conversion() {
+        let body = json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hi"}],
+            "container": "container_abc"
+        });
+        let oai: OaiCreateMessageParams = serde_json::from_value(body.clone()).unwrap();
+        let (claude, _) = oai.convert_with_report(&OaiRequestOptions::default());
+        assert_eq!(claude.container.as_deref(), Some("container_abc"));
+
+        let params: CreateMessageParams = serde_json::from_value(body).unwrap();
+        let (claude, _) = params.convert_with_report(&OaiRequestOptions::default());
+        assert_eq!(serde_json::to_value(&claude).unwrap()["container"], "container_abc");
+    }
+
+    #[test]
+    fn test_missing_messages_default_to_empty() {
+        let params: OaiCreateMessageParams =
+            serde_json::from_value(json!({"model": "claude-sonnet-4-5"})).unwrap();
+        assert!(params.messages.is_empty());
+        let params: OaiCreateMessageParams =
+            serde_json::from_value(json!({"model": "claude-sonnet-4-5", "messages": null}))
+                .unwrap();
+        let (claude, _) = params.convert_with_report(&OaiRequestOptions::default());
+        assert!(claude.messages.is_empty());
+    }
+
+    #[test]
+    fn test_audio_modality_rejected() {
+        let params = |modalities: Value| -> OaiCreateMessageParams {
+            serde_json::from_value(json!({
+                "model": "claude-sonnet-4-5",
+                "messages": [{"role": "user", "content": "hi"}],
+                "modalities": modalities
+            }))
+            .unwrap()
+        };
+
+        assert!(params(json!(["text"])).check_modalities().is_ok());
+        assert!(params(Value::Null).check_modalities().is_ok());
+        match params(json!(["text", "audio"])).check_modalities() {
+            Err(ClewdrError::BadRequest { msg }) => assert!(msg.contains("Audio output")),
+            other => panic!("Expected BadRequest, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_normalize_tool_ids_keeps_pairing() {
+        let params: OaiCreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                {"role": "user", "content": "weather?"},
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_abc",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": "{}"}
+                    }]
+                },
+                {"role": "tool", "tool_call_id": "call_abc", "content": "sunny"}
+            ]
+        }))
+        .unwrap();
+        let options = OaiRequestOptions {
+            normalize_tool_ids: true,
+            ..Default::default()
+        };
+
+        let (claude, report) = params.convert_with_report(&options);
+        assert_eq!(report.tool_ids_rewritten, 1);
+        let MessageContent::Blocks { content } = &claude.messages[1].content else {
+            panic!("Expected Blocks content");
+        };
+        assert!(matches!(&content[0], ContentBlock::ToolUse { id, .. } if id == "toolu_call_abc"));
+        let MessageContent::Blocks { content } = &claude.messages[2].content else {
+            panic!("Expected Blocks content");
+        };
+        assert!(matches!(
+            &content[0],
+            ContentBlock::ToolResult { tool_use_id, .. } if tool_use_id == "toolu_call_abc"
+        ));
+    }
+
+    #[test]
+    fn test_normalize_tool_ids_keeps_colliding_ids_apart() {
+        let tool_call = |id: &str| {
+            json!({
+                "id": id,
+                "type": "function",
+                "function": {"name": "get_weather", "arguments": "{}"}
+            })
+        };
+        let params: OaiCreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                {"role": "user", "content": "weather?"},
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [tool_call("call.1"), tool_call("call_1")]
+                },
+                {"role": "tool", "tool_call_id": "call_1", "content": "rainy"},
+                {"role": "tool", "tool_call_id": "call.1", "content": "sunny"}
+            ]
+        }))
+        .unwrap();
+        let options = OaiRequestOptions {
+            normalize_tool_ids: true,
+            ..Default::default()
+        };
+
+        let (claude, report) = params.convert_with_report(&options);
+        assert_eq!(report.tool_ids_rewritten, 2);
+        let ids: Vec<&str> = claude
+            .messages
+            .iter()
+            .filter_map(|msg| match &msg.content {
+                MessageContent::Blocks { content } => Some(content),
+                MessageContent::Text { .. } => None,
+            })
+            .flatten()
+            .filter_map(|block| match block {
+                ContentBlock::ToolUse { id, .. } => Some(id.as_str()),
+                ContentBlock::ToolResult { tool_use_id, .. } => Some(tool_use_id.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids, ["toolu_call_1", "toolu_call_1_2", "toolu_call_1_2", "toolu_call_1"]);
+    }
+
+    #[test]
+    fn test_split_tool_calls_merged_into_one_turn() {
+        let params: OaiCreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                {"role": "user", "content": "weather?"},
+                {"role": "assistant", "content": "Let me check."},
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_abc",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": "{}"}
+                    }]
+                },
+                {"role": "tool", "tool_call_id": "call_abc", "content": "sunny"}
+            ]
+        }))
+        .unwrap();
+
+        let (claude, _) = params.convert_with_report(&OaiRequestOptions::default());
+        let roles: Vec<_> = claude.messages.iter().map(|m| m.role).collect();
+        assert_eq!(roles, vec![Role::User, Role::Assistant, Role::User]);
+        let MessageContent::Blocks { content } = &claude.messages[1].content else {
+            panic!("Expected Blocks content");
+        };
+        match content.as_slice() {
+            [ContentBlock::Text { text, .. }, ContentBlock::ToolUse { name, .. }] => {
+                assert_eq!(text, "Let me check.");
+                assert_eq!(name, "get_weather");
+            }
+            other => panic!("Unexpected blocks: {:?}", other),
+        }
+
+        // Claude-shaped messages sent to the OpenAI endpoint are merged too
+        let params: CreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                {"role": "user", "content": "weather?"},
+                {"role": "assistant", "content": "Let me check."},
+                {"role": "assistant", "content": [{
+                    "type": "tool_use",
+                    "id": "toolu_01",
+                    "name": "get_weather",
+                    "input": {}
+                }]}
+            ]
+        }))
+        .unwrap();
+        let (claude, _) = params.convert_with_report(&OaiRequestOptions::default());
+        assert_eq!(claude.messages.len(), 2);
+        assert!(matches!(
+            &claude.messages[1].content,
+            MessageContent::Blocks { content } if content.len() == 2
+        ));
+    }
+
+    #[test]
+    fn test_prefixed_user_message_promoted_to_system() {
+        let params = || -> OaiCreateMessageParams {
+            serde_json::from_value(json!({
+                "model": "claude-sonnet-4-5",
+                "messages": [
+                    {"role": "user", "content": "System: You are a pirate."},
+                    {"role": "user", "content": "hi"}
+                ]
+            }))
+            .unwrap()
+        };
+
+        // Off by default, the message is kept as sent
+        let (claude, _) = params().convert_with_report(&OaiRequestOptions::default());
+        assert!(claude.system.is_none());
+        assert_eq!(claude.messages.len(), 2);
+
+        let options = OaiRequestOptions {
+            system_prompt_prefix: Some("System:".to_string()),
+            ..Default::default()
+        };
+        let (claude, _) = params().convert_with_report(&options);
+        let system = claude.system.expect("system should be present");
+        assert_eq!(system[0]["text"], "You are a pirate.");
+        assert_eq!(claude.messages.len(), 1);
+        assert_eq!(claude.messages[0].role, Role::User);
+
+        // A lone message is never promoted, the conversation would be empty
+        let mut messages = vec![Message::new_text(Role::User, "System: hi")];
+        assert!(!promote_prefixed_system_prompt(&mut messages, "System:"));
+    }
+
+    #[test]
+    fn test_mid_conversation_system_kept_in_place() {
+        let params: OaiCreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "hi"},
+                {"role": "assistant", "content": "hello"},
+                {"role": "system", "content": "Answer in French from now on."},
+                {"role": "user", "content": "bye"}
+            ]
+        }))
+        .unwrap();
+
+        let claude: ClaudeCreateMessageParams = params.into();
+        let system = claude.system.expect("system should be present");
+        assert_eq!(system.as_array().unwrap().len(), 1);
+        assert_eq!(system[0]["text"], "Be brief.");
+        let roles: Vec<_> = claude.messages.iter().map(|m| m.role).collect();
+        assert_eq!(roles, vec![Role::User, Role::Assistant, Role::User, Role::User]);
+        assert_eq!(
+            claude.messages[2].content,
+            MessageContent::Text {
+                content: "[System note] Answer in French from now on.".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_tool_message_object_content() {
+        let msg: OaiMessage = serde_json::from_value(json!({
+            "role": "tool",
+            "tool_call_id": "call_123",
+            "content": {"result": "success", "count": 2}
+        }))
+        .unwrap();
+        assert!(matches!(msg.content, OaiMessageContent::Object(_)));
+
+        let converted = convert_oai_message(msg, "image/png");
+        let MessageContent::Blocks { content } = converted.content else {
+            panic!("Expected Blocks content");
+        };
+        match &content[0] {
+            ContentBlock::ToolResult { content, .. } => {
+                let text = content.as_str().expect("tool_result content should be a string");
+                let parsed: Value = serde_json::from_str(text).unwrap();
+                assert_eq!(parsed, json!({"result": "success", "count": 2}));
+            }
+            other => panic!("Expected ToolResult block, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reasoning_content_becomes_thinking_block() {
+        let msg: OaiMessage = serde_json::from_value(json!({
+            "role": "assistant",
+            "content": "The answer is 4.",
+            "reasoning_content": "2 + 2 = 4",
+            "reasoning_signature": "sig_abcdefghijklmnop"
+        }))
+        .unwrap();
+
+        let converted = convert_oai_message(msg, "image/png");
+        assert_eq!(converted.role, Role::Assistant);
+        let MessageContent::Blocks { content } = converted.content else {
+            panic!("Expected Blocks content");
+        };
+        assert_eq!(content.len(), 2);
+        match &content[0] {
+            ContentBlock::Thinking {
+                thinking,
+                signature,
+                ..
+            } => {
+                assert_eq!(thinking, "2 + 2 = 4");
+                assert_eq!(signature.as_deref(), Some("sig_abcdefghijklmnop"));
+            }
+            other => panic!("Expected Thinking block, got {:?}", other),
+        }
+        assert!(matches!(&content[1], ContentBlock::Text { text, .. } if text == "The answer is 4."));
+    }
+
     #[test]
     fn test_oai_role_conversion() {
         assert_eq!(Role::from(OaiRole::System), Role::System);
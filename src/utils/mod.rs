@@ -1,11 +1,13 @@
 use axum::body::Body;
 use colored::{ColoredString, Colorize};
+use serde_json::Value;
 use tokio::spawn;
 use tracing::error;
 
 use crate::{
     config::{CLEWDR_CONFIG, LOG_DIR},
     error::ClewdrError,
+    types::claude::CreateMessageParams,
 };
 
 /// Helper function to format a boolean value as "Enabled" or "Disabled"
@@ -30,6 +32,20 @@ pub fn print_out_json(json: impl serde::ser::Serialize, file_name: &str) {
     print_out_text(text, file_name);
 }
 
+/// Helper function to print out a redacted request to a file in the log directory
+///
+/// The request is only serialized when file output is enabled.
+///
+/// # Arguments
+/// * `params` - The request to redact and output
+/// * `file_name` - The name of the file to write in the log directory
+pub fn print_out_request(params: &CreateMessageParams, file_name: &str) {
+    if CLEWDR_CONFIG.load().no_fs {
+        return;
+    }
+    print_out_json(redact_for_log(params, None), file_name);
+}
+
 /// Helper function to print out text to a file in the log directory
 ///
 /// # Arguments
@@ -53,6 +69,60 @@ pub fn print_out_text(text: String, file_name: &str) {
     });
 }
 
+/// Redacts a request before it is dumped to a debug log
+///
+/// Base64 payloads are replaced with `"[base64 N bytes]"` and, if
+/// `max_text_chars` is set, longer text blocks are truncated.
+///
+/// # Arguments
+/// * `params` - The request to redact
+/// * `max_text_chars` - Maximum number of characters kept per text block
+pub fn redact_for_log(params: &CreateMessageParams, max_text_chars: Option<usize>) -> Value {
+    let mut value = serde_json::to_value(params).unwrap_or_default();
+    redact_value_for_log(&mut value, max_text_chars);
+    value
+}
+
+/// Redacts base64 payloads and long text in an arbitrary JSON value in place
+///
+/// # Arguments
+/// * `value` - The JSON value to redact
+/// * `max_text_chars` - Maximum number of characters kept per text block
+pub fn redact_value_for_log(value: &mut Value, max_text_chars: Option<usize>) {
+    match value {
+        Value::Object(obj) => {
+            let is_base64 = obj.get("type").and_then(Value::as_str) == Some("base64");
+            let is_text = obj.get("type").and_then(Value::as_str) == Some("text");
+            for (key, field) in obj.iter_mut() {
+                match (key.as_str(), field) {
+                    ("data", Value::String(data)) if is_base64 => {
+                        *data = format!("[base64 {} bytes]", data.len());
+                    }
+                    ("url", Value::String(url)) if url.starts_with("data:") => {
+                        if let Some((meta, data)) = url.split_once(',') {
+                            *url = format!("{},[base64 {} bytes]", meta, data.len());
+                        }
+                    }
+                    ("text", Value::String(text)) if is_text => {
+                        let total = text.chars().count();
+                        if let Some(max) = max_text_chars.filter(|max| total > *max) {
+                            let kept: String = text.chars().take(max).collect();
+                            *text = format!("{}... [truncated, {} chars]", kept, total);
+                        }
+                    }
+                    (_, field) => redact_value_for_log(field, max_text_chars),
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                redact_value_for_log(item, max_text_chars);
+            }
+        }
+        _ => {}
+    }
+}
+
 /// Timezone for the API
 pub const TIME_ZONE: &str = "America/New_York";
 
@@ -71,3 +141,40 @@ pub fn forward_response(in_: wreq::Response) -> Result<http::Response<Body>, Cle
 
     Ok(res.body(Body::from_stream(stream))?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_redact_for_log_hides_image_data() {
+        let params: CreateMessageParams = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "messages": [{
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "What is in this picture?"},
+                    {
+                        "type": "image",
+                        "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}
+                    }
+                ]
+            }]
+        }))
+        .unwrap();
+
+        let redacted = redact_for_log(&params, None);
+        let content = &redacted["messages"][0]["content"];
+        assert_eq!(content[1]["source"]["data"], "[base64 12 bytes]");
+        assert_eq!(content[1]["source"]["media_type"], "image/png");
+        assert_eq!(content[0]["text"], "What is in this picture?");
+
+        let redacted = redact_for_log(&params, Some(7));
+        assert_eq!(
+            redacted["messages"][0]["content"][0]["text"],
+            "What is... [truncated, 24 chars]"
+        );
+    }
+}
@@ -1,12 +1,26 @@
 //! Format conversion integration tests
 //!
 //! These tests verify the format conversion logic between Claude and OpenAI APIs.
-
+//! Each fixture is run through the real conversion and compared with the
+//! expected output.
+
+use std::convert::Infallible;
+
+use axum::response::{IntoResponse, Sse};
+use clewdr::{
+    format::{
+        citations_to_annotations, clean_json_schema, document_to_image_source, ensure_valid_schema,
+        extract_citations_from_tool_result, move_constraints_to_description,
+        oai_image_url_to_claude, remap_function_call_args,
+    },
+    middleware::claude::{OaiResponseOptions, transform_stream, transforms_json},
+    types::{
+        claude::{ContentBlock, CreateMessageParams, CreateMessageResponse, DocumentSource},
+        oai::OaiCreateMessageParams,
+    },
+};
 use serde_json::{json, Value};
 
-// Note: These tests are designed to be run when the project can be compiled.
-// For now, they serve as documentation of the expected behavior.
-
 /// Test data for Claude → OpenAI conversion
 mod claude_to_oai {
     use super::*;
@@ -148,7 +162,8 @@ mod claude_to_oai {
                     }]
                 },
                 "finish_reason": "tool_calls"
-            }]
+            }],
+            "usage": null
         })
     }
 }
@@ -183,13 +198,14 @@ mod oai_to_claude {
     }
 
     /// Expected Claude format for tool result
+    /// Note: content stays a string, Claude rejects objects in tool_result
     pub fn expected_claude_tool_result() -> Value {
         json!({
             "role": "user",
             "content": [{
                 "type": "tool_result",
                 "tool_use_id": "call_123",
-                "content": {"result": "success", "data": [1, 2, 3]}
+                "content": "{\"result\": \"success\", \"data\": [1, 2, 3]}"
             }]
         })
     }
@@ -264,7 +280,8 @@ mod streaming {
                 "content_block": {
                     "type": "tool_use",
                     "id": "tool_123",
-                    "name": "Read"
+                    "name": "Read",
+                    "input": {}
                 }
             }),
             json!({
@@ -492,17 +509,23 @@ mod schema_cleaning {
     }
 
     /// Schema after cleaning
+    ///
+    /// Constraints are described in the description, numeric bounds are kept
+    /// as Claude supports them, and a `null` type union becomes `nullable`.
     pub fn schema_cleaned() -> Value {
         json!({
             "type": "object",
             "properties": {
                 "name": {
                     "type": "string",
-                    "description": "Constraints: minLength=1, maxLength=100, pattern=^[a-z]+$"
+                    "description": "Minimum length: 1. Maximum length: 100. Pattern: ^[a-z]+$"
                 },
                 "age": {
                     "type": "integer",
-                    "description": "Constraints: minimum=0, maximum=150"
+                    "nullable": true,
+                    "minimum": 0,
+                    "maximum": 150,
+                    "description": "Minimum: 0. Maximum: 150"
                 }
             },
             "required": ["name"]
@@ -510,6 +533,174 @@ mod schema_cleaning {
     }
 }
 
+/// Converts a Claude response fixture, dropping the non-deterministic `created`
+fn convert_response(response: Value) -> Value {
+    let response: CreateMessageResponse = serde_json::from_value(response).unwrap();
+    let mut converted = transforms_json(response, &OaiResponseOptions::default());
+    converted.as_object_mut().unwrap().remove("created");
+    parse_tool_arguments(&mut converted);
+    converted
+}
+
+/// Parses the tool call arguments of a response so key order doesn't matter
+fn parse_tool_arguments(response: &mut Value) {
+    let calls = response
+        .pointer_mut("/choices/0/message/tool_calls")
+        .and_then(Value::as_array_mut);
+    for call in calls.into_iter().flatten() {
+        if let Some(arguments) = call.pointer_mut("/function/arguments")
+            && let Some(parsed) = arguments.as_str().and_then(|a| serde_json::from_str(a).ok())
+        {
+            *arguments = parsed;
+        }
+    }
+}
+
+/// Runs Claude stream events through `transform_stream` and returns the JSON
+/// payloads of the emitted OpenAI chunks
+async fn collect_oai_chunks(events: Vec<Value>) -> Vec<Value> {
+    let input = futures::stream::iter(events.into_iter().map(|e| {
+        Ok::<_, Infallible>(eventsource_stream::Event {
+            event: String::new(),
+            data: e.to_string(),
+            id: String::new(),
+            retry: None,
+        })
+    }));
+    let stream = transform_stream(input, &OaiResponseOptions::default());
+    let body = Sse::new(stream).into_response().into_body();
+    let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+    String::from_utf8_lossy(&bytes)
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .map(|data| serde_json::from_str(data).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_text_response_to_oai() {
+    assert_eq!(
+        convert_response(claude_to_oai::sample_text_response()),
+        claude_to_oai::expected_oai_text_response()
+    );
+}
+
+#[test]
+fn test_tool_use_response_to_oai() {
+    let mut expected = claude_to_oai::expected_oai_tool_response();
+    parse_tool_arguments(&mut expected);
+    assert_eq!(convert_response(claude_to_oai::sample_tool_use_response()), expected);
+}
+
+#[test]
+fn test_thinking_response_to_oai() {
+    let converted = convert_response(claude_to_oai::sample_thinking_response());
+    // Thinking is not part of the OpenAI message
+    assert_eq!(
+        converted["choices"][0]["message"],
+        json!({"role": "assistant", "content": "Here is my answer."})
+    );
+}
+
+#[test]
+fn test_web_search_response_to_oai() {
+    let converted = convert_response(claude_to_oai::sample_web_search_response());
+    let message = &converted["choices"][0]["message"];
+    let content = message["content"].as_str().unwrap();
+    assert!(content.starts_with("Based on my search, here are the results:"));
+    assert!(content.contains("https://example.com/article"));
+    assert_eq!(message["annotations"][0]["url_citation"]["title"], "Example Article");
+}
+
+#[test]
+fn test_oai_tool_messages_to_claude() {
+    let params: OaiCreateMessageParams = serde_json::from_value(json!({
+        "model": "claude-3-opus",
+        "messages": [
+            oai_to_claude::sample_assistant_with_tool_calls(),
+            oai_to_claude::sample_tool_result_message()
+        ]
+    }))
+    .unwrap();
+
+    let messages = serde_json::to_value(CreateMessageParams::from(params).messages).unwrap();
+    assert_eq!(
+        messages,
+        json!([
+            oai_to_claude::expected_claude_assistant_with_tool_use(),
+            oai_to_claude::expected_claude_tool_result()
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_text_stream_to_oai() {
+    let chunks = collect_oai_chunks(streaming::sample_stream_events()).await;
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[0], streaming::expected_oai_stream_event());
+    assert_eq!(chunks[1]["choices"][0]["delta"]["content"], ", world!");
+}
+
+#[tokio::test]
+async fn test_tool_call_stream_to_oai() {
+    let chunks = collect_oai_chunks(streaming::sample_tool_call_stream_events()).await;
+    assert_eq!(chunks, vec![streaming::expected_oai_tool_call_event()]);
+}
+
+#[test]
+fn test_image_conversion() {
+    let image_url = |fixture: Value| match serde_json::from_value::<ContentBlock>(fixture) {
+        Ok(ContentBlock::ImageUrl { image_url }) => image_url,
+        other => panic!("unexpected block: {other:?}"),
+    };
+
+    let convert = |fixture: Value| oai_image_url_to_claude(&image_url(fixture), "image/png");
+    let converted = convert(images::sample_oai_data_uri_image());
+    assert_eq!(json!(converted), images::expected_claude_image());
+    // HTTP URLs are passed through unchanged
+    let converted = convert(images::sample_oai_http_image());
+    assert_eq!(json!(converted), images::sample_oai_http_image());
+
+    let document: DocumentSource =
+        serde_json::from_value(images::sample_claude_document()["source"].clone()).unwrap();
+    let source = document_to_image_source(&document).unwrap();
+    assert_eq!(source.media_type, "application/pdf");
+    assert_eq!(source.data, "JVBERi0xLjQ=");
+}
+
+#[test]
+fn test_web_search_annotations() {
+    let citations = extract_citations_from_tool_result(&web_search::sample_web_search_result());
+    assert_eq!(
+        json!(citations_to_annotations(&citations, 1000)),
+        web_search::expected_oai_annotations()
+    );
+}
+
+#[test]
+fn test_param_remapping() {
+    let cases = [
+        ("Grep", param_remapping::grep_before(), param_remapping::grep_after()),
+        ("Glob", param_remapping::glob_before(), param_remapping::glob_after()),
+        ("Read", param_remapping::read_before(), param_remapping::read_after()),
+        ("Write", param_remapping::write_before(), param_remapping::write_after()),
+    ];
+    for (tool, mut args, expected) in cases {
+        remap_function_call_args(tool, &mut args);
+        assert_eq!(args, expected, "{tool}");
+    }
+}
+
+#[test]
+fn test_schema_cleaning() {
+    // Same pipeline as the OpenAI tool conversion
+    let mut schema = schema_cleaning::schema_with_unsupported();
+    move_constraints_to_description(&mut schema);
+    clean_json_schema(&mut schema);
+    ensure_valid_schema(&mut schema);
+    assert_eq!(schema, schema_cleaning::schema_cleaned());
+}
+
 /// Test runner (manual verification)
 /// 
 /// Run this to print all test data for manual verification: